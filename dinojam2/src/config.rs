@@ -7,6 +7,16 @@ pub const TITLE: &str = "dinojam2";
 pub const RESOLUTION: f32 = 16.0 / 9.0;
 pub const WINDOW_HEIGHT: f32 = 576.0;
 
+// Network config
+pub const SERVER_ADDR: &str = "127.0.0.1:5000";
+pub const DEFAULT_PLAYER_NAME: &str = "Player";
+pub const MAX_RECONNECT_ATTEMPTS: u32 = 3;
+
+// Replay config
+// Set DINOJAM2_REPLAY to the save file that the replay viewer should open
+pub const REPLAY_PATH_ENV: &str = "DINOJAM2_REPLAY";
+pub const DEFAULT_REPLAY_PATH: &str = "saves/last_match.json";
+
 // // Monitor information
 // pub const MONITOR_HEIGHT: f32 = 1080.0;
 // pub const MONITOR_WIDTH: f32 = 1920.0;
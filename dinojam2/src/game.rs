@@ -53,12 +53,21 @@ pub fn run(app: &mut App) {
     // app.add_plugin(Material2dPlugin::<PostProcessingMaterial>::default());
     app.add_system(util::debug_current_state);
     app.add_plugin(plugins::camera::CameraPlugin);
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_plugin(plugins::network::NetworkPlugin);
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_plugin(plugins::turn_timer::TurnTimerPlugin);
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_plugin(plugins::spectate::SpectatePlugin);
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_plugin(plugins::chat::ChatPlugin);
     // app.add_plugin(plugins::input::InputHandlePlugin);
     // app.add_plugin(plugins::player::PlayerPlugin);
     // app.add_plugin(scenes::loading_scene::LoadingScenePlugin);
     app.add_plugin(scenes::main_menu_scene::MainMenuScenePlugin);
     // app.add_plugin(scenes::game_scene::GameScenePlugin);
     app.add_plugin(scenes::test_tile_scene::TestTileScenePlugin);
+    app.add_plugin(scenes::replay_scene::ReplayScenePlugin);
     // app.add_plugin(scenes::level_select_scene::LevelSelectScenePlugin);
     // app.add_plugin(scenes::playing_scene::PlayingScenePlugin);
     // app.add_plugin(scenes::victory_scene::VictoryScenePlugin);
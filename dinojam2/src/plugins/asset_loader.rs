@@ -8,7 +8,9 @@ use iyes_progress::prelude::*;
 
 use crate::asset_management::{asset_collections::*, HandleFromPath};
 use crate::states::AppState;
-use shared::asset_management::{terrain_descriptors::*, unit_descriptors::*};
+use shared::asset_management::{
+    building_descriptors::*, terrain_descriptors::*, unit_descriptors::*,
+};
 use shared::units::Units;
 
 pub struct AssetLoaderPlugin;
@@ -36,12 +38,14 @@ impl Plugin for AssetLoaderPlugin {
                 //.with_collection::<UiScenes>()
                 .with_collection::<UnitAssets>()
                 .with_collection::<TerrainAssets>()
+                .with_collection::<BuildingAssets>()
                 //.with_collection::<CutsceneAssets>()
                 .with_collection::<MapAssets>(),
             //.with_collection::<AudioAssets>(),
         );
         app.add_plugin(TomlAssetPlugin::<UnitAsset>::new(&["units.toml"]));
         app.add_plugin(TomlAssetPlugin::<TerrainAsset>::new(&["terrain.toml"]));
+        app.add_plugin(TomlAssetPlugin::<BuildingAsset>::new(&["buildings.toml"]));
         // app.add_plugin(TomlAssetPlugin::<CutsceneMetaAsset>::new(&[
         //     "cutscene.toml",
         // ]));
@@ -0,0 +1,194 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use iyes_loopless::prelude::*;
+use shared::{
+    chat::{ChatScope, Emote, MAX_CHAT_LENGTH},
+    messages::{self, ClientMessage},
+    GameState, PlayerId,
+};
+
+use crate::asset_management::asset_collections::UiAssets;
+use crate::plugins::network::{client_connected, ChatLine, ChatLog};
+use crate::states::AppState;
+use crate::util;
+
+// How many of the latest chat lines are shown
+const SHOWN_LINES: usize = 8;
+
+// Typing this in front of a message only sends it to our team
+const TEAM_PREFIX: &str = "/t ";
+
+// Keys that send the emotes, in the order of Emote::ALL
+const EMOTE_KEYS: [KeyCode; 6] = [
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+];
+
+/// Shows the chat in the corner of the screen, Enter to type a message and F1 to F6 for emotes
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatInput>();
+        app.add_enter_system(AppState::InGame, spawn_chat_overlay);
+        app.add_exit_system(AppState::InGame, util::despawn_with::<ChatOverlay>);
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(AppState::InGame)
+                .run_if(client_connected)
+                .with_system(type_chat)
+                .with_system(send_emotes)
+                .into(),
+        );
+        app.add_system(update_chat_overlay.run_in_state(AppState::InGame));
+    }
+}
+
+/// What we are typing, None while we arent
+#[derive(Default)]
+pub struct ChatInput(pub Option<String>);
+
+/// Marker for the chat text
+#[derive(Component)]
+struct ChatOverlay;
+
+fn spawn_chat_overlay(mut commands: Commands, ui_assets: Res<UiAssets>) {
+    commands.insert_resource(ChatInput::default());
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: ui_assets.font_regular.clone(),
+                    font_size: 18.0,
+                    color: Color::WHITE,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(8.0),
+                    right: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(ChatOverlay);
+}
+
+/// Enter starts a message and sends it, Escape throws it away
+fn type_chat(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut input: ResMut<ChatInput>,
+    mut client: ResMut<RenetClient>,
+) {
+    let typing = match input.0.as_mut() {
+        Some(typing) => typing,
+        None => {
+            characters.clear();
+            if keyboard_input.just_pressed(KeyCode::Return) {
+                input.0 = Some(String::new());
+            }
+            return;
+        }
+    };
+
+    for character in characters.iter() {
+        if !character.char.is_control() && typing.chars().count() < MAX_CHAT_LENGTH {
+            typing.push(character.char);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        typing.pop();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        input.0 = None;
+    } else if keyboard_input.just_pressed(KeyCode::Return) {
+        let message = match typing.strip_prefix(TEAM_PREFIX) {
+            Some(text) => ClientMessage::Chat {
+                scope: ChatScope::Team,
+                text: text.to_string(),
+            },
+            None => ClientMessage::Chat {
+                scope: ChatScope::Everyone,
+                text: typing.clone(),
+            },
+        };
+        if !typing.trim().is_empty() {
+            client.send_message(message.channel(), messages::encode(&message));
+        }
+        input.0 = None;
+    }
+}
+
+fn send_emotes(
+    keyboard_input: Res<Input<KeyCode>>,
+    input: Res<ChatInput>,
+    mut client: ResMut<RenetClient>,
+) {
+    if input.0.is_some() {
+        return;
+    }
+    for (key, emote) in EMOTE_KEYS.into_iter().zip(Emote::ALL) {
+        if keyboard_input.just_pressed(key) {
+            let message = ClientMessage::Emote(emote);
+            client.send_message(message.channel(), messages::encode(&message));
+        }
+    }
+}
+
+fn update_chat_overlay(
+    chat_log: Res<ChatLog>,
+    input: Res<ChatInput>,
+    game_state: Res<GameState>,
+    mut overlay_q: Query<&mut Text, With<ChatOverlay>>,
+) {
+    let name_of = |player_id: PlayerId| {
+        game_state
+            .players
+            .get(&player_id)
+            .map(|player| player.name.clone())
+            .unwrap_or_else(|| player_id.to_string())
+    };
+
+    let mut lines: Vec<String> = chat_log
+        .0
+        .iter()
+        .rev()
+        .take(SHOWN_LINES)
+        .rev()
+        .map(|line| match line {
+            ChatLine::Chat {
+                player_id,
+                scope: ChatScope::Everyone,
+                text,
+            } => format!("{}: {}", name_of(*player_id), text),
+            ChatLine::Chat {
+                player_id,
+                scope: ChatScope::Team,
+                text,
+            } => format!("{} (team): {}", name_of(*player_id), text),
+            ChatLine::Emote { player_id, emote } => format!("{} {}", name_of(*player_id), emote),
+            ChatLine::Rejected(reason) => format!("Not sent: {:?}", reason),
+        })
+        .collect();
+    lines.push(match &input.0 {
+        Some(typing) => format!("> {}_", typing),
+        None => format!("Enter: chat, {}team  F1-F6: emotes", TEAM_PREFIX),
+    });
+    let value = lines.join("\n");
+
+    for mut text in overlay_q.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
@@ -1,2 +1,10 @@
 pub mod asset_loader;
 pub mod camera;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod chat;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod network;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod spectate;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod turn_timer;
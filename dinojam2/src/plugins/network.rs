@@ -0,0 +1,506 @@
+use std::{io, net::UdpSocket, time::SystemTime};
+
+use bevy::{prelude::*, utils::Duration};
+use bevy_renet::{
+    renet::{ClientAuthentication, RenetClient, RenetError},
+    RenetClientPlugin,
+};
+use iyes_loopless::prelude::*;
+use shared::{
+    auth,
+    buildings::Buildings,
+    channels::{client_connection_config, ServerChannel},
+    chat::{ChatRejectReason, ChatScope, Emote},
+    connection::{ConnectInfo, Session},
+    hash,
+    messages::{
+        self, ClientMessage, DecodeError, DesyncReport, Handshake, LobbyPlayer, ServerMessage,
+    },
+    terrain::Terrain,
+    units::Units,
+    GameEvent, GameState, PlayerId,
+};
+
+use crate::config::{DEFAULT_PLAYER_NAME, MAX_RECONNECT_ATTEMPTS, SERVER_ADDR};
+use crate::plugins::spectate::Spectating;
+use crate::states::AppState;
+
+pub struct NetworkPlugin;
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(RenetClientPlugin);
+        app.insert_resource(GameState::default());
+        app.init_resource::<ServerSync>();
+        app.init_resource::<Lobby>();
+        app.init_resource::<Spectators>();
+        app.init_resource::<ChatLog>();
+        app.add_event::<GameEvent>();
+
+        app.add_enter_system(AppState::InGame, connect_to_server);
+        app.add_exit_system(AppState::InGame, disconnect_from_server);
+
+        app.add_system(
+            send_handshake
+                .run_in_state(AppState::InGame)
+                .run_if(client_connected)
+                .run_if(handshake_not_sent),
+        );
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            receive_messages_from_server
+                .run_in_state(AppState::InGame)
+                .run_if(client_connected),
+        );
+        app.add_system(
+            send_undo
+                .run_in_state(AppState::InGame)
+                .run_if(client_connected),
+        );
+        app.add_system(handle_renet_error.run_in_state(AppState::InGame));
+    }
+}
+
+/// Keeps track of whether the server has sent us its StateSnapshot yet.
+///
+/// Until it has there is no board to apply incremental events to. The snapshot comes over its own
+/// channel, so events can overtake it and are held on to until it shows up.
+#[derive(Default)]
+pub struct ServerSync {
+    pub synced: bool,
+    /// Updates received before the snapshot
+    pub pending_updates: Vec<ServerUpdate>,
+    /// Whether we have told the server which version of the game we are running yet
+    pub handshake_sent: bool,
+}
+
+/// A change the server made to its GameState, along with the position in the history it was
+/// made at and the hash the server's GameState had afterwards
+pub enum ServerUpdate {
+    Event {
+        index: usize,
+        event: GameEvent,
+        state_hash: u64,
+    },
+    Undo {
+        index: usize,
+        state_hash: u64,
+    },
+}
+
+/// When the turn of the player who is currently playing runs out, as last told by the server
+pub struct TurnCountdown {
+    pub player_id: PlayerId,
+    /// Compared against Time::time_since_startup
+    pub ends_at: Duration,
+}
+
+/// Everyone with a seat in the game, as last told by the server
+#[derive(Default)]
+pub struct Lobby(pub Vec<LobbyPlayer>);
+
+/// The names of everyone watching the game, as last told by the server
+#[derive(Default)]
+pub struct Spectators(pub Vec<String>);
+
+/// Everything said in chat since we joined, oldest first
+#[derive(Default)]
+pub struct ChatLog(pub Vec<ChatLine>);
+
+/// One line of the chat
+pub enum ChatLine {
+    Chat {
+        player_id: PlayerId,
+        scope: ChatScope,
+        text: String,
+    },
+    Emote {
+        player_id: PlayerId,
+        emote: Emote,
+    },
+    /// Something we tried to say that the server did not pass on
+    Rejected(ChatRejectReason),
+}
+
+/// The name we play under, and how many times in a row we have tried to get back into the game
+struct ConnectionAttempts {
+    username: String,
+    reconnects: u32,
+}
+
+/// Condition that is true while the client has an open connection to the server
+pub fn client_connected(client: Option<Res<RenetClient>>) -> bool {
+    client.map(|client| client.is_connected()).unwrap_or(false)
+}
+
+fn handshake_not_sent(sync: Res<ServerSync>) -> bool {
+    !sync.handshake_sent
+}
+
+/// Creates a RenetClient that starts connecting to the server straight away.
+///
+/// In secure mode this first has to fetch a connect token, which can fail.
+fn new_renet_client(connect_info: &ConnectInfo) -> io::Result<RenetClient> {
+    let server_addr = SERVER_ADDR.parse().unwrap();
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+
+    let (client_id, authentication) = if auth::secure_mode() {
+        let issued = auth::request_connect_token(auth::token_service_addr(), connect_info)?;
+        (
+            issued.client_id,
+            ClientAuthentication::Secure {
+                connect_token: issued.connect_token,
+            },
+        )
+    } else {
+        let client_id = current_time.as_millis() as u64;
+        (
+            client_id,
+            ClientAuthentication::Unsecure {
+                client_id,
+                protocol_id: shared::PROTOCOL_ID,
+                server_addr,
+                user_data: Some(connect_info.to_user_data()),
+            },
+        )
+    };
+
+    RenetClient::new(
+        current_time,
+        socket,
+        client_id,
+        client_connection_config(),
+        authentication,
+    )
+    .map_err(|err| io::Error::other(err.to_string()))
+}
+
+fn connect_to_server(mut commands: Commands, spectating: Option<Res<Spectating>>) {
+    let username = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_PLAYER_NAME.to_string());
+
+    let client = new_renet_client(&ConnectInfo {
+        name: username.clone(),
+        session_token: None,
+        spectator: spectating.is_some(),
+    });
+    match client {
+        Ok(client) => commands.insert_resource(client),
+        Err(err) => {
+            error!("Could not connect to the server: {}", err);
+            commands.insert_resource(NextState(AppState::MainMenu));
+            return;
+        }
+    }
+
+    commands.insert_resource(GameState::default());
+    commands.insert_resource(ServerSync::default());
+    commands.insert_resource(Lobby::default());
+    commands.insert_resource(Spectators::default());
+    commands.insert_resource(ChatLog::default());
+    commands.insert_resource(ConnectionAttempts {
+        username,
+        reconnects: 0,
+    });
+    commands.remove_resource::<Session>();
+}
+
+fn disconnect_from_server(mut commands: Commands, client: Option<ResMut<RenetClient>>) {
+    if let Some(mut client) = client {
+        client.disconnect();
+    }
+    commands.remove_resource::<RenetClient>();
+    commands.remove_resource::<Session>();
+    commands.remove_resource::<TurnCountdown>();
+}
+
+/// Try to get back into our game if we lose connection to the server, and drop back to the main
+/// menu if that doesnt work out.
+fn handle_renet_error(
+    mut commands: Commands,
+    mut renet_error: EventReader<RenetError>,
+    session: Option<Res<Session>>,
+    attempts: Option<ResMut<ConnectionAttempts>>,
+) {
+    let err = match renet_error.iter().last() {
+        Some(err) => err,
+        None => return,
+    };
+    error!("Lost connection to the server: {}", err);
+
+    match (session, attempts) {
+        (Some(session), Some(mut attempts)) if attempts.reconnects < MAX_RECONNECT_ATTEMPTS => {
+            attempts.reconnects += 1;
+            info!(
+                "Reconnecting as player {} (attempt {}/{})",
+                session.player_id, attempts.reconnects, MAX_RECONNECT_ATTEMPTS
+            );
+
+            let client = new_renet_client(&ConnectInfo {
+                name: attempts.username.clone(),
+                session_token: Some(session.token),
+                spectator: false,
+            });
+            match client {
+                Ok(client) => {
+                    commands.insert_resource(client);
+                    // The server will send a fresh snapshot once we are back in
+                    commands.insert_resource(ServerSync::default());
+                }
+                Err(err) => {
+                    error!("Could not reconnect to the server: {}", err);
+                    commands.insert_resource(NextState(AppState::MainMenu));
+                }
+            }
+        }
+        _ => commands.insert_resource(NextState(AppState::MainMenu)),
+    }
+}
+
+/// The server wont give us a seat until it knows we are compatible with it
+fn send_handshake(
+    mut client: ResMut<RenetClient>,
+    mut sync: ResMut<ServerSync>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    let message = ClientMessage::Hello(Handshake::new(&buildings, &units, &terrain));
+    client.send_message(message.channel(), messages::encode(&message));
+    sync.handshake_sent = true;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn receive_messages_from_server(
+    mut commands: Commands,
+    mut client: ResMut<RenetClient>,
+    mut game_state: ResMut<GameState>,
+    mut sync: ResMut<ServerSync>,
+    mut lobby: ResMut<Lobby>,
+    mut spectators: ResMut<Spectators>,
+    mut chat_log: ResMut<ChatLog>,
+    mut game_events: EventWriter<GameEvent>,
+    mut attempts: ResMut<ConnectionAttempts>,
+    time: Res<Time>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    for channel in ServerChannel::ALL {
+        while let Some(message) = client.receive_message(channel) {
+            let message = match messages::decode::<ServerMessage>(&message) {
+                Ok(message) => message,
+                Err(DecodeError::WrongVersion(version)) => {
+                    error!(
+                        "The server speaks protocol version {}, we speak {}",
+                        version,
+                        messages::PROTOCOL_VERSION
+                    );
+                    commands.insert_resource(NextState(AppState::MainMenu));
+                    return;
+                }
+                Err(err) => {
+                    warn!("Failed to read message from server: {}", err);
+                    continue;
+                }
+            };
+
+            match message {
+                ServerMessage::StateSnapshot(snapshot) => {
+                    trace!("Received snapshot with {} events", snapshot.histroy.len());
+                    *game_state = *snapshot;
+                    sync.synced = true;
+                }
+                ServerMessage::Session(session) => {
+                    info!("Playing as player {}", session.player_id);
+                    attempts.reconnects = 0;
+                    commands.insert_resource(session);
+                }
+                ServerMessage::Disconnect(reason) => {
+                    error!("Disconnected by the server: {:?}", reason);
+                    // There is no point trying to get back in
+                    commands.remove_resource::<Session>();
+                    commands.insert_resource(NextState(AppState::MainMenu));
+                    return;
+                }
+                ServerMessage::GameEvent {
+                    index,
+                    event,
+                    state_hash,
+                } => {
+                    if !sync.synced {
+                        trace!("Holding on to event received before snapshot: {:?}", event);
+                    }
+                    sync.pending_updates.push(ServerUpdate::Event {
+                        index,
+                        event,
+                        state_hash,
+                    });
+                }
+                ServerMessage::Undone { index, state_hash } => {
+                    sync.pending_updates
+                        .push(ServerUpdate::Undo { index, state_hash });
+                }
+                ServerMessage::UndoRejected => {
+                    warn!("Server did not let us undo our last action");
+                }
+                ServerMessage::Rejected { event, reason } => {
+                    warn!("Server rejected {:?}: {:?}", event, reason);
+                }
+                ServerMessage::Lobby(players) => {
+                    trace!("Lobby: {:?}", players);
+                    lobby.0 = players;
+                }
+                ServerMessage::Spectators(names) => {
+                    trace!("Spectators: {:?}", names);
+                    spectators.0 = names;
+                }
+                ServerMessage::Chat {
+                    player_id,
+                    scope,
+                    text,
+                } => {
+                    info!("[{}] {}", player_id, text);
+                    chat_log.0.push(ChatLine::Chat {
+                        player_id,
+                        scope,
+                        text,
+                    });
+                }
+                ServerMessage::Emote { player_id, emote } => {
+                    chat_log.0.push(ChatLine::Emote { player_id, emote });
+                }
+                ServerMessage::ChatRejected(reason) => {
+                    warn!("Server did not pass on our chat message: {:?}", reason);
+                    chat_log.0.push(ChatLine::Rejected(reason));
+                }
+                ServerMessage::Cursor { player_id, tile } => {
+                    trace!("Player {} is hovering over {:?}", player_id, tile);
+                }
+                ServerMessage::TurnTimer {
+                    player_id,
+                    remaining_ms,
+                } => {
+                    commands.insert_resource(TurnCountdown {
+                        player_id,
+                        ends_at: time.time_since_startup() + Duration::from_millis(remaining_ms),
+                    });
+                }
+                ServerMessage::Pong(value) => {
+                    trace!("Pong {}", value);
+                }
+            }
+
+            // Once there is a board, catch up on any events it doesnt include yet
+            if sync.synced {
+                for update in std::mem::take(&mut sync.pending_updates) {
+                    let desync = apply_update(
+                        update,
+                        &mut game_state,
+                        &mut game_events,
+                        &buildings,
+                        &units,
+                        &terrain,
+                    );
+                    if let Some(report) = desync {
+                        // Anything after this builds on a broken state, wait for the server to
+                        // send a fresh snapshot instead
+                        sync.synced = false;
+                        let message = ClientMessage::Desync(report);
+                        client.send_message(message.channel(), messages::encode(&message));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Applies a change from the server, unless the snapshot we got already included it.
+///
+/// Returns a report for the server if we dont end up in the same state it did.
+fn apply_update(
+    update: ServerUpdate,
+    game_state: &mut GameState,
+    game_events: &mut EventWriter<GameEvent>,
+    buildings: &Buildings,
+    units: &Units,
+    terrain: &Terrain,
+) -> Option<DesyncReport> {
+    match update {
+        ServerUpdate::Event {
+            index,
+            event,
+            state_hash,
+        } => {
+            if index < game_state.histroy.len() {
+                trace!("Skipping event already in snapshot: {:?}", event);
+                return None;
+            }
+
+            trace!("{:#?}", event);
+
+            // We trust the server, no need to validate
+            game_state.consume(&event, buildings, units, terrain);
+
+            // Send the events into the bevy event system so systems can react to it
+            game_events.send(event);
+            check_sync(index + 1, state_hash, game_state)
+        }
+        ServerUpdate::Undo { index, state_hash } => {
+            if index >= game_state.histroy.len() {
+                trace!(
+                    "Skipping undo of event {} that the snapshot doesnt have",
+                    index
+                );
+                return None;
+            }
+
+            trace!("Undoing {:?}", game_state.histroy[index]);
+            *game_state = GameState::from_events(
+                &game_state.initial_board,
+                &game_state.histroy[..index],
+                buildings,
+                units,
+                terrain,
+            );
+            check_sync(index, state_hash, game_state)
+        }
+    }
+}
+
+/// Compares our GameState against the server's, once it has `history_len` events
+fn check_sync(history_len: usize, state_hash: u64, game_state: &GameState) -> Option<DesyncReport> {
+    let actual_hash = hash::state_hash(game_state);
+    if actual_hash == state_hash && game_state.histroy.len() == history_len {
+        return None;
+    }
+
+    let index = history_len.saturating_sub(1);
+    error!(
+        "Out of sync with the server after event {} {:?}: expected hash {:#x}, got {:#x}\n{:#?}",
+        index,
+        game_state.histroy.get(index),
+        state_hash,
+        actual_hash,
+        game_state
+    );
+    Some(DesyncReport {
+        index,
+        expected_hash: state_hash,
+        actual_hash,
+    })
+}
+
+/// Ctrl+Z takes back the last action we did this turn
+fn send_undo(keyboard_input: Res<Input<KeyCode>>, mut client: ResMut<RenetClient>) {
+    let ctrl = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if ctrl && keyboard_input.just_pressed(KeyCode::Z) {
+        let message = ClientMessage::UndoAction;
+        client.send_message(message.channel(), messages::encode(&message));
+    }
+}
@@ -0,0 +1,135 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use shared::{GameState, PlayerId};
+
+use crate::asset_management::asset_collections::UiAssets;
+use crate::plugins::network::{Lobby, Spectators};
+use crate::states::AppState;
+use crate::util;
+
+/// Lets spectators see who else is watching, and switch between the sides of the players
+pub struct SpectatePlugin;
+
+impl Plugin for SpectatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Perspective>();
+        app.add_enter_system(AppState::InGame, spawn_spectator_hud);
+        app.add_exit_system(AppState::InGame, util::despawn_with::<SpectatorHud>);
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(AppState::InGame)
+                .run_if_resource_exists::<Spectating>()
+                .with_system(switch_perspective)
+                .with_system(update_spectator_hud)
+                .into(),
+        );
+    }
+}
+
+/// Present while we are only watching the match instead of playing in it
+pub struct Spectating;
+
+/// The player whose side of the board a spectator is watching from, None to watch everyone
+#[derive(Default)]
+pub struct Perspective(pub Option<PlayerId>);
+
+/// Marker for the spectator info text
+#[derive(Component)]
+struct SpectatorHud;
+
+fn spawn_spectator_hud(
+    mut commands: Commands,
+    ui_assets: Res<UiAssets>,
+    spectating: Option<Res<Spectating>>,
+) {
+    commands.insert_resource(Perspective::default());
+    if spectating.is_none() {
+        return;
+    }
+
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: ui_assets.font_regular.clone(),
+                    font_size: 18.0,
+                    color: Color::WHITE,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(8.0),
+                    left: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(SpectatorHud);
+}
+
+/// Tab moves on to the next player, and after the last one back to watching everyone
+fn switch_perspective(
+    keyboard_input: Res<Input<KeyCode>>,
+    lobby: Res<Lobby>,
+    mut perspective: ResMut<Perspective>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Tab) {
+        return;
+    }
+
+    let players: Vec<PlayerId> = lobby.0.iter().map(|player| player.player_id).collect();
+    perspective.0 = match perspective.0 {
+        None => players.first().copied(),
+        Some(current) => players
+            .iter()
+            .position(|player_id| *player_id == current)
+            .and_then(|index| players.get(index + 1))
+            .copied(),
+    };
+}
+
+fn update_spectator_hud(
+    perspective: Res<Perspective>,
+    spectators: Res<Spectators>,
+    game_state: Res<GameState>,
+    mut hud_q: Query<&mut Text, With<SpectatorHud>>,
+) {
+    let watching = match perspective.0.and_then(|player_id| {
+        game_state
+            .players
+            .get(&player_id)
+            .map(|player| (player_id, player))
+    }) {
+        Some((player_id, player)) => {
+            let units = game_state
+                .board
+                .iter()
+                .filter(|tile| tile.unit.map(|unit| unit.owner) == Some(player_id))
+                .count();
+            format!(
+                "Watching {} ({}, team {})  {} gold  {} units",
+                player.name,
+                player.faction,
+                player.team,
+                game_state.available_gold(player_id),
+                units
+            )
+        }
+        None => "Watching everyone".to_string(),
+    };
+    let value = format!(
+        "{}\nSpectators: {}\nTab: switch player",
+        watching,
+        spectators.0.join(", ")
+    );
+
+    for mut text in hud_q.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use shared::{connection::Session, GameState};
+
+use crate::asset_management::asset_collections::UiAssets;
+use crate::plugins::network::TurnCountdown;
+use crate::states::AppState;
+use crate::util;
+
+/// Shows how much time is left in the current turn
+pub struct TurnTimerPlugin;
+
+impl Plugin for TurnTimerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(AppState::InGame, spawn_countdown);
+        app.add_exit_system(AppState::InGame, util::despawn_with::<CountdownText>);
+        app.add_system(update_countdown.run_in_state(AppState::InGame));
+    }
+}
+
+/// Marker for the countdown text
+#[derive(Component)]
+struct CountdownText;
+
+fn spawn_countdown(mut commands: Commands, ui_assets: Res<UiAssets>) {
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: ui_assets.font_bold.clone(),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(8.0),
+                    right: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(CountdownText);
+}
+
+fn update_countdown(
+    time: Res<Time>,
+    countdown: Option<Res<TurnCountdown>>,
+    session: Option<Res<Session>>,
+    game_state: Res<GameState>,
+    mut text_q: Query<&mut Text, With<CountdownText>>,
+) {
+    let value = match countdown {
+        Some(countdown) if game_state.active_player_id == countdown.player_id => {
+            let remaining = countdown
+                .ends_at
+                .saturating_sub(time.time_since_startup())
+                .as_secs();
+            let whose = match &session {
+                Some(session) if session.player_id == countdown.player_id => {
+                    "Your turn".to_string()
+                }
+                _ => game_state
+                    .players
+                    .get(&countdown.player_id)
+                    .map(|player| format!("{}'s turn", player.name))
+                    .unwrap_or_default(),
+            };
+            format!("{} {}:{:02}", whose, remaining / 60, remaining % 60)
+        }
+        // No time limit, or the turn has already moved on
+        _ => String::new(),
+    };
+
+    for mut text in text_q.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::plugins::spectate::Spectating;
 use crate::states::AppState;
 use crate::util;
 use bevy::app::AppExit;
@@ -23,6 +25,8 @@ impl Plugin for MainMenuScenePlugin {
                 // our menu button handlers
                 .with_system(butt_exit.run_if(on_butt_interact::<ExitButt>))
                 .with_system(butt_game.run_if(on_butt_interact::<EnterButt>))
+                .with_system(butt_spectate.run_if(on_butt_interact::<SpectateButt>))
+                .with_system(butt_replay.run_if(on_butt_interact::<ReplayButt>))
                 .into(),
         );
     }
@@ -40,6 +44,14 @@ struct ExitButt;
 #[derive(Component)]
 struct EnterButt;
 
+/// Marker for the "Spectate" button
+#[derive(Component)]
+struct SpectateButt;
+
+/// Marker for the "Watch Replay" button
+#[derive(Component)]
+struct ReplayButt;
+
 /// Change button color on interaction
 fn butt_interact_visual(
     mut query: Query<(&Interaction, &mut UiColor), (Changed<Interaction>, With<Button>)>,
@@ -81,10 +93,24 @@ fn butt_exit(mut ev: EventWriter<AppExit>) {
 
 /// Handler for the Enter Game button
 fn butt_game(mut commands: Commands) {
+    #[cfg(not(target_arch = "wasm32"))]
+    commands.remove_resource::<Spectating>();
     // queue state transition
     commands.insert_resource(NextState(AppState::InGame));
 }
 
+/// Handler for the Spectate button
+fn butt_spectate(mut commands: Commands) {
+    #[cfg(not(target_arch = "wasm32"))]
+    commands.insert_resource(Spectating);
+    commands.insert_resource(NextState(AppState::InGame));
+}
+
+/// Handler for the Watch Replay button
+fn butt_replay(mut commands: Commands) {
+    commands.insert_resource(NextState(AppState::Replay));
+}
+
 /// Construct the main menu UI
 fn setup_menu(mut commands: Commands, ass: Res<AssetServer>) {
     let butt_style = Style {
@@ -132,6 +158,34 @@ fn setup_menu(mut commands: Commands, ass: Res<AssetServer>) {
         .insert(EnterButt)
         .id();
 
+    let butt_spectate = commands
+        .spawn_bundle(ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        })
+        .with_children(|btn| {
+            btn.spawn_bundle(TextBundle {
+                text: Text::from_section("Spectate", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .insert(SpectateButt)
+        .id();
+
+    let butt_replay = commands
+        .spawn_bundle(ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        })
+        .with_children(|btn| {
+            btn.spawn_bundle(TextBundle {
+                text: Text::from_section("Watch Replay", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .insert(ReplayButt)
+        .id();
+
     let butt_exit = commands
         .spawn_bundle(ButtonBundle {
             style: butt_style.clone(),
@@ -148,5 +202,5 @@ fn setup_menu(mut commands: Commands, ass: Res<AssetServer>) {
 
     commands
         .entity(menu)
-        .push_children(&[butt_enter, butt_exit]);
+        .push_children(&[butt_enter, butt_spectate, butt_replay, butt_exit]);
 }
@@ -1,5 +1,6 @@
 pub mod game_scene;
 pub mod main_menu_scene;
+pub mod replay_scene;
 pub mod test_scene;
 pub mod test_tile_scene;
 
@@ -0,0 +1,436 @@
+use crate::asset_management::asset_collections::{MapAssets, UiAssets};
+use crate::config::{DEFAULT_REPLAY_PATH, REPLAY_PATH_ENV};
+use crate::scenes::test_tile_scene::{TILE_SIZE_X, TILE_SIZE_Y};
+use crate::states::AppState;
+use crate::util;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use iyes_loopless::prelude::*;
+use shared::{
+    buildings::Buildings, save::SaveFile, terrain::Terrain, units::Units, Faction, GameEvent,
+    GameState, MAP_HEIGHT, MAP_WIDTH,
+};
+
+/// Steps through a saved match without needing a server
+pub struct ReplayScenePlugin;
+
+impl Plugin for ReplayScenePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(AppState::Replay, load_replay);
+        app.add_exit_system(AppState::Replay, util::despawn_with::<ReplayEntity>);
+        app.add_exit_system(AppState::Replay, remove_replay);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(AppState::Replay)
+                .run_if_resource_exists::<Replay>()
+                .label("replay_controls")
+                .with_system(replay_controls)
+                .with_system(advance_playback)
+                .into(),
+        );
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(AppState::Replay)
+                .run_if_resource_exists::<Replay>()
+                .after("replay_controls")
+                .with_system(render_board)
+                .with_system(update_hud)
+                .into(),
+        );
+    }
+}
+
+// Playback speed in events per second
+const DEFAULT_SPEED: f32 = 2.0;
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 16.0;
+const REPLAY_MAP_Z: f32 = 0.;
+const REPLAY_LABEL_Z: f32 = 1.;
+
+/// Marker for everything spawned by the replay scene
+#[derive(Component)]
+struct ReplayEntity;
+
+/// Label showing what stands on a board tile
+#[derive(Component)]
+struct ReplayTileLabel(usize);
+
+/// Marker for the playback info text
+#[derive(Component)]
+struct ReplayHud;
+
+/// A saved match and how far into it we are
+pub struct Replay {
+    save: SaveFile,
+    /// The match as it was after the first `position()` events
+    game_state: GameState,
+    playing: bool,
+    speed: f32,
+    /// How far we are towards the next event while playing
+    progress: f32,
+    /// Digits typed in for a turn to jump to
+    turn_input: String,
+}
+
+impl Replay {
+    fn new(save: SaveFile) -> Self {
+        Self {
+            game_state: save.initial_state(),
+            save,
+            playing: false,
+            speed: DEFAULT_SPEED,
+            progress: 0.0,
+            turn_input: String::new(),
+        }
+    }
+
+    /// How many events have been played so far
+    fn position(&self) -> usize {
+        self.game_state.histroy.len()
+    }
+
+    fn len(&self) -> usize {
+        self.save.events.len()
+    }
+
+    /// Plays the next event, returning false at the end of the match
+    fn step_forward(&mut self, buildings: &Buildings, units: &Units, terrain: &Terrain) -> bool {
+        match self.save.events.get(self.position()) {
+            Some(event) => {
+                // The whole replay was validated when it was loaded
+                self.game_state.consume(event, buildings, units, terrain);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves to the point where `position` events have been played.
+    ///
+    /// Events can't be taken back, so going backwards replays the match from the start.
+    fn seek(&mut self, position: usize, buildings: &Buildings, units: &Units, terrain: &Terrain) {
+        let position = position.min(self.len());
+        if position < self.position() {
+            self.game_state = self.save.initial_state();
+        }
+        while self.position() < position {
+            self.step_forward(buildings, units, terrain);
+        }
+    }
+
+    /// The positions at which each turn begins. Turn 1 begins once the game has started and
+    /// every EndTurn begins the next one.
+    fn turn_starts(&self) -> Vec<usize> {
+        self.save
+            .events
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| {
+                matches!(
+                    event,
+                    GameEvent::BeginGame { .. } | GameEvent::EndTurn { .. }
+                )
+            })
+            .map(|(index, _)| index + 1)
+            .collect()
+    }
+
+    /// The turn being played at the current position, 0 before the game has started
+    fn current_turn(&self) -> usize {
+        self.turn_starts()
+            .iter()
+            .take_while(|start| **start <= self.position())
+            .count()
+    }
+}
+
+fn load_replay(
+    mut commands: Commands,
+    assets: Res<MapAssets>,
+    ui_assets: Res<UiAssets>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    let path = std::env::var(REPLAY_PATH_ENV).unwrap_or_else(|_| DEFAULT_REPLAY_PATH.to_string());
+
+    // Replaying the whole match up front makes sure every event in it can be played back
+    let save = match SaveFile::read(&path).and_then(|save| {
+        save.restore(&buildings, &units, &terrain)?;
+        Ok(save)
+    }) {
+        Ok(save) => save,
+        Err(err) => {
+            error!("Could not load replay {}: {}", path, err);
+            commands.insert_resource(NextState(AppState::MainMenu));
+            return;
+        }
+    };
+    info!("Loaded replay {} with {} events", path, save.events.len());
+
+    let replay = Replay::new(save);
+    spawn_board(
+        &mut commands,
+        &replay.game_state,
+        &assets,
+        &ui_assets,
+        &terrain,
+    );
+    spawn_hud(&mut commands, &ui_assets);
+    commands.insert_resource(replay);
+}
+
+fn remove_replay(mut commands: Commands) {
+    commands.remove_resource::<Replay>();
+}
+
+fn spawn_board(
+    commands: &mut Commands,
+    game_state: &GameState,
+    assets: &MapAssets,
+    ui_assets: &UiAssets,
+    terrain: &Terrain,
+) {
+    let map_size: TilemapSize = UVec2::new(MAP_WIDTH as u32, MAP_HEIGHT as u32).into();
+    let grid_size = TilemapGridSize {
+        x: TILE_SIZE_X as f32,
+        y: TILE_SIZE_Y as f32,
+    };
+    let tile_size = TilemapTileSize {
+        x: TILE_SIZE_X as f32,
+        y: TILE_SIZE_Y as f32,
+    };
+    let map_type = TilemapType::Hexagon(HexCoordSystem::RowOdd);
+    let map_transform = Transform::from_translation(Vec2::ZERO.extend(REPLAY_MAP_Z));
+
+    let label_style = TextStyle {
+        font: ui_assets.font_bold.clone(),
+        font_size: 14.0,
+        color: Color::BLACK,
+    };
+
+    let map = commands.spawn().insert(ReplayEntity).id();
+    let mut tile_storage = TileStorage::empty(map_size);
+    for (index, board_tile) in game_state.board.iter().enumerate() {
+        let tile_pos: TilePos =
+            UVec2::new((index % MAP_WIDTH) as u32, (index / MAP_WIDTH) as u32).into();
+        let tile = commands
+            .spawn_bundle(TileBundle {
+                position: tile_pos,
+                tilemap_id: TilemapId(map),
+                texture: TileTexture(terrain[board_tile.terrain].sprite_idx as u32),
+                ..default()
+            })
+            .insert(board_tile.terrain)
+            .insert(ReplayEntity)
+            .id();
+        tile_storage.set(&tile_pos, tile);
+
+        let tile_center = tile_pos
+            .center_in_world(&grid_size, &map_type)
+            .extend(REPLAY_LABEL_Z);
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::from_section("", label_style.clone())
+                    .with_alignment(TextAlignment::CENTER),
+                transform: map_transform * Transform::from_translation(tile_center),
+                ..default()
+            })
+            .insert(ReplayTileLabel(index))
+            .insert(ReplayEntity);
+    }
+
+    commands.entity(map).insert_bundle(TilemapBundle {
+        grid_size,
+        size: map_size,
+        storage: tile_storage,
+        texture: TilemapTexture::Single(assets.terrain.clone()),
+        tile_size,
+        transform: map_transform,
+        map_type,
+        ..default()
+    });
+}
+
+fn spawn_hud(commands: &mut Commands, ui_assets: &UiAssets) {
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: ui_assets.font_regular.clone(),
+                    font_size: 18.0,
+                    color: Color::WHITE,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(8.0),
+                    left: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(ReplayHud)
+        .insert(ReplayEntity);
+}
+
+/// Space plays and pauses, left and right step through events, up and down change the speed.
+/// Typing a number and pressing enter jumps to that turn, escape goes back to the menu.
+fn replay_controls(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut typed: EventReader<ReceivedCharacter>,
+    mut replay: ResMut<Replay>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        commands.insert_resource(NextState(AppState::MainMenu));
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        replay.playing = !replay.playing;
+        replay.progress = 0.0;
+        // Play from the start again once the end has been reached
+        if replay.playing && replay.position() == replay.len() {
+            replay.seek(0, &buildings, &units, &terrain);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        replay.playing = false;
+        replay.step_forward(&buildings, &units, &terrain);
+    }
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        replay.playing = false;
+        let position = replay.position().saturating_sub(1);
+        replay.seek(position, &buildings, &units, &terrain);
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        replay.speed = (replay.speed * 2.0).min(MAX_SPEED);
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        replay.speed = (replay.speed / 2.0).max(MIN_SPEED);
+    }
+
+    for typed in typed.iter() {
+        if typed.char.is_ascii_digit() {
+            replay.turn_input.push(typed.char);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        replay.turn_input.pop();
+    }
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        let turn_input = std::mem::take(&mut replay.turn_input);
+        if let Ok(turn) = turn_input.parse::<usize>() {
+            // Turn 0 is everything before the game started
+            let position = match turn.checked_sub(1) {
+                Some(turn_index) => replay.turn_starts().get(turn_index).copied(),
+                None => Some(0),
+            };
+            match position {
+                Some(position) => {
+                    replay.playing = false;
+                    replay.seek(position, &buildings, &units, &terrain);
+                }
+                None => warn!("Replay has no turn {}", turn),
+            }
+        }
+    }
+}
+
+fn advance_playback(
+    time: Res<Time>,
+    mut replay: ResMut<Replay>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    if !replay.playing {
+        return;
+    }
+
+    replay.progress += time.delta_seconds() * replay.speed;
+    while replay.progress >= 1.0 {
+        replay.progress -= 1.0;
+        if !replay.step_forward(&buildings, &units, &terrain) {
+            replay.playing = false;
+            break;
+        }
+    }
+}
+
+/// Shows the units and buildings of the current position on the board
+fn render_board(
+    replay: Res<Replay>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    mut label_q: Query<(&ReplayTileLabel, &mut Text)>,
+) {
+    if !replay.is_changed() {
+        return;
+    }
+
+    let board = &replay.game_state.board;
+    for (ReplayTileLabel(index), mut text) in label_q.iter_mut() {
+        let board_tile = &board[*index];
+        let mut lines = Vec::new();
+        let mut faction = None;
+        if let Some(building) = board_tile.building {
+            let descriptor = &buildings[building.kind];
+            lines.push(format!("{} {}", descriptor.pub_name, building.health));
+            faction = Some(descriptor.faction.clone());
+        }
+        if let Some(unit) = board_tile.unit {
+            let descriptor = &units[unit.kind];
+            lines.push(format!("{} {}", descriptor.pub_name, unit.health));
+            faction = Some(descriptor.faction.clone());
+        }
+
+        let section = &mut text.sections[0];
+        section.value = lines.join("\n");
+        section.style.color = match faction {
+            Some(faction) if faction == Faction::Volcano.to_string() => Color::ORANGE_RED,
+            Some(faction) if faction == Faction::Dinosaur.to_string() => Color::DARK_GREEN,
+            _ => Color::BLACK,
+        };
+    }
+}
+
+fn update_hud(replay: Res<Replay>, mut hud_q: Query<&mut Text, With<ReplayHud>>) {
+    if !replay.is_changed() {
+        return;
+    }
+
+    let last_event = match replay.position().checked_sub(1) {
+        Some(index) => format!("{:?}", replay.save.events[index]),
+        None => "Start of match".to_string(),
+    };
+    let turn_input = if replay.turn_input.is_empty() {
+        String::new()
+    } else {
+        format!("  Go to turn: {}", replay.turn_input)
+    };
+
+    for mut text in hud_q.iter_mut() {
+        text.sections[0].value = format!(
+            "{}  Event {}/{}  Turn {}/{}  Speed {}x{}\n{}\n\
+             Space: play/pause  Left/Right: step  Up/Down: speed  Number + Enter: go to turn  Esc: menu",
+            if replay.playing { "Playing" } else { "Paused" },
+            replay.position(),
+            replay.len(),
+            replay.current_turn(),
+            replay.turn_starts().len(),
+            replay.speed,
+            turn_input,
+            last_event,
+        );
+    }
+}
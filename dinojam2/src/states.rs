@@ -8,6 +8,7 @@ pub enum AppState {
     AssetsLoading,
     MainMenu,
     InGame,
+    Replay,
     // PlayCutscene,
     // // dev tools / editors
     // EditorCutscene,
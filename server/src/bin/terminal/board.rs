@@ -0,0 +1,138 @@
+use std::fmt::Write;
+
+use shared::{
+    buildings::Buildings, terrain::Terrain, units::Units, GameState, PlayerId, Stage, MAP_HEIGHT,
+    MAP_WIDTH,
+};
+
+// Letters players are shown as on the board, in order of their ids
+const PLAYER_MARKERS: [char; 4] = ['A', 'B', 'C', 'D'];
+
+/// Draws the board as text, followed by a list of the players and everything on the board.
+///
+/// Every tile is shown as its index, its terrain and what stands on it. Odd rows are shifted
+/// half a tile to the right, like they are in the game:
+///
+/// ```text
+///   0.   1.   2~A  3~
+///      8.   9^*  10.B 11.
+/// ```
+///
+/// A letter is a unit of that player, `*` a building nobody stands on. `watching` is the player
+/// a spectator is watching from the side of.
+pub fn render(
+    game_state: &GameState,
+    me: Option<PlayerId>,
+    watching: Option<PlayerId>,
+    buildings: &Buildings,
+    units: &Units,
+    terrain: &Terrain,
+) -> String {
+    let mut text = String::new();
+
+    for y in 0..MAP_HEIGHT {
+        if y % 2 == 1 {
+            text.push_str("   ");
+        }
+        for x in 0..MAP_WIDTH {
+            let index = y * MAP_WIDTH + x;
+            let tile = &game_state.board[index];
+            let marker = match (&tile.unit, &tile.building) {
+                (Some(unit), _) => marker(game_state, unit.owner),
+                (None, Some(_)) => '*',
+                (None, None) => ' ',
+            };
+            write!(
+                text,
+                "{:>3}{}{} ",
+                index,
+                terrain_glyph(&terrain[tile.terrain].name),
+                marker
+            )
+            .unwrap();
+        }
+        text.push('\n');
+    }
+    text.push('\n');
+
+    for (player_id, player) in game_state.players.iter() {
+        let mut notes = Vec::new();
+        if me == Some(*player_id) {
+            notes.push("you");
+        }
+        if watching == Some(*player_id) {
+            notes.push("watching");
+        }
+        if game_state.stage == Stage::InGame && game_state.active_player_id == *player_id {
+            notes.push("playing");
+        }
+        if player.eliminated {
+            notes.push("eliminated");
+        }
+        write!(
+            text,
+            "{} {} ({}, team {}) {} gold",
+            marker(game_state, *player_id),
+            player.name,
+            player.faction,
+            player.team,
+            game_state.available_gold(*player_id)
+        )
+        .unwrap();
+        if !notes.is_empty() {
+            write!(text, " [{}]", notes.join(", ")).unwrap();
+        }
+        text.push('\n');
+    }
+
+    for (index, tile) in game_state.board.iter().enumerate() {
+        if let Some(unit) = &tile.unit {
+            let descriptor = &units[unit.kind];
+            writeln!(
+                text,
+                "{:>3}: {} {} {}/{} hp",
+                index,
+                marker(game_state, unit.owner),
+                descriptor.name,
+                unit.health,
+                descriptor.max_hp
+            )
+            .unwrap();
+        }
+        if let Some(building) = &tile.building {
+            let descriptor = &buildings[building.kind];
+            writeln!(
+                text,
+                "{:>3}: {} ({}) {}/{} hp",
+                index, descriptor.name, descriptor.faction, building.health, descriptor.max_hp
+            )
+            .unwrap();
+        }
+    }
+
+    match game_state.stage {
+        Stage::PreGame => text.push_str("Waiting for players to join\n"),
+        Stage::InGame => writeln!(text, "Round {}", game_state.round).unwrap(),
+        Stage::Ended => text.push_str("The game is over\n"),
+    }
+    text
+}
+
+/// The letter a player is shown as
+pub fn marker(game_state: &GameState, player_id: PlayerId) -> char {
+    game_state
+        .players
+        .keys()
+        .position(|id| *id == player_id)
+        .and_then(|seat| PLAYER_MARKERS.get(seat).copied())
+        .unwrap_or('?')
+}
+
+fn terrain_glyph(name: &str) -> char {
+    match name {
+        "Land" => '.',
+        "Water" => '~',
+        "Lava" => '^',
+        _ => name.chars().next().unwrap_or('?'),
+    }
+}
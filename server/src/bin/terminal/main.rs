@@ -0,0 +1,697 @@
+//! Plays the game from a terminal, without a window or a GPU.
+//!
+//! Connects to `dinojam2-server` like the game does, draws the board as text and reads commands
+//! from stdin, one per line. Since stdin can just as well be a file, it can also play out a
+//! script of moves:
+//!
+//! ```text
+//! wait
+//! build 12 DinoScout
+//! move 12 13
+//! end
+//! ```
+//!
+//! Run it from a directory with the game's `assets` folder in it, or point it at one with
+//! `--assets <dir>`. With `--spectate` it only watches the match, and `view <player>` shows the
+//! board from the side of one of the players.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead},
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use bevy_renet::renet::{ClientAuthentication, RenetClient};
+use log::{error, warn};
+use shared::{
+    auth,
+    buildings::Buildings,
+    channels::{client_connection_config, ServerChannel},
+    chat::{ChatScope, Emote},
+    connection::{ConnectInfo, Session, SessionToken},
+    hash,
+    messages::{self, ClientMessage, DecodeError, DesyncReport, Handshake, ServerMessage},
+    terrain::Terrain,
+    units::{UnitKind, Units},
+    GameEvent, GameState, PlayerId, Stage,
+};
+
+mod board;
+
+const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:5000";
+const DEFAULT_PLAYER_NAME: &str = "Terminal";
+
+// How often the connection is looked after
+const TICK: Duration = Duration::from_millis(16);
+
+const HELP: &str = "\
+Commands:
+  build <tile> <unit>  build a unit on one of your buildings
+  move <from> <to>     move a unit, or attack with it
+  end                  end your turn
+  undo                 take back your last action this turn
+  say <text>           chat to the other players
+  team <text>          chat to your team only
+  emote <name>         send a quick message: hello, goodluck, wellplayed, thanks, oops, goodgame
+  board                draw the board again
+  view <player|all>    watch from the side of the player with that letter, or everyone's
+  wait                 hold off on the commands after this one until it is your turn
+  help                 show this
+  quit                 leave the game";
+
+/// Command line options of the terminal client
+struct TerminalArgs {
+    name: String,
+    server: SocketAddr,
+    assets: PathBuf,
+    /// Watch the match instead of playing in it
+    spectate: bool,
+    /// Token of a seat to take back, like one in a match the server resumed from a save
+    session_token: Option<SessionToken>,
+}
+
+impl TerminalArgs {
+    /// Reads `--name <name>`, `--server <addr>`, `--assets <dir>`, `--spectate` and
+    /// `--session-token <token>`
+    fn from_env() -> Self {
+        let mut args = Self {
+            name: DEFAULT_PLAYER_NAME.to_string(),
+            server: DEFAULT_SERVER_ADDR.parse().unwrap(),
+            assets: PathBuf::from("assets"),
+            spectate: false,
+            session_token: None,
+        };
+
+        let mut env_args = std::env::args().skip(1);
+        while let Some(arg) = env_args.next() {
+            match arg.as_str() {
+                "--name" => args.name = env_args.next().expect("--name needs a name"),
+                "--server" => {
+                    args.server = env_args
+                        .next()
+                        .and_then(|addr| addr.parse().ok())
+                        .expect("--server needs an address, like 127.0.0.1:5000")
+                }
+                "--assets" => {
+                    args.assets = env_args
+                        .next()
+                        .map(PathBuf::from)
+                        .expect("--assets needs a directory")
+                }
+                "--spectate" => args.spectate = true,
+                "--session-token" => {
+                    args.session_token = Some(
+                        env_args
+                            .next()
+                            .and_then(|token| token.parse().ok())
+                            .expect("--session-token needs a token"),
+                    )
+                }
+                _ => warn!("Ignoring unknown argument {}", arg),
+            }
+        }
+        args
+    }
+}
+
+/// Something typed in by the player
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Build { at: usize, unit: String },
+    Move { from: usize, to: usize },
+    End,
+    Undo,
+    Say(ChatScope, String),
+    Emote(Emote),
+    Board,
+    View(Option<char>),
+    Wait,
+    Help,
+    Quit,
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let tile = |word: Option<&str>| {
+            word.and_then(|word| word.parse().ok())
+                .ok_or_else(|| "expected a tile number".to_string())
+        };
+
+        let command = match words.next().unwrap_or_default() {
+            "build" => Command::Build {
+                at: tile(words.next())?,
+                unit: words
+                    .next()
+                    .ok_or_else(|| "build needs a unit".to_string())?
+                    .to_string(),
+            },
+            "move" => Command::Move {
+                from: tile(words.next())?,
+                to: tile(words.next())?,
+            },
+            "end" => Command::End,
+            "undo" => Command::Undo,
+            "say" => Command::Say(ChatScope::Everyone, words.collect::<Vec<_>>().join(" ")),
+            "team" => Command::Say(ChatScope::Team, words.collect::<Vec<_>>().join(" ")),
+            "emote" => Command::Emote(
+                words
+                    .next()
+                    .ok_or_else(|| "emote needs a name, like wellplayed".to_string())?
+                    .parse()?,
+            ),
+            "board" => Command::Board,
+            "view" => match words.next() {
+                Some("all") => Command::View(None),
+                Some(player) if player.chars().count() == 1 => Command::View(
+                    player
+                        .chars()
+                        .next()
+                        .map(|letter| letter.to_ascii_uppercase()),
+                ),
+                _ => return Err("view needs a player letter, or all".to_string()),
+            },
+            "wait" => Command::Wait,
+            "help" => Command::Help,
+            "quit" => Command::Quit,
+            other => return Err(format!("unknown command {:?}, try help", other)),
+        };
+        Ok(command)
+    }
+}
+
+/// Everything the client knows about the game
+struct Client {
+    renet: RenetClient,
+    game_state: GameState,
+    session: Option<Session>,
+    /// Whether we are only watching
+    spectating: bool,
+    /// The player whose side of the board we are watching from, if any
+    perspective: Option<PlayerId>,
+    /// Set once the snapshot from the server arrived
+    synced: bool,
+    /// Events that arrived before the snapshot did
+    pending: Vec<(usize, GameEvent, u64)>,
+    handshake_sent: bool,
+    buildings: Buildings,
+    units: Units,
+    terrain: Terrain,
+}
+
+fn main() {
+    env_logger::init();
+    let args = TerminalArgs::from_env();
+    let (buildings, units, terrain) = server::descriptors::load(&args.assets);
+
+    let connect_info = ConnectInfo {
+        name: args.name.clone(),
+        session_token: args.session_token,
+        spectator: args.spectate,
+    };
+    let renet = match new_renet_client(args.server, &connect_info) {
+        Ok(renet) => renet,
+        Err(err) => {
+            error!("Could not connect to {}: {}", args.server, err);
+            std::process::exit(1);
+        }
+    };
+    let mut client = Client {
+        renet,
+        game_state: GameState::default(),
+        session: None,
+        spectating: args.spectate,
+        perspective: None,
+        synced: false,
+        pending: Vec::new(),
+        handshake_sent: false,
+        buildings,
+        units,
+        terrain,
+    };
+
+    println!(
+        "Connecting to {} as {}, type help for commands",
+        args.server, args.name
+    );
+    let input = read_stdin();
+    let mut commands = VecDeque::new();
+    let mut input_closed = false;
+    let mut waiting = false;
+    let mut last_update = Instant::now();
+
+    loop {
+        let now = Instant::now();
+        if let Err(err) = client.renet.update(now - last_update) {
+            error!("Lost connection to the server: {}", err);
+            std::process::exit(1);
+        }
+        last_update = now;
+
+        loop {
+            match input.try_recv() {
+                Ok(line) if line.trim().is_empty() => {}
+                Ok(line) => match Command::parse(&line) {
+                    Ok(command) => commands.push_back(command),
+                    Err(err) => println!("{}", err),
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    input_closed = true;
+                    break;
+                }
+            }
+        }
+
+        if client.renet.is_connected() {
+            if !client.handshake_sent {
+                let handshake = Handshake::new(&client.buildings, &client.units, &client.terrain);
+                client.send(ClientMessage::Hello(handshake));
+                client.handshake_sent = true;
+            }
+            if !client.receive() {
+                break;
+            }
+
+            if waiting && client.can_stop_waiting() {
+                waiting = false;
+            }
+            while !waiting && client.joined() {
+                match commands.pop_front() {
+                    Some(Command::Wait) => waiting = !client.can_stop_waiting(),
+                    Some(Command::Quit) => {
+                        client.disconnect();
+                        return;
+                    }
+                    Some(command) => client.run(command),
+                    None => break,
+                }
+            }
+        }
+
+        // A script that ran out of commands is done with the game
+        if input_closed && commands.is_empty() && !waiting && client.joined() {
+            client.disconnect();
+            return;
+        }
+
+        if let Err(err) = client.renet.send_packets() {
+            error!("Lost connection to the server: {}", err);
+            std::process::exit(1);
+        }
+        thread::sleep(TICK);
+    }
+}
+
+impl Client {
+    fn me(&self) -> Option<PlayerId> {
+        self.session.map(|session| session.player_id)
+    }
+
+    /// Whether we have a seat, or are watching and have seen the board
+    fn joined(&self) -> bool {
+        self.session.is_some() || (self.spectating && self.synced)
+    }
+
+    fn send(&mut self, message: ClientMessage) {
+        self.renet
+            .send_message(message.channel(), messages::encode(&message));
+    }
+
+    fn disconnect(&mut self) {
+        // Get anything that is still waiting out the door first
+        self.renet.send_packets().ok();
+        self.renet.disconnect();
+    }
+
+    /// Whether it is our turn, or there will never be one again. Spectators never get a turn,
+    /// so they wait until the game is over.
+    fn can_stop_waiting(&self) -> bool {
+        match self.game_state.stage {
+            Stage::PreGame => false,
+            Stage::InGame => self.synced && Some(self.game_state.active_player_id) == self.me(),
+            Stage::Ended => true,
+        }
+    }
+
+    fn draw(&self) {
+        println!(
+            "{}",
+            board::render(
+                &self.game_state,
+                self.me(),
+                self.perspective,
+                &self.buildings,
+                &self.units,
+                &self.terrain
+            )
+        );
+    }
+
+    fn run(&mut self, command: Command) {
+        match command {
+            Command::Board => return self.draw(),
+            Command::View(letter) => return self.view(letter),
+            Command::Help => return println!("{}", HELP),
+            _ => {}
+        }
+
+        let player_id = match self.me() {
+            Some(player_id) => player_id,
+            None => return println!("Spectators can only watch"),
+        };
+
+        let event = match command {
+            Command::Build { at, unit } => {
+                let unit_kind = self.units.iter().position(|descriptor| {
+                    descriptor.name.eq_ignore_ascii_case(&unit)
+                        || descriptor.pub_name.eq_ignore_ascii_case(&unit)
+                });
+                match unit_kind {
+                    Some(unit_kind) => GameEvent::BuildUnit {
+                        player_id,
+                        at,
+                        unit_kind: UnitKind(unit_kind),
+                    },
+                    None => {
+                        println!("There is no unit called {}", unit);
+                        return;
+                    }
+                }
+            }
+            Command::Move { from, to } => GameEvent::MoveUnit {
+                player_id,
+                from,
+                to,
+            },
+            Command::End => GameEvent::EndTurn { player_id },
+            Command::Undo => return self.send(ClientMessage::UndoAction),
+            Command::Say(scope, text) => return self.send(ClientMessage::Chat { scope, text }),
+            Command::Emote(emote) => return self.send(ClientMessage::Emote(emote)),
+            Command::Board | Command::View(_) | Command::Help | Command::Wait | Command::Quit => {
+                return
+            }
+        };
+        self.send(ClientMessage::GameEvent(event));
+    }
+
+    /// Watches from the side of the player shown as `letter`, or everyone's
+    fn view(&mut self, letter: Option<char>) {
+        let player_id = letter.and_then(|letter| {
+            self.game_state
+                .players
+                .keys()
+                .copied()
+                .find(|player_id| board::marker(&self.game_state, *player_id) == letter)
+        });
+        match (letter, player_id) {
+            (Some(letter), None) => println!("There is no player {}", letter),
+            _ => {
+                self.perspective = player_id;
+                self.draw();
+            }
+        }
+    }
+
+    /// The name a player joined with, or their id if we dont know them
+    fn name_of(&self, player_id: PlayerId) -> String {
+        self.game_state
+            .players
+            .get(&player_id)
+            .map(|player| player.name.clone())
+            .unwrap_or_else(|| player_id.to_string())
+    }
+
+    /// Handles everything the server sent. Returns false once there is no point staying.
+    fn receive(&mut self) -> bool {
+        for channel in ServerChannel::ALL {
+            while let Some(message) = self.renet.receive_message(channel) {
+                let message = match messages::decode::<ServerMessage>(&message) {
+                    Ok(message) => message,
+                    Err(DecodeError::WrongVersion(version)) => {
+                        error!(
+                            "The server speaks protocol version {}, we speak {}",
+                            version,
+                            messages::PROTOCOL_VERSION
+                        );
+                        return false;
+                    }
+                    Err(err) => {
+                        warn!("Failed to read message from server: {}", err);
+                        continue;
+                    }
+                };
+
+                match message {
+                    ServerMessage::StateSnapshot(snapshot) => {
+                        self.game_state = *snapshot;
+                        self.synced = true;
+                        for (index, event, state_hash) in std::mem::take(&mut self.pending) {
+                            // Events after a desync wait for the next snapshot
+                            if self.synced {
+                                self.apply(index, event, state_hash);
+                            } else {
+                                self.pending.push((index, event, state_hash));
+                            }
+                        }
+                        self.draw();
+                    }
+                    ServerMessage::Session(session) => {
+                        println!(
+                            "Playing as player {}, take the seat back with --session-token {}",
+                            session.player_id, session.token
+                        );
+                        self.session = Some(session);
+                    }
+                    ServerMessage::Disconnect(reason) => {
+                        error!("Disconnected by the server: {:?}", reason);
+                        return false;
+                    }
+                    ServerMessage::GameEvent {
+                        index,
+                        event,
+                        state_hash,
+                    } => {
+                        if self.synced {
+                            self.apply(index, event, state_hash);
+                        } else {
+                            self.pending.push((index, event, state_hash));
+                        }
+                    }
+                    ServerMessage::Undone { index, state_hash } => {
+                        if self.synced && index < self.game_state.histroy.len() {
+                            println!("Undid {:?}", self.game_state.histroy[index]);
+                            self.game_state = GameState::from_events(
+                                &self.game_state.initial_board,
+                                &self.game_state.histroy[..index],
+                                &self.buildings,
+                                &self.units,
+                                &self.terrain,
+                            );
+                            self.check_sync(index, state_hash);
+                        }
+                    }
+                    ServerMessage::UndoRejected => println!("Nothing to undo"),
+                    ServerMessage::Rejected { event, reason } => {
+                        println!("Rejected {:?}: {:?}", event, reason)
+                    }
+                    ServerMessage::Lobby(players) => {
+                        let names: Vec<_> = players
+                            .iter()
+                            .map(|player| {
+                                if player.connected {
+                                    player.name.clone()
+                                } else {
+                                    format!("{} (away)", player.name)
+                                }
+                            })
+                            .collect();
+                        println!("Players: {}", names.join(", "));
+                    }
+                    ServerMessage::Spectators(names) => {
+                        println!("Watching: {}", names.join(", "));
+                    }
+                    ServerMessage::Chat {
+                        player_id,
+                        scope,
+                        text,
+                    } => match scope {
+                        ChatScope::Everyone => println!("<{}> {}", self.name_of(player_id), text),
+                        ChatScope::Team => {
+                            println!("<{}> (team) {}", self.name_of(player_id), text)
+                        }
+                    },
+                    ServerMessage::Emote { player_id, emote } => {
+                        println!("* {} {}", self.name_of(player_id), emote);
+                    }
+                    ServerMessage::ChatRejected(reason) => {
+                        println!("Chat message not sent: {:?}", reason)
+                    }
+                    ServerMessage::TurnTimer {
+                        player_id,
+                        remaining_ms,
+                    } => {
+                        if Some(player_id) == self.me() {
+                            println!("{}s left on your turn", remaining_ms / 1000);
+                        }
+                    }
+                    ServerMessage::Cursor { .. } | ServerMessage::Pong(_) => {}
+                }
+            }
+        }
+        true
+    }
+
+    /// Consumes an event from the server, unless the snapshot already had it
+    fn apply(&mut self, index: usize, event: GameEvent, state_hash: u64) {
+        if index < self.game_state.histroy.len() {
+            return;
+        }
+
+        println!("{}", self.describe(&event));
+        self.game_state
+            .consume(&event, &self.buildings, &self.units, &self.terrain);
+        self.check_sync(index + 1, state_hash);
+
+        match event {
+            GameEvent::BeginGame { .. } | GameEvent::EndGame { .. } => self.draw(),
+            GameEvent::EndTurn { .. } if self.can_stop_waiting() => {
+                println!("Your turn");
+                self.draw();
+            }
+            GameEvent::EndTurn { .. }
+                if self.perspective == Some(self.game_state.active_player_id) =>
+            {
+                self.draw()
+            }
+            _ => {}
+        }
+    }
+
+    /// Compares our GameState against the server's, once it has `history_len` events. If they
+    /// differ the server is told, and everything it sends is held back until the fresh snapshot
+    /// it answers with.
+    fn check_sync(&mut self, history_len: usize, state_hash: u64) {
+        let actual_hash = hash::state_hash(&self.game_state);
+        if actual_hash == state_hash && self.game_state.histroy.len() == history_len {
+            return;
+        }
+
+        let index = history_len.saturating_sub(1);
+        warn!(
+            "Out of sync with the server after event {}: expected hash {:#x}, got {:#x}",
+            index, state_hash, actual_hash
+        );
+        self.synced = false;
+        self.send(ClientMessage::Desync(DesyncReport {
+            index,
+            expected_hash: state_hash,
+            actual_hash,
+        }));
+    }
+
+    /// A line about what happened, for the player to read
+    fn describe(&self, event: &GameEvent) -> String {
+        let name = |player_id: &PlayerId| {
+            let name = self
+                .game_state
+                .players
+                .get(player_id)
+                .map(|player| player.name.as_str())
+                .unwrap_or("someone");
+            format!("{} ({})", name, board::marker(&self.game_state, *player_id))
+        };
+
+        match event {
+            GameEvent::ConfigureMatch { settings } => format!("Match set up: {:?}", settings),
+            GameEvent::BeginGame { goes_first } => {
+                format!("The game begins, {} goes first", name(goes_first))
+            }
+            GameEvent::EndGame { reason } => format!("The game is over: {:?}", reason),
+            GameEvent::PlayerJoined { name, .. } => format!("{} joined", name),
+            GameEvent::PlayerDisconnected { player_id } => format!("{} left", name(player_id)),
+            GameEvent::PlayerEliminated { player_id } => {
+                format!("{} was eliminated", name(player_id))
+            }
+            GameEvent::BuildUnit {
+                player_id,
+                at,
+                unit_kind,
+            } => format!(
+                "{} built a {} on {}",
+                name(player_id),
+                self.units[*unit_kind].name,
+                at
+            ),
+            GameEvent::MoveUnit {
+                player_id,
+                from,
+                to,
+            } => format!("{} moved {} to {}", name(player_id), from, to),
+            GameEvent::EndTurn { player_id } => format!("{} ended their turn", name(player_id)),
+        }
+    }
+}
+
+/// Reads stdin on a thread of its own, so the connection is looked after while waiting on input.
+/// The channel disconnects once stdin is closed.
+fn read_stdin() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Creates a RenetClient that starts connecting to the server straight away, the same way the
+/// game does
+fn new_renet_client(
+    server_addr: SocketAddr,
+    connect_info: &ConnectInfo,
+) -> io::Result<RenetClient> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let (client_id, authentication) = if auth::secure_mode() {
+        let issued = auth::request_connect_token(auth::token_service_addr(), connect_info)?;
+        (
+            issued.client_id,
+            ClientAuthentication::Secure {
+                connect_token: issued.connect_token,
+            },
+        )
+    } else {
+        let client_id = current_time.as_millis() as u64;
+        (
+            client_id,
+            ClientAuthentication::Unsecure {
+                client_id,
+                protocol_id: shared::PROTOCOL_ID,
+                server_addr,
+                user_data: Some(connect_info.to_user_data()),
+            },
+        )
+    };
+
+    RenetClient::new(
+        current_time,
+        socket,
+        client_id,
+        client_connection_config(),
+        authentication,
+    )
+    .map_err(|err| io::Error::other(err.to_string()))
+}
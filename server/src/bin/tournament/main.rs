@@ -0,0 +1,375 @@
+//! Plays bots against each other without a server or any clients, to see which one is stronger
+//! and to gather numbers for balancing the units.
+//!
+//! Run it from a directory with the game's `assets` folder in it, or point it at one with
+//! `--assets <dir>`.
+
+use std::{collections::BTreeMap, fmt, fs, path::PathBuf, str::FromStr, time::Duration};
+
+use log::{info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use shared::{
+    ai::{
+        heuristic::{self, HeuristicBot},
+        mcts::{Budget, MctsBot},
+        Bot, Difficulty,
+    },
+    buildings::Buildings,
+    terrain::Terrain,
+    units::Units,
+    EndGameReason, GameEvent, GameState, MatchSettings, PlayerId, Stage,
+};
+
+mod maps;
+mod stats;
+
+use maps::Map;
+use stats::{MatchRecord, Report};
+
+const MAPS_DIR: &str = "maps";
+
+const MIN_PLAYERS: usize = 2;
+const MAX_PLAYERS: usize = 4;
+
+/// A kind of bot to sit down in matches, written like `heuristic:hard`, `mcts:200` for a
+/// budget of 200 iterations or `mcts:50ms` for 50 milliseconds per action
+#[derive(Debug, Clone, Copy)]
+enum BotSpec {
+    Heuristic(Difficulty),
+    Mcts(Budget),
+}
+
+impl BotSpec {
+    fn create(&self, seed: u64) -> Box<dyn Bot> {
+        match self {
+            BotSpec::Heuristic(difficulty) => Box::new(HeuristicBot::with_seed(*difficulty, seed)),
+            BotSpec::Mcts(budget) => Box::new(MctsBot::with_seed(*budget, seed)),
+        }
+    }
+}
+
+impl fmt::Display for BotSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BotSpec::Heuristic(difficulty) => write!(f, "heuristic:{}", difficulty),
+            BotSpec::Mcts(Budget::Iterations(iterations)) => write!(f, "mcts:{}", iterations),
+            BotSpec::Mcts(Budget::Time(time)) => write!(f, "mcts:{}ms", time.as_millis()),
+        }
+    }
+}
+
+impl FromStr for BotSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, setting) = s.split_once(':').unwrap_or((s, ""));
+        match kind {
+            "heuristic" if setting.is_empty() => Ok(BotSpec::Heuristic(Difficulty::default())),
+            "heuristic" => Ok(BotSpec::Heuristic(setting.parse()?)),
+            "mcts" if setting.is_empty() => Ok(BotSpec::Mcts(Budget::Iterations(200))),
+            "mcts" => {
+                let budget = match setting.strip_suffix("ms") {
+                    Some(millis) => millis
+                        .parse()
+                        .map(|millis| Budget::Time(Duration::from_millis(millis))),
+                    None => setting.parse().map(Budget::Iterations),
+                };
+                budget.map(BotSpec::Mcts).map_err(|_| {
+                    format!("mcts needs a number of iterations or ms, not {}", setting)
+                })
+            }
+            _ => Err(format!("unknown bot {}, expected heuristic or mcts", kind)),
+        }
+    }
+}
+
+/// How the report is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Csv,
+}
+
+/// Command line options of the tournament
+struct TournamentArgs {
+    assets: PathBuf,
+    games: u32,
+    /// Games that go on for longer than this go to whoever is ahead, or are a draw if it is close
+    max_rounds: u32,
+    /// Decides everything random about the matches, so a run can be repeated
+    seed: u64,
+    /// Map files to play on, taking turns. All maps in the assets when empty.
+    maps: Vec<PathBuf>,
+    /// The bots to sit down in every match, one per seat
+    bots: Vec<BotSpec>,
+    settings: MatchSettings,
+    format: Format,
+    /// Where the report is written to, instead of stdout
+    output: Option<PathBuf>,
+}
+
+impl TournamentArgs {
+    /// Reads `--assets <dir>`, `--games <count>`, `--max-rounds <count>`, `--seed <number>`,
+    /// `--map <file>`, `--bot <spec>`, `--free-for-all`, `--shared-economy`,
+    /// `--format <json|csv>` and `--output <file>`. `--map` and `--bot` can be given more than
+    /// once.
+    fn from_env() -> Self {
+        let mut args = Self {
+            assets: PathBuf::from("assets"),
+            games: 100,
+            max_rounds: 50,
+            seed: rand::random(),
+            maps: Vec::new(),
+            bots: Vec::new(),
+            settings: MatchSettings::default(),
+            format: Format::Json,
+            output: None,
+        };
+
+        let mut env_args = std::env::args().skip(1);
+        while let Some(arg) = env_args.next() {
+            let mut value = || {
+                env_args
+                    .next()
+                    .unwrap_or_else(|| panic!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "--assets" => args.assets = PathBuf::from(value()),
+                "--games" => args.games = value().parse().expect("--games needs a count"),
+                "--max-rounds" => {
+                    args.max_rounds = value().parse().expect("--max-rounds needs a count")
+                }
+                "--seed" => args.seed = value().parse().expect("--seed needs a number"),
+                "--map" => args.maps.push(PathBuf::from(value())),
+                "--bot" => args.bots.push(
+                    value()
+                        .parse()
+                        .unwrap_or_else(|err| panic!("--bot: {}", err)),
+                ),
+                "--free-for-all" => args.settings.teams = shared::TeamMode::FreeForAll,
+                "--shared-economy" => args.settings.economy = shared::Economy::Shared,
+                "--format" => {
+                    args.format = match value().as_str() {
+                        "json" => Format::Json,
+                        "csv" => Format::Csv,
+                        format => panic!("--format needs json or csv, not {}", format),
+                    }
+                }
+                "--output" => args.output = Some(PathBuf::from(value())),
+                _ => warn!("Ignoring unknown argument {}", arg),
+            }
+        }
+
+        if args.bots.is_empty() {
+            args.bots = vec![
+                BotSpec::Mcts(Budget::Iterations(200)),
+                BotSpec::Heuristic(Difficulty::Hard),
+            ];
+        }
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&args.bots.len()) {
+            panic!(
+                "Matches need {} to {} bots, got {}",
+                MIN_PLAYERS,
+                MAX_PLAYERS,
+                args.bots.len()
+            );
+        }
+        args
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args = TournamentArgs::from_env();
+    let (buildings, units, terrain) = server::descriptors::load(&args.assets);
+    let maps = load_maps(&args, &buildings, &terrain);
+
+    info!(
+        "Playing {} games with seed {} on {} maps",
+        args.games,
+        args.seed,
+        maps.len()
+    );
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut report = Report::new(&units);
+    for game in 0..args.games {
+        let map = &maps[game as usize % maps.len()];
+
+        // Rotate the bots through the seats, so every bot gets to play every faction
+        let seat_count = args.bots.len();
+        let mut seats: Vec<(PlayerId, BotSpec, Box<dyn Bot>)> = (0..seat_count)
+            .map(|seat| {
+                let spec = args.bots[(seat + game as usize) % seat_count];
+                (seat as PlayerId + 1, spec, spec.create(rng.gen()))
+            })
+            .collect();
+        let goes_first = rng.gen_range(0..seat_count);
+
+        let record = play_match(
+            &mut seats,
+            goes_first,
+            map,
+            args.settings,
+            args.max_rounds,
+            &buildings,
+            &units,
+            &terrain,
+        );
+        info!(
+            "Game {} on {}: {} after {} rounds",
+            game + 1,
+            map.name,
+            match (record.winner, record.decided_at_cap) {
+                (Some(team), false) => format!("team {} won", team),
+                (Some(team), true) => format!("team {} was ahead", team),
+                (None, _) => "draw".to_string(),
+            },
+            record.rounds
+        );
+        report.record(&record, &units);
+    }
+
+    let output = match args.format {
+        Format::Json => report.to_json(),
+        Format::Csv => report.to_csv(),
+    };
+    match &args.output {
+        Some(path) => fs::write(path, output)
+            .unwrap_or_else(|err| panic!("Could not write {}: {}", path.display(), err)),
+        None => print!("{}", output),
+    }
+}
+
+/// Plays a whole match between the bots, each sat as the player next to it, and records how
+/// it went
+#[allow(clippy::too_many_arguments)]
+fn play_match(
+    seats: &mut [(PlayerId, BotSpec, Box<dyn Bot>)],
+    goes_first: usize,
+    map: &Map,
+    settings: MatchSettings,
+    max_rounds: u32,
+    buildings: &Buildings,
+    units: &Units,
+    terrain: &Terrain,
+) -> MatchRecord {
+    let mut game_state = GameState::on_board(map.board);
+    let mut setup = vec![GameEvent::ConfigureMatch { settings }];
+    setup.extend(
+        seats
+            .iter()
+            .map(|(player_id, spec, _)| GameEvent::PlayerJoined {
+                player_id: *player_id,
+                name: spec.to_string(),
+            }),
+    );
+    setup.push(GameEvent::BeginGame {
+        goes_first: seats[goes_first].0,
+    });
+    for event in setup {
+        game_state.consume(&event, buildings, units, terrain);
+    }
+    // Nobody earns gold in a match yet, so the map hands it out up front
+    for player in game_state.players.values_mut() {
+        player.gold = map.starting_gold;
+    }
+
+    let mut kills = Vec::new();
+    while game_state.stage == Stage::InGame && game_state.round <= max_rounds {
+        let player_id = game_state.active_player_id;
+        let bot = match seats.iter_mut().find(|(seat, _, _)| *seat == player_id) {
+            Some((_, _, bot)) => bot,
+            None => break,
+        };
+
+        // Bots play by the same rules as the server would hold them to
+        let event = bot
+            .choose_action(&game_state, player_id, buildings, units, terrain)
+            .filter(|event| game_state.validate(event, buildings, units, terrain))
+            .unwrap_or(GameEvent::EndTurn { player_id });
+
+        let fight = match event {
+            GameEvent::MoveUnit { from, to, .. } => game_state.board[from]
+                .unit
+                .zip(game_state.board[to].unit)
+                .map(|(attacker, defender)| (to, attacker, defender)),
+            _ => None,
+        };
+        game_state.consume(&event, buildings, units, terrain);
+        if let Some((to, attacker, defender)) = fight {
+            let survived = game_state.board[to]
+                .unit
+                .map(|unit| unit.owner == defender.owner)
+                .unwrap_or(false);
+            if !survived {
+                kills.push((attacker.kind.0, defender.kind.0));
+            }
+        }
+
+        if let Some(team) = game_state.determine_winner() {
+            let event = GameEvent::EndGame {
+                reason: EndGameReason::TeamWon { team },
+            };
+            game_state.consume(&event, buildings, units, terrain);
+        }
+    }
+
+    // Nothing in the rules ends a game on its own yet, so a game that runs into the round cap
+    // goes to the team that is ahead on the board
+    let decided_at_cap = game_state.stage == Stage::InGame;
+    if decided_at_cap {
+        if let Some(team) = heuristic::leading_team(&game_state, buildings, units) {
+            let event = GameEvent::EndGame {
+                reason: EndGameReason::TeamWon { team },
+            };
+            game_state.consume(&event, buildings, units, terrain);
+        }
+    }
+
+    let winner = match game_state.histroy.last() {
+        Some(GameEvent::EndGame {
+            reason: EndGameReason::TeamWon { team },
+        }) => Some(*team),
+        _ => None,
+    };
+    let bots: BTreeMap<PlayerId, String> = seats
+        .iter()
+        .map(|(player_id, spec, _)| (*player_id, spec.to_string()))
+        .collect();
+    MatchRecord {
+        map: map.name.clone(),
+        bots,
+        // The round counter has already moved on to the first round past the cap
+        rounds: game_state.round.min(max_rounds),
+        game_state,
+        winner,
+        decided_at_cap,
+        kills,
+    }
+}
+
+/// The maps that were asked for, or every map in the assets if none were
+fn load_maps(args: &TournamentArgs, buildings: &Buildings, terrain: &Terrain) -> Vec<Map> {
+    let maps = if args.maps.is_empty() {
+        let dir = args.assets.join(MAPS_DIR);
+        if dir.is_dir() {
+            Map::read_dir(&dir, buildings, terrain)
+        } else {
+            Ok(Vec::new())
+        }
+    } else {
+        args.maps
+            .iter()
+            .map(|path| {
+                Map::read(path, buildings, terrain)
+                    .map_err(|err| format!("{}: {}", path.display(), err))
+            })
+            .collect()
+    };
+
+    let mut maps = maps.unwrap_or_else(|err| panic!("Could not load map {}", err));
+    if maps.is_empty() {
+        maps.push(Map::default_board());
+    }
+    maps
+}
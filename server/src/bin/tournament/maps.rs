@@ -0,0 +1,131 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+use shared::{
+    buildings::{Building, BuildingKind, Buildings},
+    terrain::{Terrain, TerrainKind},
+    BoardTile, GameState, MAP_HEIGHT, MAP_SIZE, MAP_WIDTH,
+};
+
+/// Extension of the files maps are read from
+pub const MAP_EXTENSION: &str = "map.toml";
+
+/// A board to play matches on, along with the gold everyone starts with
+pub struct Map {
+    pub name: String,
+    pub board: [BoardTile; MAP_SIZE],
+    pub starting_gold: u32,
+}
+
+/// A map as it is written down. Every row is a string with a character per tile:
+///
+/// - `.` land
+/// - `~` water
+/// - `^` lava
+/// - `V` a volcano on land
+/// - `D` a dino village on land
+#[derive(Deserialize)]
+struct MapFile {
+    name: String,
+    #[serde(default)]
+    starting_gold: u32,
+    rows: Vec<String>,
+}
+
+impl Map {
+    /// The board every match starts on when no map is picked
+    pub fn default_board() -> Self {
+        Self {
+            name: "default".to_string(),
+            board: GameState::default().board,
+            starting_gold: 0,
+        }
+    }
+
+    pub fn read(path: &Path, buildings: &Buildings, terrain: &Terrain) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let file: MapFile = toml::from_str(&text).map_err(|err| err.to_string())?;
+        if file.rows.len() != MAP_HEIGHT {
+            return Err(format!(
+                "map has {} rows, expected {}",
+                file.rows.len(),
+                MAP_HEIGHT
+            ));
+        }
+
+        let terrain_kind = |name: &str| {
+            terrain
+                .iter()
+                .position(|descriptor| descriptor.name == name)
+                .map(TerrainKind)
+                .ok_or_else(|| format!("there is no {} terrain", name))
+        };
+        let building_kind = |name: &str| {
+            buildings
+                .iter()
+                .position(|descriptor| descriptor.name == name)
+                .map(BuildingKind)
+                .ok_or_else(|| format!("there is no {} building", name))
+        };
+
+        let mut board = [BoardTile::default(); MAP_SIZE];
+        for (y, row) in file.rows.iter().enumerate() {
+            let tiles: Vec<char> = row.chars().filter(|c| !c.is_whitespace()).collect();
+            if tiles.len() != MAP_WIDTH {
+                return Err(format!(
+                    "row {} has {} tiles, expected {}",
+                    y + 1,
+                    tiles.len(),
+                    MAP_WIDTH
+                ));
+            }
+
+            for (x, tile) in tiles.into_iter().enumerate() {
+                let position = (x as u32, y as u32);
+                let (terrain_name, building_name) = match tile {
+                    '.' => ("Land", None),
+                    '~' => ("Water", None),
+                    '^' => ("Lava", None),
+                    'V' => ("Land", Some("Volcano")),
+                    'D' => ("Land", Some("DinoVillage")),
+                    _ => return Err(format!("unknown tile {:?} in row {}", tile, y + 1)),
+                };
+
+                let board_tile = &mut board[y * MAP_WIDTH + x];
+                board_tile.terrain = terrain_kind(terrain_name)?;
+                if let Some(building_name) = building_name {
+                    let kind = building_kind(building_name)?;
+                    board_tile.building = Some(Building::new(position, kind, buildings));
+                }
+            }
+        }
+
+        Ok(Self {
+            name: file.name,
+            board,
+            starting_gold: file.starting_gold,
+        })
+    }
+
+    /// Reads every map in a directory, in order of their file names
+    pub fn read_dir(
+        dir: &Path,
+        buildings: &Buildings,
+        terrain: &Terrain,
+    ) -> Result<Vec<Self>, String> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .map_err(|err| format!("{}: {}", dir.display(), err))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.to_string_lossy().ends_with(MAP_EXTENSION))
+            .collect();
+        paths.sort();
+
+        paths
+            .iter()
+            .map(|path| {
+                Self::read(path, buildings, terrain)
+                    .map_err(|err| format!("{}: {}", path.display(), err))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use serde::Serialize;
+use shared::{units::Units, GameEvent, GameState, TeamId};
+
+/// How a single match went
+pub struct MatchRecord {
+    pub map: String,
+    /// The bot that sat in each seat, by player id
+    pub bots: BTreeMap<shared::PlayerId, String>,
+    /// Rounds that were played, which the round counter is one past if the game hit the cap
+    pub rounds: u32,
+    pub game_state: GameState,
+    pub winner: Option<TeamId>,
+    /// The game hit the round cap and was given to the team that was ahead, if any
+    pub decided_at_cap: bool,
+    /// Every fight that killed a unit, as the kind of the attacker and the kind that died
+    pub kills: Vec<(usize, usize)>,
+}
+
+/// Everything learned from a batch of matches
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub games: u32,
+    pub draws: u32,
+    /// Games that hit the round cap and went to the team that was ahead
+    pub decided_at_cap: u32,
+    pub average_rounds: f64,
+    pub average_actions: f64,
+    pub factions: BTreeMap<String, WinStats>,
+    pub bots: BTreeMap<String, WinStats>,
+    pub maps: BTreeMap<String, MapStats>,
+    pub units: BTreeMap<String, UnitStats>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct WinStats {
+    pub games: u32,
+    pub wins: u32,
+    pub win_rate: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MapStats {
+    pub games: u32,
+    pub draws: u32,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct UnitStats {
+    pub built: u32,
+    pub kills: u32,
+    pub deaths: u32,
+}
+
+impl WinStats {
+    fn record(&mut self, won: bool) {
+        self.games += 1;
+        if won {
+            self.wins += 1;
+        }
+        self.win_rate = self.wins as f64 / self.games as f64;
+    }
+}
+
+impl Report {
+    /// Adds up the unit kinds, so that units that never got built show up too
+    pub fn new(units: &Units) -> Self {
+        Self {
+            units: units
+                .iter()
+                .map(|descriptor| (descriptor.name.clone(), UnitStats::default()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, record: &MatchRecord, units: &Units) {
+        let game_state = &record.game_state;
+        let games = self.games as f64;
+        self.games += 1;
+        // Keeps a running average, so nothing needs to be kept around between matches
+        let average = |average: f64, value: f64| (average * games + value) / (games + 1.0);
+        self.average_rounds = average(self.average_rounds, record.rounds as f64);
+        let actions = game_state
+            .histroy
+            .iter()
+            .filter(|event| event.acting_player().is_some())
+            .count();
+        self.average_actions = average(self.average_actions, actions as f64);
+
+        let map = self.maps.entry(record.map.clone()).or_default();
+        map.games += 1;
+        if record.winner.is_none() {
+            self.draws += 1;
+            map.draws += 1;
+        } else if record.decided_at_cap {
+            self.decided_at_cap += 1;
+        }
+
+        for (player_id, player) in game_state.players.iter() {
+            let won = record.winner == Some(player.team);
+            self.factions
+                .entry(player.faction.to_string())
+                .or_default()
+                .record(won);
+            if let Some(bot) = record.bots.get(player_id) {
+                self.bots.entry(bot.clone()).or_default().record(won);
+            }
+        }
+
+        for event in game_state.histroy.iter() {
+            if let GameEvent::BuildUnit { unit_kind, .. } = event {
+                self.unit(units, unit_kind.0).built += 1;
+            }
+        }
+        for (attacker, killed) in record.kills.iter() {
+            self.unit(units, *attacker).kills += 1;
+            self.unit(units, *killed).deaths += 1;
+        }
+    }
+
+    fn unit(&mut self, units: &Units, kind: usize) -> &mut UnitStats {
+        self.units.entry(units.0[kind].name.clone()).or_default()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Writes the report as one row per number, which is easy to pull into a spreadsheet
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("category,name,stat,value\n");
+        let mut row = |category: &str, name: &str, stat: &str, value: String| {
+            writeln!(csv, "{},{},{},{}", category, name, stat, value).unwrap();
+        };
+
+        row("match", "all", "games", self.games.to_string());
+        row("match", "all", "draws", self.draws.to_string());
+        row(
+            "match",
+            "all",
+            "decided_at_cap",
+            self.decided_at_cap.to_string(),
+        );
+        row(
+            "match",
+            "all",
+            "average_rounds",
+            self.average_rounds.to_string(),
+        );
+        row(
+            "match",
+            "all",
+            "average_actions",
+            self.average_actions.to_string(),
+        );
+        for (category, stats) in [("faction", &self.factions), ("bot", &self.bots)] {
+            for (name, stats) in stats.iter() {
+                row(category, name, "games", stats.games.to_string());
+                row(category, name, "wins", stats.wins.to_string());
+                row(category, name, "win_rate", stats.win_rate.to_string());
+            }
+        }
+        for (name, stats) in self.maps.iter() {
+            row("map", name, "games", stats.games.to_string());
+            row("map", name, "draws", stats.draws.to_string());
+        }
+        for (name, stats) in self.units.iter() {
+            row("unit", name, "built", stats.built.to_string());
+            row("unit", name, "kills", stats.kills.to_string());
+            row("unit", name, "deaths", stats.deaths.to_string());
+        }
+        csv
+    }
+}
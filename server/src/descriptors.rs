@@ -0,0 +1,53 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use shared::{
+    buildings::{BuildingDescriptor, Buildings},
+    terrain::{Terrain, TerrainDescriptor},
+    units::{UnitDescriptor, Units},
+};
+
+// The same files meta.assets points the game at
+const BUILDINGS_FILE: &str = "buildings/test.buildings.toml";
+const UNITS_FILE: &str = "units/test.units.toml";
+const TERRAIN_FILE: &str = "terrain/test.terrain.toml";
+
+#[derive(Deserialize)]
+struct BuildingFile {
+    building: Vec<BuildingDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct UnitFile {
+    unit: Vec<UnitDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct TerrainFile {
+    terrain: Vec<TerrainDescriptor>,
+}
+
+/// Reads the descriptors straight from an assets folder, for when there is no asset server
+/// around to load them.
+///
+/// Panics if any of them is missing or malformed.
+pub fn load(assets: &Path) -> (Buildings, Units, Terrain) {
+    fn read<T: for<'de> Deserialize<'de>>(path: PathBuf) -> T {
+        let text = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Could not read {}: {}", path.display(), err));
+        toml::from_str(&text)
+            .unwrap_or_else(|err| panic!("Could not parse {}: {}", path.display(), err))
+    }
+
+    let buildings: BuildingFile = read(assets.join(BUILDINGS_FILE));
+    let units: UnitFile = read(assets.join(UNITS_FILE));
+    let terrain: TerrainFile = read(assets.join(TERRAIN_FILE));
+    (
+        Buildings(buildings.building),
+        Units(units.unit),
+        Terrain(terrain.terrain),
+    )
+}
@@ -0,0 +1,1114 @@
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, UdpSocket},
+    time::SystemTime,
+};
+
+use log::{debug, error, info, trace, warn};
+
+use bevy::{
+    prelude::*,
+    reflect::{FromReflect, Reflect},
+    utils::Duration,
+};
+use bevy_renet::{
+    renet::{RenetServer, ServerAuthentication, ServerConfig, ServerEvent, NETCODE_KEY_BYTES},
+    RenetServerPlugin,
+};
+use iyes_loopless::prelude::*;
+use shared::{
+    channels::{server_connection_config, ClientChannel},
+    connection::ConnectInfo,
+    hash,
+    messages::{
+        self, ClientMessage, DecodeError, DisconnectReason, Handshake, LobbyPlayer, RejectReason,
+        ServerMessage,
+    },
+    PlayerId,
+};
+//use renet_visualizer::RenetServerVisualizer;
+
+pub mod descriptors;
+pub mod plugins;
+
+use plugins::{
+    abuse::{AbuseGuard, Verdict},
+    chat::ChatRelay,
+    sessions::{SessionExpired, Sessions, HANDSHAKE_TIMEOUT, RECONNECT_GRACE_PERIOD},
+    spectators::SpectatorFeed,
+};
+
+#[derive(
+    Clone, Copy, Debug, Eq, Hash, PartialEq, Default, Reflect, FromReflect, serde::Deserialize,
+)]
+pub enum AppState {
+    #[default]
+    AssetsLoading,
+    ServerListening,
+}
+
+/// Hosts a match: seats the clients that connect and relays the events they send to everyone,
+/// including those that are only watching.
+///
+/// Loading the descriptors and creating the RenetServer is left to whoever builds the app, so
+/// tests can hand them over directly.
+pub struct ServerPlugin {
+    /// The match begins once this many players have joined
+    pub players: usize,
+    pub settings: shared::MatchSettings,
+    /// How many clients can watch the match at once
+    pub spectators: usize,
+    /// How far behind the players spectators see the match
+    pub spectator_delay: Duration,
+}
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(RenetServerPlugin);
+        app.add_plugin(plugins::sessions::SessionsPlugin);
+        app.add_plugin(plugins::chat::ChatPlugin);
+        app.add_plugin(plugins::abuse::AbusePlugin);
+        app.add_plugin(plugins::spectators::SpectatorsPlugin {
+            delay: self.spectator_delay,
+        });
+        app.insert_resource(MatchConfig {
+            players: self.players,
+            settings: self.settings,
+            spectators: self.spectators,
+        });
+        app.insert_resource(shared::GameState::default());
+        app.init_resource::<PacketStats>();
+
+        app.add_startup_system(debug_server_state);
+        app.add_system(server_update_system.run_in_state(AppState::ServerListening));
+    }
+}
+
+/// How the match hosted by this server is set up
+pub struct MatchConfig {
+    /// The match begins once this many players have joined
+    pub players: usize,
+    pub settings: shared::MatchSettings,
+    /// How many clients can watch the match at once
+    pub spectators: usize,
+}
+
+/// Creates a RenetServer listening on `addr`. Port 0 picks any free port, see RenetServer::addr
+/// for the one it got.
+pub fn new_renet_server(
+    addr: SocketAddr,
+    max_clients: usize,
+    private_key: Option<[u8; NETCODE_KEY_BYTES]>,
+) -> RenetServer {
+    let socket = UdpSocket::bind(addr).unwrap();
+    let server_addr = socket.local_addr().unwrap();
+    let connection_config = server_connection_config();
+    let authentication = match private_key {
+        Some(private_key) => ServerAuthentication::Secure { private_key },
+        None => ServerAuthentication::Unsecure,
+    };
+    let server_config = ServerConfig::new(
+        max_clients,
+        shared::PROTOCOL_ID,
+        server_addr,
+        authentication,
+    );
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    RenetServer::new(current_time, server_config, connection_config, socket).unwrap()
+}
+
+fn debug_server_state(server: Res<RenetServer>) {
+    trace!("Server listening on {}", server.addr());
+    println!("Server listening on {}", server.addr());
+}
+
+/// Counts the packets clients sent that could not be read, the events they sent, and how often
+/// clients fell out of sync with us
+#[derive(Default)]
+pub struct PacketStats {
+    pub malformed: u64,
+    pub malformed_by_client: HashMap<u64, u64>,
+    pub desyncs: u64,
+    /// Events sent by clients and bots that went through
+    pub events_processed: u64,
+    pub events_rejected: HashMap<RejectReason, u64>,
+    /// Everything read from renet clients, in bytes
+    pub bytes_received: u64,
+    /// Everything handed to renet for clients, in bytes
+    pub bytes_sent: u64,
+}
+
+impl PacketStats {
+    /// Counts an event a client or bot sent, by whether it went through
+    pub fn count_event(&mut self, result: Result<(), RejectReason>) {
+        match result {
+            Ok(()) => self.events_processed += 1,
+            Err(reason) => *self.events_rejected.entry(reason).or_default() += 1,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn server_update_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut expired_sessions: EventReader<SessionExpired>,
+    mut server: ResMut<RenetServer>,
+    mut sessions: ResMut<Sessions>,
+    mut game_state: ResMut<shared::GameState>,
+    mut packet_stats: ResMut<PacketStats>,
+    mut spectator_feed: ResMut<SpectatorFeed>,
+    mut chat: ResMut<ChatRelay>,
+    mut guard: ResMut<AbuseGuard>,
+    match_config: Res<MatchConfig>,
+    time: Res<Time>,
+    buildings: Res<shared::buildings::Buildings>,
+    units: Res<shared::units::Units>,
+    terrain: Res<shared::terrain::Terrain>,
+) {
+    for event in server_events.iter() {
+        match event {
+            ServerEvent::ClientConnected(id, user_data) => {
+                if guard.bans.is_client_banned(*id) {
+                    info!("Client {} is banned, dropping it.", id);
+                    kick_client(
+                        *id,
+                        DisconnectReason::Banned,
+                        &mut server,
+                        &mut packet_stats,
+                        &mut sessions,
+                    );
+                    continue;
+                }
+                // Clients only get a seat once they have shown they are running the same version
+                // of the game as we are, see ClientMessage::Hello below.
+                let deadline = time.time_since_startup() + HANDSHAKE_TIMEOUT;
+                sessions.await_handshake(*id, ConnectInfo::from_user_data(user_data), deadline);
+                info!("Client {} connected.", id);
+            }
+            ServerEvent::ClientDisconnected(id) => {
+                info!("Client {} disconnected.", id);
+                packet_stats.malformed_by_client.remove(id);
+                chat.forget(*id);
+                guard.forget(*id);
+
+                if sessions.stop_spectating(*id) {
+                    broadcast_spectators(&mut server, &mut packet_stats, &sessions);
+                    continue;
+                }
+
+                // Hold on to the seat while the game is running, the player might be able to
+                // make it back in with their session token.
+                if game_state.stage == shared::Stage::InGame {
+                    let deadline = time.time_since_startup() + RECONNECT_GRACE_PERIOD;
+                    if let Some(player_id) = sessions.hold(*id, deadline) {
+                        info!(
+                            "Holding seat of player {} for {:?}.",
+                            player_id, RECONNECT_GRACE_PERIOD
+                        );
+                        skip_held_turns(
+                            &mut server,
+                            &mut packet_stats,
+                            &sessions,
+                            &mut game_state,
+                            &buildings,
+                            &units,
+                            &terrain,
+                        );
+                        broadcast_lobby(&mut server, &mut packet_stats, &sessions, &game_state);
+                        continue;
+                    }
+                }
+
+                if let Some(player_id) = sessions.end(*id) {
+                    remove_player(
+                        player_id,
+                        &mut server,
+                        &mut packet_stats,
+                        &sessions,
+                        &mut game_state,
+                        &buildings,
+                        &units,
+                        &terrain,
+                    );
+                    broadcast_lobby(&mut server, &mut packet_stats, &sessions, &game_state);
+                }
+            }
+        }
+    }
+
+    // Players that didnt make it back in time lose their seat
+    for SessionExpired { player_id } in expired_sessions.iter() {
+        info!("Player {} did not reconnect in time.", player_id);
+        remove_player(
+            *player_id,
+            &mut server,
+            &mut packet_stats,
+            &sessions,
+            &mut game_state,
+            &buildings,
+            &units,
+            &terrain,
+        );
+        broadcast_lobby(&mut server, &mut packet_stats, &sessions, &game_state);
+    }
+
+    let server_handshake = Handshake::new(&buildings, &units, &terrain);
+
+    // Receive messages from clients. Broadcast valid events.
+    'clients: for client_id in server.clients_id().into_iter() {
+        for channel in ClientChannel::ALL {
+            while let Some(message) = server.receive_message(client_id, channel) {
+                packet_stats.bytes_received += message.len() as u64;
+                match guard.on_message(client_id, time.time_since_startup()) {
+                    Verdict::Read => {}
+                    Verdict::Drop => continue,
+                    Verdict::Kick(reason) => {
+                        expel_client(
+                            client_id,
+                            reason,
+                            &mut server,
+                            &mut packet_stats,
+                            &mut sessions,
+                            &mut game_state,
+                            &buildings,
+                            &units,
+                            &terrain,
+                        );
+                        continue 'clients;
+                    }
+                }
+
+                let message = match messages::decode::<ClientMessage>(&message) {
+                    Ok(message) => message,
+                    Err(DecodeError::WrongVersion(version)) => {
+                        info!(
+                            "Client {} speaks protocol version {}, dropping it.",
+                            client_id, version
+                        );
+                        let reason = DisconnectReason::IncompatibleClient {
+                            server: server_handshake,
+                            client: None,
+                        };
+                        kick_client(
+                            client_id,
+                            reason,
+                            &mut server,
+                            &mut packet_stats,
+                            &mut sessions,
+                        );
+                        continue 'clients;
+                    }
+                    Err(err) => {
+                        packet_stats.malformed += 1;
+                        let count = packet_stats
+                            .malformed_by_client
+                            .entry(client_id)
+                            .or_default();
+                        *count += 1;
+                        debug!(
+                            "Client {} sent {} ({} malformed so far)",
+                            client_id, err, count
+                        );
+                        if let Some(reason) = guard.on_invalid(client_id) {
+                            expel_client(
+                                client_id,
+                                reason,
+                                &mut server,
+                                &mut packet_stats,
+                                &mut sessions,
+                                &mut game_state,
+                                &buildings,
+                                &units,
+                                &terrain,
+                            );
+                            continue 'clients;
+                        }
+                        continue;
+                    }
+                };
+
+                match message {
+                    ClientMessage::Hello(client_handshake) => {
+                        let connect_info = match sessions.take_pending(client_id) {
+                            Some(connect_info) => connect_info,
+                            None => {
+                                warn!("Client {} said hello twice.", client_id);
+                                continue;
+                            }
+                        };
+
+                        if client_handshake != server_handshake {
+                            info!(
+                                "Client {} is incompatible: {:?}, expected {:?}.",
+                                client_id, client_handshake, server_handshake
+                            );
+                            let reason = DisconnectReason::IncompatibleClient {
+                                server: server_handshake,
+                                client: Some(client_handshake),
+                            };
+                            kick_client(
+                                client_id,
+                                reason,
+                                &mut server,
+                                &mut packet_stats,
+                                &mut sessions,
+                            );
+                            continue 'clients;
+                        }
+
+                        if connect_info.spectator {
+                            watch_match(
+                                client_id,
+                                connect_info,
+                                &match_config,
+                                &mut server,
+                                &mut packet_stats,
+                                &mut sessions,
+                                &game_state,
+                            );
+                            continue;
+                        }
+
+                        seat_client(
+                            client_id,
+                            connect_info,
+                            &match_config,
+                            &mut server,
+                            &mut packet_stats,
+                            &mut sessions,
+                            &mut game_state,
+                            &buildings,
+                            &units,
+                            &terrain,
+                        );
+                    }
+                    ClientMessage::GameEvent(event) => {
+                        // Clients can only act on behalf of the player they are sat as
+                        let applied = match sessions.player_id(client_id) {
+                            Some(player_id) => apply_player_event(
+                                player_id,
+                                event.clone(),
+                                &mut server,
+                                &mut packet_stats,
+                                &sessions,
+                                &mut game_state,
+                                &buildings,
+                                &units,
+                                &terrain,
+                            ),
+                            None => Err(RejectReason::NotSeated),
+                        };
+                        packet_stats.count_event(applied);
+                        match applied {
+                            Ok(()) => guard.on_valid(client_id),
+                            Err(reason) => {
+                                reject(client_id, event, reason, &mut server, &mut packet_stats);
+                                if let Some(reason) = guard.on_invalid(client_id) {
+                                    expel_client(
+                                        client_id,
+                                        reason,
+                                        &mut server,
+                                        &mut packet_stats,
+                                        &mut sessions,
+                                        &mut game_state,
+                                        &buildings,
+                                        &units,
+                                        &terrain,
+                                    );
+                                    continue 'clients;
+                                }
+                            }
+                        }
+                    }
+                    ClientMessage::UndoAction => {
+                        let undone = sessions.player_id(client_id).and_then(|player_id| {
+                            game_state.undo_last(player_id, &buildings, &units, &terrain)
+                        });
+                        match undone {
+                            Some(previous) => {
+                                let index = previous.histroy.len();
+                                info!("Client {} took back event {}.", client_id, index);
+                                *game_state = previous;
+                                let state_hash = hash::state_hash(&game_state);
+                                broadcast(
+                                    &mut server,
+                                    &mut packet_stats,
+                                    &sessions,
+                                    ServerMessage::Undone { index, state_hash },
+                                );
+                                guard.on_valid(client_id);
+                            }
+                            None => {
+                                send(
+                                    &mut server,
+                                    &mut packet_stats,
+                                    client_id,
+                                    ServerMessage::UndoRejected,
+                                );
+                                if let Some(reason) = guard.on_invalid(client_id) {
+                                    expel_client(
+                                        client_id,
+                                        reason,
+                                        &mut server,
+                                        &mut packet_stats,
+                                        &mut sessions,
+                                        &mut game_state,
+                                        &buildings,
+                                        &units,
+                                        &terrain,
+                                    );
+                                    continue 'clients;
+                                }
+                            }
+                        }
+                    }
+                    ClientMessage::Chat { scope, text } => {
+                        chat.chat(
+                            client_id,
+                            scope,
+                            &text,
+                            time.time_since_startup(),
+                            &mut server,
+                            &mut packet_stats,
+                            &sessions,
+                            &game_state,
+                        );
+                    }
+                    ClientMessage::Emote(emote) => {
+                        chat.emote(
+                            client_id,
+                            emote,
+                            time.time_since_startup(),
+                            &mut server,
+                            &mut packet_stats,
+                            &sessions,
+                        );
+                    }
+                    ClientMessage::Cursor(tile) => {
+                        if let Some(player_id) = sessions.player_id(client_id) {
+                            let message = ServerMessage::Cursor { player_id, tile };
+                            for other_id in sessions.seated_clients() {
+                                if other_id != client_id {
+                                    send(&mut server, &mut packet_stats, other_id, message.clone());
+                                }
+                            }
+                        }
+                    }
+                    ClientMessage::Ping(value) => {
+                        send(
+                            &mut server,
+                            &mut packet_stats,
+                            client_id,
+                            ServerMessage::Pong(value),
+                        );
+                    }
+                    ClientMessage::Desync(report) => {
+                        packet_stats.desyncs += 1;
+
+                        // Spectators are behind on purpose, the feed gets them back on track
+                        if sessions.is_spectating(client_id) {
+                            warn!(
+                                "Spectator {} desynced after event {}.",
+                                client_id, report.index
+                            );
+                            spectator_feed.resync(client_id);
+                            continue;
+                        }
+
+                        error!(
+                            "Client {} desynced after event {} ({:?}): expected hash {:#x}, got {:#x}",
+                            client_id,
+                            report.index,
+                            game_state.histroy.get(report.index),
+                            report.expected_hash,
+                            report.actual_hash,
+                        );
+                        debug!(
+                            "Server state:\n{}",
+                            serde_json::to_string_pretty(&*game_state).unwrap()
+                        );
+
+                        // Put the client back on track
+                        send(
+                            &mut server,
+                            &mut packet_stats,
+                            client_id,
+                            ServerMessage::StateSnapshot(Box::new(game_state.clone())),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    server.send_packets().unwrap();
+}
+
+/// Sends a message to a single client over the channel it belongs on
+fn send(
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    client_id: u64,
+    message: ServerMessage,
+) {
+    let payload = messages::encode(&message);
+    packet_stats.bytes_sent += payload.len() as u64;
+    server.send_message(client_id, message.channel(), payload);
+}
+
+/// Sends a message to every player over the channel it belongs on. Spectators are left out,
+/// they get to see what happens from plugins::spectators.
+fn broadcast(
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    message: ServerMessage,
+) {
+    let payload = messages::encode(&message);
+    for client_id in sessions.seated_clients() {
+        packet_stats.bytes_sent += payload.len() as u64;
+        server.send_message(client_id, message.channel(), payload.clone());
+    }
+}
+
+/// Sends a message to every client, players and spectators alike
+fn broadcast_to_everyone(
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    message: ServerMessage,
+) {
+    let payload = messages::encode(&message);
+    let clients = server.clients_id().len() as u64;
+    packet_stats.bytes_sent += payload.len() as u64 * clients;
+    server.broadcast_message(message.channel(), payload);
+}
+
+/// Tells a client that its event did not go through
+fn reject(
+    client_id: u64,
+    event: shared::GameEvent,
+    reason: RejectReason,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+) {
+    send(
+        server,
+        packet_stats,
+        client_id,
+        ServerMessage::Rejected { event, reason },
+    );
+}
+
+/// Consumes an event and tells every client about it.
+/// NOTE: Like GameState::consume this assumes the event has already been validated
+#[allow(clippy::too_many_arguments)]
+fn apply_event(
+    event: shared::GameEvent,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    game_state: &mut shared::GameState,
+    buildings: &shared::buildings::Buildings,
+    units: &shared::units::Units,
+    terrain: &shared::terrain::Terrain,
+) {
+    game_state.consume(&event, buildings, units, terrain);
+    let index = game_state.histroy.len() - 1;
+    let state_hash = hash::state_hash(game_state);
+    broadcast(
+        server,
+        packet_stats,
+        sessions,
+        ServerMessage::GameEvent {
+            index,
+            event,
+            state_hash,
+        },
+    );
+}
+
+/// Checks an event a player sent and applies it if it holds up. Every kind of connection goes
+/// through here, so that all players are held to the same rules.
+#[allow(clippy::too_many_arguments)]
+fn apply_player_event(
+    player_id: PlayerId,
+    event: shared::GameEvent,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    game_state: &mut shared::GameState,
+    buildings: &shared::buildings::Buildings,
+    units: &shared::units::Units,
+    terrain: &shared::terrain::Terrain,
+) -> Result<(), RejectReason> {
+    // Players can only act on their own behalf
+    if event.acting_player() != Some(player_id) {
+        debug!(
+            "Player {} sent event for another player: {:?}",
+            player_id, event
+        );
+        return Err(RejectReason::NotYourPlayer);
+    }
+
+    if !game_state.validate(&event, buildings, units, terrain) {
+        debug!("Player {} sent invalid event: {:?}", player_id, event);
+        return Err(RejectReason::Invalid);
+    }
+
+    trace!("Player {} sent: {:?}", player_id, event);
+    apply_event(
+        event,
+        server,
+        packet_stats,
+        sessions,
+        game_state,
+        buildings,
+        units,
+        terrain,
+    );
+    end_game_if_won(
+        server,
+        packet_stats,
+        sessions,
+        game_state,
+        buildings,
+        units,
+        terrain,
+    );
+    skip_held_turns(
+        server,
+        packet_stats,
+        sessions,
+        game_state,
+        buildings,
+        units,
+        terrain,
+    );
+    Ok(())
+}
+
+/// Ends the game if a team has won it
+fn end_game_if_won(
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    game_state: &mut shared::GameState,
+    buildings: &shared::buildings::Buildings,
+    units: &shared::units::Units,
+    terrain: &shared::terrain::Terrain,
+) {
+    if let Some(team) = game_state.determine_winner() {
+        let event = shared::GameEvent::EndGame {
+            reason: shared::EndGameReason::TeamWon { team },
+        };
+        apply_event(
+            event,
+            server,
+            packet_stats,
+            sessions,
+            game_state,
+            buildings,
+            units,
+            terrain,
+        );
+    }
+}
+
+/// Tells every client who is sat in the game
+fn broadcast_lobby(
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    game_state: &shared::GameState,
+) {
+    broadcast_to_everyone(
+        server,
+        packet_stats,
+        ServerMessage::Lobby(lobby(sessions, game_state)),
+    );
+}
+
+/// Everyone sat in the game, as shown in the lobby
+fn lobby(sessions: &Sessions, game_state: &shared::GameState) -> Vec<LobbyPlayer> {
+    game_state
+        .players
+        .iter()
+        .map(|(player_id, player)| LobbyPlayer {
+            player_id: *player_id,
+            name: player.name.clone(),
+            faction: player.faction,
+            team: player.team,
+            connected: !sessions.is_held(*player_id),
+        })
+        .collect()
+}
+
+/// Tells every client who is watching the game
+fn broadcast_spectators(
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+) {
+    broadcast_to_everyone(
+        server,
+        packet_stats,
+        ServerMessage::Spectators(sessions.spectator_names()),
+    );
+}
+
+/// Lets a client that passed the handshake watch the match, if there is room for it
+fn watch_match(
+    client_id: u64,
+    connect_info: ConnectInfo,
+    match_config: &MatchConfig,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &mut Sessions,
+    game_state: &shared::GameState,
+) {
+    if sessions.spectators().count() >= match_config.spectators {
+        info!("Client {} tried to watch, but there is no room.", client_id);
+        kick_client(
+            client_id,
+            DisconnectReason::SpectatorsFull,
+            server,
+            packet_stats,
+            sessions,
+        );
+        return;
+    }
+
+    info!("Client {} is watching as {}.", client_id, connect_info.name);
+    sessions.spectate(client_id, connect_info.name);
+    send(
+        server,
+        packet_stats,
+        client_id,
+        ServerMessage::Lobby(lobby(sessions, game_state)),
+    );
+    broadcast_spectators(server, packet_stats, sessions);
+}
+
+/// Gives a client that passed the handshake its seat, either a new one or the one it held before
+#[allow(clippy::too_many_arguments)]
+fn seat_client(
+    client_id: u64,
+    connect_info: ConnectInfo,
+    match_config: &MatchConfig,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &mut Sessions,
+    game_state: &mut shared::GameState,
+    buildings: &shared::buildings::Buildings,
+    units: &shared::units::Units,
+    terrain: &shared::terrain::Terrain,
+) {
+    // Bring the recently joined client up to date with everything that has happened so far.
+    // Events that come after it are numbered, so the client can tell which ones it already has.
+    send(
+        server,
+        packet_stats,
+        client_id,
+        ServerMessage::StateSnapshot(Box::new(game_state.clone())),
+    );
+
+    // A player coming back for a seat we held for them just picks up where they left, which
+    // includes the players of a match resumed from a save
+    if let Some(session) = connect_info
+        .session_token
+        .and_then(|token| sessions.resume(client_id, token))
+    {
+        send(
+            server,
+            packet_stats,
+            client_id,
+            ServerMessage::Session(session),
+        );
+        broadcast_lobby(server, packet_stats, sessions, game_state);
+        info!(
+            "Client {} reconnected as player {}.",
+            client_id, session.player_id
+        );
+        return;
+    }
+
+    // New players can only join while the match is still filling up
+    if game_state.stage != shared::Stage::PreGame
+        || game_state.players.len() >= match_config.players
+    {
+        info!("Client {} tried to join a match that is full.", client_id);
+        kick_client(
+            client_id,
+            DisconnectReason::MatchFull,
+            server,
+            packet_stats,
+            sessions,
+        );
+        return;
+    }
+
+    // With unsecure authentication clients pick their own id, which might be one a bot already
+    // plays as
+    if game_state.players.contains_key(&client_id) {
+        info!(
+            "Client {} tried to join as a player that is already playing.",
+            client_id
+        );
+        kick_client(
+            client_id,
+            DisconnectReason::PlayerIdTaken,
+            server,
+            packet_stats,
+            sessions,
+        );
+        return;
+    }
+
+    let session = sessions.start(client_id);
+    send(
+        server,
+        packet_stats,
+        client_id,
+        ServerMessage::Session(session),
+    );
+
+    join_match(
+        session.player_id,
+        connect_info.name,
+        match_config,
+        server,
+        packet_stats,
+        sessions,
+        game_state,
+        buildings,
+        units,
+        terrain,
+    );
+}
+
+/// Adds a new player to the match, settling its rules first if they are the first one in, and
+/// beginning it once everyone is there. Returns whether the player got in, they dont if the
+/// match has begun or someone already plays as `player_id`.
+#[allow(clippy::too_many_arguments)]
+fn join_match(
+    player_id: PlayerId,
+    name: String,
+    match_config: &MatchConfig,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    game_state: &mut shared::GameState,
+    buildings: &shared::buildings::Buildings,
+    units: &shared::units::Units,
+    terrain: &shared::terrain::Terrain,
+) -> bool {
+    let joined = shared::GameEvent::PlayerJoined { player_id, name };
+    if !game_state.validate(&joined, buildings, units, terrain) {
+        return false;
+    }
+
+    // The rules of the match are settled before the first player joins it
+    if game_state.histroy.is_empty() {
+        let event = shared::GameEvent::ConfigureMatch {
+            settings: match_config.settings,
+        };
+        apply_event(
+            event,
+            server,
+            packet_stats,
+            sessions,
+            game_state,
+            buildings,
+            units,
+            terrain,
+        );
+    }
+
+    // Add the new player to the game
+    apply_event(
+        joined,
+        server,
+        packet_stats,
+        sessions,
+        game_state,
+        buildings,
+        units,
+        terrain,
+    );
+    broadcast_lobby(server, packet_stats, sessions, game_state);
+
+    // Game can start once everyone has joined
+    if game_state.players.len() == match_config.players {
+        let event = shared::GameEvent::BeginGame {
+            goes_first: player_id,
+        };
+        apply_event(
+            event,
+            server,
+            packet_stats,
+            sessions,
+            game_state,
+            buildings,
+            units,
+            terrain,
+        );
+        trace!("The game has begun");
+    }
+    true
+}
+
+/// Tells a client why it is being dropped, then drops it
+fn kick_client(
+    client_id: u64,
+    reason: DisconnectReason,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &mut Sessions,
+) {
+    send(
+        server,
+        packet_stats,
+        client_id,
+        ServerMessage::Disconnect(reason),
+    );
+    // Get the reason out the door before the connection goes away
+    server.send_packets().unwrap();
+    server.disconnect(client_id);
+    sessions.end(client_id);
+}
+
+/// Kicks a client that misbehaved. Players lose their seat right away instead of having it held
+/// for them.
+#[allow(clippy::too_many_arguments)]
+fn expel_client(
+    client_id: u64,
+    reason: DisconnectReason,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &mut Sessions,
+    game_state: &mut shared::GameState,
+    buildings: &shared::buildings::Buildings,
+    units: &shared::units::Units,
+    terrain: &shared::terrain::Terrain,
+) {
+    info!("Kicking client {}: {:?}", client_id, reason);
+    let spectating = sessions.is_spectating(client_id);
+    let player_id = sessions.player_id(client_id);
+    kick_client(client_id, reason, server, packet_stats, sessions);
+
+    if spectating {
+        broadcast_spectators(server, packet_stats, sessions);
+    }
+    if let Some(player_id) = player_id {
+        remove_player(
+            player_id,
+            server,
+            packet_stats,
+            sessions,
+            game_state,
+            buildings,
+            units,
+            terrain,
+        );
+        broadcast_lobby(server, packet_stats, sessions, game_state);
+    }
+}
+
+/// Takes a player out of the game for good, ending the game if it can't go on without them
+#[allow(clippy::too_many_arguments)]
+fn remove_player(
+    player_id: PlayerId,
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    game_state: &mut shared::GameState,
+    buildings: &shared::buildings::Buildings,
+    units: &shared::units::Units,
+    terrain: &shared::terrain::Terrain,
+) {
+    let event = shared::GameEvent::PlayerDisconnected { player_id };
+    apply_event(
+        event,
+        server,
+        packet_stats,
+        sessions,
+        game_state,
+        buildings,
+        units,
+        terrain,
+    );
+
+    if game_state.stage != shared::Stage::InGame {
+        return;
+    }
+
+    // Then end the game if there is no other team left to play against
+    let mut teams_left: Vec<shared::TeamId> = game_state
+        .players
+        .values()
+        .filter(|player| !player.eliminated)
+        .map(|player| player.team)
+        .collect();
+    teams_left.sort_unstable();
+    teams_left.dedup();
+    if teams_left.len() < 2 {
+        let event = shared::GameEvent::EndGame {
+            reason: shared::EndGameReason::PlayerLeft { player_id },
+        };
+        apply_event(
+            event,
+            server,
+            packet_stats,
+            sessions,
+            game_state,
+            buildings,
+            units,
+            terrain,
+        );
+        return;
+    }
+
+    skip_held_turns(
+        server,
+        packet_stats,
+        sessions,
+        game_state,
+        buildings,
+        units,
+        terrain,
+    );
+}
+
+/// Ends the turn for players whose seat is being held, since nobody is there to take it
+fn skip_held_turns(
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    game_state: &mut shared::GameState,
+    buildings: &shared::buildings::Buildings,
+    units: &shared::units::Units,
+    terrain: &shared::terrain::Terrain,
+) {
+    // Give up after a full round, in case everyone is away
+    for _ in 0..game_state.turn_order.len() {
+        let player_id = game_state.active_player_id;
+        if game_state.stage != shared::Stage::InGame || !sessions.is_held(player_id) {
+            return;
+        }
+
+        info!("Skipping the turn of disconnected player {}.", player_id);
+        let event = shared::GameEvent::EndTurn { player_id };
+        apply_event(
+            event,
+            server,
+            packet_stats,
+            sessions,
+            game_state,
+            buildings,
+            units,
+            terrain,
+        );
+    }
+}
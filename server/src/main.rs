@@ -1,39 +1,27 @@
-use std::{net::UdpSocket, time::SystemTime};
+use std::path::PathBuf;
 
-use log::{info, trace, warn};
+use log::{warn, LevelFilter};
 
 use bevy::{
     app::ScheduleRunnerSettings,
     asset::AssetPlugin,
     //diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
-    reflect::{FromReflect, Reflect},
     utils::Duration,
 };
-use bevy_renet::{
-    renet::{
-        RenetConnectionConfig, RenetServer, ServerAuthentication, ServerConfig, ServerEvent,
-        NETCODE_USER_DATA_BYTES,
-    },
-    RenetServerPlugin,
-};
 use iyes_loopless::prelude::*;
 use iyes_progress::prelude::*;
-//use renet_visualizer::RenetServerVisualizer;
+use server::{
+    new_renet_server, plugins,
+    plugins::turn_timer::{TimeControl, TimeoutAction},
+    AppState, ServerPlugin,
+};
+use shared::ai::Difficulty;
 
-mod plugins;
-
-#[derive(
-    Clone, Copy, Debug, Eq, Hash, PartialEq, Default, Reflect, FromReflect, serde::Deserialize,
-)]
-pub enum AppState {
-    #[default]
-    AssetsLoading,
-    ServerListening,
-}
+const SERVER_ADDR: &str = "127.0.0.1:5000";
 
 fn main() {
-    env_logger::init();
+    init_logger();
 
     // this app loops forever at 60 fps
     let mut app = App::new();
@@ -48,133 +36,271 @@ fn main() {
     app.add_plugin(ProgressPlugin::new(AppState::AssetsLoading));
 
     // Plugins
-    app.add_plugin(RenetServerPlugin);
+    let args = ServerArgs::from_env();
     app.add_plugin(plugins::asset_loader::AssetLoaderPlugin);
+    app.add_plugin(ServerPlugin {
+        players: args.players,
+        settings: args.settings,
+        spectators: args.spectators,
+        spectator_delay: args.spectator_delay,
+    });
+    app.add_plugin(plugins::save::SavePlugin {
+        save_path: args.save_path,
+        resume_from: args.resume_from,
+    });
+    app.add_plugin(plugins::turn_timer::TurnTimerPlugin {
+        time_control: args.time_control,
+        on_timeout: args.on_timeout,
+    });
+    app.add_plugin(plugins::bots::BotsPlugin {
+        count: args.bots,
+        difficulty: args.bot_difficulty,
+    });
+    if let Some(port) = args.bot_port {
+        app.add_plugin(plugins::bot_protocol::BotProtocolPlugin { port });
+    }
+    app.add_plugin(plugins::admin::AdminPlugin {
+        stdin: true,
+        port: args.admin_port,
+        password: args
+            .admin_port
+            .map(|_| plugins::admin::password_from_env())
+            .unwrap_or_default(),
+    });
+    if args.metrics_port.is_some() || args.metrics_file.is_some() {
+        app.add_plugin(plugins::metrics::MetricsPlugin {
+            port: args.metrics_port,
+            file: args.metrics_file,
+        });
+    }
+    if let Some(path) = args.ban_list {
+        // Without a token service there is nothing that sees where clients connect from
+        if !shared::auth::secure_mode() {
+            panic!(
+                "--ban-list needs secure authentication, set {}=secure",
+                shared::auth::AUTH_ENV
+            );
+        }
+        let bans = plugins::abuse::BanList::load(&path)
+            .unwrap_or_else(|err| panic!("Failed to read ban list {:?}: {}", path, err));
+        app.insert_resource(plugins::abuse::AbuseGuard::new(bans));
+    }
+    if let Some(path) = args.chat_blocklist {
+        let blocklist = std::fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Failed to read chat blocklist {:?}: {}", path, err));
+        let words = blocklist.lines().map(str::to_string).collect();
+        app.insert_resource(plugins::chat::ChatRelay::new(
+            plugins::chat::masking_filter(words),
+        ));
+    }
 
-    app.insert_resource(shared::GameState::default());
-    app.insert_resource(new_renet_server());
+    // Secure mode needs the private key both for the server and for signing connect tokens
+    let private_key = shared::auth::secure_mode().then(shared::auth::private_key_from_env);
+    app.insert_resource(new_renet_server(
+        SERVER_ADDR.parse().unwrap(),
+        args.players + args.spectators,
+        private_key,
+    ));
+    if let Some(private_key) = private_key {
+        app.add_plugin(plugins::token_service::TokenServicePlugin {
+            private_key,
+            password: shared::auth::server_password_from_env(),
+        });
+    }
     //app.insert_resource(RenetServerVisualizer::<200>::default());
 
-    app.add_startup_system(debug_server_state);
-    app.add_system(server_update_system);
-
     app.run();
 }
 
-fn new_renet_server() -> RenetServer {
-    let server_addr = "127.0.0.1:5000".parse().unwrap();
-    let socket = UdpSocket::bind(server_addr).unwrap();
-    let connection_config = RenetConnectionConfig::default();
-    let server_config = ServerConfig::new(
-        2,
-        shared::PROTOCOL_ID,
-        server_addr,
-        ServerAuthentication::Unsecure,
-    );
-    let current_time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap();
-    RenetServer::new(current_time, server_config, connection_config, socket).unwrap()
+/// Logs like env_logger::init does, but leaves room for the admin console to turn the level up
+/// later on
+fn init_logger() {
+    let level = env_logger::Builder::from_default_env().build().filter();
+    let logger = env_logger::Builder::from_default_env()
+        .filter_level(LevelFilter::Trace)
+        .build();
+    log::set_boxed_logger(Box::new(logger)).unwrap();
+    log::set_max_level(level);
 }
 
-fn debug_server_state(server: Res<RenetServer>) {
-    trace!("Server listening on {}", server.addr());
-    println!("Server listening on {}", server.addr());
+const MIN_PLAYERS: usize = 2;
+const MAX_PLAYERS: usize = 4;
+const DEFAULT_SPECTATORS: usize = 4;
+
+/// Command line options of the server
+struct ServerArgs {
+    /// Where the match is saved to as it goes on
+    save_path: PathBuf,
+    /// A saved match to pick back up
+    resume_from: Option<PathBuf>,
+    /// How many players a match needs before it begins
+    players: usize,
+    settings: shared::MatchSettings,
+    time_control: TimeControl,
+    on_timeout: TimeoutAction,
+    /// How many of the players are bots run by the server
+    bots: usize,
+    bot_difficulty: Difficulty,
+    /// Local port bots written in other languages can connect to
+    bot_port: Option<u16>,
+    /// How many clients can watch the match, and how far behind the players they are
+    spectators: usize,
+    spectator_delay: Duration,
+    /// A file of words to hide in chat, one per line
+    chat_blocklist: Option<PathBuf>,
+    /// A file of addresses that are not let in, one per line
+    ban_list: Option<PathBuf>,
+    /// Local port the admin console can be reached on
+    admin_port: Option<u16>,
+    /// Local port the metrics are served on
+    metrics_port: Option<u16>,
+    /// A file the metrics are written to every so often
+    metrics_file: Option<PathBuf>,
 }
 
-fn server_update_system(
-    mut server_events: EventReader<ServerEvent>,
-    mut server: ResMut<RenetServer>,
-    mut game_state: ResMut<shared::GameState>,
-    buildings: Res<shared::buildings::Buildings>,
-    units: Res<shared::units::Units>,
-    terrain: Res<shared::terrain::Terrain>,
-) {
-    for event in server_events.iter() {
-        match event {
-            ServerEvent::ClientConnected(id, user_data) => {
-                // Tell the recently joined player about the other player
-                for (player_id, player) in game_state.players.iter() {
-                    let event = shared::GameEvent::PlayerJoined {
-                        player_id: *player_id,
-                        name: player.name.clone(),
+impl ServerArgs {
+    /// Reads `--resume <file>`, `--save <file>`, `--players <count>`, `--free-for-all`,
+    /// `--shared-economy`, `--turn-time <seconds>`, `--clock <seconds>+<increment>`,
+    /// `--forfeit-on-timeout`, `--bots <count>`, `--bot-difficulty <easy|normal|hard>`,
+    /// `--bot-port <port>`, `--spectators <count>`, `--spectator-delay <seconds>`,
+    /// `--chat-blocklist <file>`, `--ban-list <file>`, `--admin-port <port>`,
+    /// `--metrics-port <port>` and `--metrics-file <file>`.
+    /// Resumed matches keep saving to the file they were resumed from unless told otherwise.
+    fn from_env() -> Self {
+        let mut save_path = None;
+        let mut resume_from = None;
+        let mut players = MIN_PLAYERS;
+        let mut settings = shared::MatchSettings::default();
+        let mut time_control = TimeControl::Unlimited;
+        let mut on_timeout = TimeoutAction::EndTurn;
+        let mut bots = 0;
+        let mut bot_difficulty = Difficulty::default();
+        let mut bot_port = None;
+        let mut spectators = DEFAULT_SPECTATORS;
+        let mut spectator_delay = Duration::ZERO;
+        let mut chat_blocklist = None;
+        let mut ban_list = None;
+        let mut admin_port = None;
+        let mut metrics_port = None;
+        let mut metrics_file = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--save" => save_path = args.next().map(PathBuf::from),
+                "--resume" => resume_from = args.next().map(PathBuf::from),
+                "--players" => {
+                    players = args
+                        .next()
+                        .and_then(|count| count.parse().ok())
+                        .filter(|count| (MIN_PLAYERS..=MAX_PLAYERS).contains(count))
+                        .unwrap_or_else(|| {
+                            panic!(
+                                "--players needs a count from {} to {}",
+                                MIN_PLAYERS, MAX_PLAYERS
+                            )
+                        })
+                }
+                "--free-for-all" => settings.teams = shared::TeamMode::FreeForAll,
+                "--shared-economy" => settings.economy = shared::Economy::Shared,
+                "--turn-time" => {
+                    let limit = args.next().and_then(|limit| limit.parse().ok());
+                    time_control = TimeControl::PerTurn {
+                        limit: Duration::from_secs(
+                            limit.expect("--turn-time needs a number of seconds"),
+                        ),
                     };
-                    server.send_message(*id, 0, bincode::serialize(&event).unwrap());
                 }
-
-                // Add the new player to the game
-                let event = shared::GameEvent::PlayerJoined {
-                    player_id: *id,
-                    name: name_from_user_data(&user_data),
-                };
-
-                game_state.consume(&event, &buildings, &units, &terrain);
-
-                //Tell all playes that a new player has joined
-                server.broadcast_message(0, bincode::serialize(&event).unwrap());
-
-                info!("Client {} connected.", id);
-
-                // Game can start once two players have joined
-                if game_state.players.len() == 2 {
-                    let event = shared::GameEvent::BeginGame { goes_first: *id };
-                    game_state.consume(&event, &buildings, &units, &terrain);
-                    server.broadcast_message(0, bincode::serialize(&event).unwrap());
-                    trace!("The game has begun");
+                "--clock" => {
+                    let clock = args.next().and_then(|clock| {
+                        let (initial, increment) =
+                            clock.split_once('+').unwrap_or((clock.as_str(), "0"));
+                        Some((initial.parse().ok()?, increment.parse().ok()?))
+                    });
+                    let (initial, increment) =
+                        clock.expect("--clock needs seconds and an increment, like 300+5");
+                    time_control = TimeControl::ChessClock {
+                        initial: Duration::from_secs(initial),
+                        increment: Duration::from_secs(increment),
+                    };
                 }
-            }
-            ServerEvent::ClientDisconnected(id) => {
-                let event = shared::GameEvent::PlayerDisconnected { player_id: *id };
-                game_state.consume(&event, &buildings, &units, &terrain);
-                server.broadcast_message(0, bincode::serialize(&event).unwrap());
-                info!("Client {} disconnected.", id);
-
-                // Then end the game, since game can't go on with a single player
-                let event = shared::GameEvent::EndGame {
-                    reason: shared::EndGameReason::PlayerLeft { player_id: *id },
-                };
-                game_state.consume(&event, &buildings, &units, &terrain);
-
-                server.broadcast_message(0, bincode::serialize(&event).unwrap());
-
-                // NOTE: Since we dont authenticate users we cant do any reconnection attempts.
-                // We simply have no way to know if the next user is the same as the one that
-                // disconnected.
-            }
-        }
-    }
-
-    // Receive GameEvents from clients. Broadcast valid events.
-    for client_id in server.clients_id().into_iter() {
-        while let Some(message) = server.receive_message(client_id, 0) {
-            if let Ok(event) = bincode::deserialize::<shared::GameEvent>(&message) {
-                if game_state.validate(&event, &buildings, &units, &terrain) {
-                    game_state.consume(&event, &buildings, &units, &terrain);
-                    trace!("Player {} sent:\n\t{:#?}", client_id, event);
-                    server.broadcast_message(0, bincode::serialize(&event).unwrap());
-
-                    // Determine if a player has won the game
-                    if let Some(winner) = game_state.determine_winner() {
-                        let event = shared::GameEvent::EndGame {
-                            reason: shared::EndGameReason::PlayerWon { winner },
-                        };
-                        server.broadcast_message(0, bincode::serialize(&event).unwrap());
-                    }
-                } else {
-                    warn!("Player {} sent invalid event:\n\t{:#?}", client_id, event);
+                "--forfeit-on-timeout" => on_timeout = TimeoutAction::Forfeit,
+                "--bots" => {
+                    bots = args
+                        .next()
+                        .and_then(|count| count.parse().ok())
+                        .expect("--bots needs a count")
+                }
+                "--bot-difficulty" => {
+                    bot_difficulty = args
+                        .next()
+                        .expect("--bot-difficulty needs easy, normal or hard")
+                        .parse()
+                        .unwrap_or_else(|err| panic!("--bot-difficulty: {}", err))
+                }
+                "--bot-port" => {
+                    bot_port = Some(
+                        args.next()
+                            .and_then(|port| port.parse().ok())
+                            .expect("--bot-port needs a port"),
+                    )
                 }
+                "--spectators" => {
+                    spectators = args
+                        .next()
+                        .and_then(|count| count.parse().ok())
+                        .expect("--spectators needs a count")
+                }
+                "--spectator-delay" => {
+                    let delay = args.next().and_then(|delay| delay.parse().ok());
+                    spectator_delay = Duration::from_secs(
+                        delay.expect("--spectator-delay needs a number of seconds"),
+                    );
+                }
+                "--chat-blocklist" => chat_blocklist = args.next().map(PathBuf::from),
+                "--ban-list" => ban_list = args.next().map(PathBuf::from),
+                "--admin-port" => {
+                    admin_port = Some(
+                        args.next()
+                            .and_then(|port| port.parse().ok())
+                            .expect("--admin-port needs a port"),
+                    )
+                }
+                "--metrics-port" => {
+                    metrics_port = Some(
+                        args.next()
+                            .and_then(|port| port.parse().ok())
+                            .expect("--metrics-port needs a port"),
+                    )
+                }
+                "--metrics-file" => metrics_file = args.next().map(PathBuf::from),
+                _ => warn!("Ignoring unknown argument {}", arg),
             }
         }
-    }
 
-    server.send_packets().unwrap();
-}
+        if bots > players {
+            panic!("A match of {} players can't have {} bots", players, bots);
+        }
 
-fn name_from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> String {
-    let mut buffer = [0u8; 8];
-    buffer.copy_from_slice(&user_data[0..8]);
-    let mut len = u64::from_le_bytes(buffer) as usize;
-    len = len.min(NETCODE_USER_DATA_BYTES - 8);
-    let data = user_data[8..len + 8].to_vec();
-    String::from_utf8(data).unwrap()
+        Self {
+            save_path: save_path
+                .or_else(|| resume_from.clone())
+                .unwrap_or_else(|| PathBuf::from(plugins::save::DEFAULT_SAVE_PATH)),
+            resume_from,
+            players,
+            settings,
+            time_control,
+            on_timeout,
+            bots,
+            bot_difficulty,
+            bot_port,
+            spectators,
+            spectator_delay,
+            chat_blocklist,
+            ban_list,
+            admin_port,
+            metrics_port,
+            metrics_file,
+        }
+    }
 }
@@ -0,0 +1,293 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use bevy::{prelude::*, utils::Duration};
+use log::{info, warn};
+use shared::messages::DisconnectReason;
+
+/// How many messages a client can send per second, and how many it can save up for a burst
+const MESSAGES_PER_SECOND: f32 = 30.0;
+const MESSAGE_BURST: f32 = 60.0;
+
+/// How many messages over the rate limit a client can send before it is kicked. Only counts up
+/// while the client doesnt give the server a break.
+const MAX_DROPPED_MESSAGES: u32 = 200;
+
+/// How many invalid actions or unreadable messages in a row get a client kicked
+const MAX_INVALID_IN_A_ROW: u32 = 20;
+
+/// Keeps misbehaving clients from flooding the server, and banned ones out of it
+pub struct AbusePlugin;
+
+impl Plugin for AbusePlugin {
+    fn build(&self, app: &mut App) {
+        // Whoever builds the app can put in an AbuseGuard with a ban list of its own
+        app.init_resource::<AbuseGuard>();
+    }
+}
+
+/// What the server should do with a message a client sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Read,
+    /// The client is over its rate limit, throw the message away unread
+    Drop,
+    Kick(DisconnectReason),
+}
+
+/// Watches how every client behaves
+#[derive(Default)]
+pub struct AbuseGuard {
+    pub bans: BanList,
+    clients: HashMap<u64, ClientRecord>,
+}
+
+/// How a single client has been behaving
+struct ClientRecord {
+    /// Messages the client can still send right away
+    allowance: f32,
+    last_message: Duration,
+    /// Messages thrown away since the client last stayed under its rate limit
+    dropped: u32,
+    invalid_in_a_row: u32,
+}
+
+impl AbuseGuard {
+    pub fn new(bans: BanList) -> Self {
+        Self {
+            bans,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Decides what to do with a message a client just sent
+    pub fn on_message(&mut self, client_id: u64, now: Duration) -> Verdict {
+        let record = self.clients.entry(client_id).or_insert(ClientRecord {
+            allowance: MESSAGE_BURST,
+            last_message: now,
+            dropped: 0,
+            invalid_in_a_row: 0,
+        });
+
+        let earned = (now - record.last_message).as_secs_f32() * MESSAGES_PER_SECOND;
+        record.allowance = (record.allowance + earned).min(MESSAGE_BURST);
+        record.last_message = now;
+        if record.allowance >= MESSAGE_BURST {
+            record.dropped = 0;
+        }
+
+        if record.allowance >= 1.0 {
+            record.allowance -= 1.0;
+            return Verdict::Read;
+        }
+
+        record.dropped += 1;
+        if record.dropped == 1 {
+            info!(
+                "Client {} is over its rate limit, dropping messages.",
+                client_id
+            );
+        }
+        if record.dropped > MAX_DROPPED_MESSAGES {
+            warn!("Client {} kept flooding the server.", client_id);
+            return Verdict::Kick(DisconnectReason::Flooding);
+        }
+        Verdict::Drop
+    }
+
+    /// Notes down that a client sent an action that was rejected, or a message that couldnt be
+    /// read. Returns the reason to kick it once that happened too often in a row.
+    pub fn on_invalid(&mut self, client_id: u64) -> Option<DisconnectReason> {
+        let record = self.clients.get_mut(&client_id)?;
+        record.invalid_in_a_row += 1;
+        if record.invalid_in_a_row < MAX_INVALID_IN_A_ROW {
+            return None;
+        }
+        warn!(
+            "Client {} sent {} invalid messages in a row.",
+            client_id, record.invalid_in_a_row
+        );
+        Some(DisconnectReason::TooManyInvalidActions)
+    }
+
+    /// Notes down that an action of a client went through
+    pub fn on_valid(&mut self, client_id: u64) {
+        if let Some(record) = self.clients.get_mut(&client_id) {
+            record.invalid_in_a_row = 0;
+        }
+    }
+
+    /// Forgets about a client that left
+    pub fn forget(&mut self, client_id: u64) {
+        self.clients.remove(&client_id);
+        self.bans.forget_client(client_id);
+    }
+}
+
+/// Addresses that are not let into the server.
+///
+/// Renet doesnt tell us where clients connect from, so bans are checked when the token service
+/// hands out connect tokens. That means bans only work with secure authentication, the server
+/// refuses a ban list without it. The token service notes down where each client id went to, so
+/// clients with a token from before a ban can still be told apart.
+#[derive(Clone, Default)]
+pub struct BanList(Arc<RwLock<Bans>>);
+
+#[derive(Default)]
+struct Bans {
+    addresses: HashSet<IpAddr>,
+    /// Where the clients that got a connect token asked for it from
+    clients: HashMap<u64, IpAddr>,
+}
+
+impl BanList {
+    /// Reads a ban list with one address per line. Empty lines and lines starting with # are
+    /// skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bans = BanList::default();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let addr = line.parse().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {:?} is not an address: {}", number + 1, line, err),
+                )
+            })?;
+            bans.ban(addr);
+        }
+        Ok(bans)
+    }
+
+    pub fn ban(&self, addr: IpAddr) {
+        self.0.write().unwrap().addresses.insert(addr);
+    }
+
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.0.read().unwrap().addresses.contains(&addr)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.read().unwrap().addresses.is_empty()
+    }
+
+    /// Notes down that `client_id` was handed to a client at `addr`
+    pub fn note_client(&self, client_id: u64, addr: IpAddr) {
+        self.0.write().unwrap().clients.insert(client_id, addr);
+    }
+
+    /// Whether a client got its connect token from a banned address
+    pub fn is_client_banned(&self, client_id: u64) -> bool {
+        let bans = self.0.read().unwrap();
+        bans.clients
+            .get(&client_id)
+            .is_some_and(|addr| bans.addresses.contains(addr))
+    }
+
+    fn forget_client(&self, client_id: u64) {
+        self.0.write().unwrap().clients.remove(&client_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_over_their_rate_limit_are_dropped_then_kicked() {
+        let mut guard = AbuseGuard::default();
+        let now = Duration::from_secs(1);
+
+        // A burst all at once is fine, as long as it fits in the allowance
+        for _ in 0..MESSAGE_BURST as u32 {
+            assert_eq!(guard.on_message(1, now), Verdict::Read);
+        }
+        for _ in 0..MAX_DROPPED_MESSAGES {
+            assert_eq!(guard.on_message(1, now), Verdict::Drop);
+        }
+        assert_eq!(
+            guard.on_message(1, now),
+            Verdict::Kick(DisconnectReason::Flooding)
+        );
+
+        // Other clients have allowances of their own
+        assert_eq!(guard.on_message(2, now), Verdict::Read);
+    }
+
+    #[test]
+    fn clients_that_slow_down_are_read_again() {
+        let mut guard = AbuseGuard::default();
+        let mut now = Duration::from_secs(1);
+        for _ in 0..MESSAGE_BURST as u32 + 10 {
+            guard.on_message(1, now);
+        }
+        assert_eq!(guard.on_message(1, now), Verdict::Drop);
+
+        // A break long enough to earn the whole allowance back forgets about what was dropped
+        now += Duration::from_secs_f32(MESSAGE_BURST / MESSAGES_PER_SECOND);
+        assert_eq!(guard.on_message(1, now), Verdict::Read);
+        assert_eq!(guard.clients[&1].dropped, 0);
+    }
+
+    #[test]
+    fn only_invalid_actions_in_a_row_get_a_client_kicked() {
+        let mut guard = AbuseGuard::default();
+        // Clients that never sent anything arent being watched yet
+        assert_eq!(guard.on_invalid(1), None);
+
+        guard.on_message(1, Duration::ZERO);
+        for _ in 1..MAX_INVALID_IN_A_ROW {
+            assert_eq!(guard.on_invalid(1), None);
+        }
+        guard.on_valid(1);
+        for _ in 1..MAX_INVALID_IN_A_ROW {
+            assert_eq!(guard.on_invalid(1), None);
+        }
+        assert_eq!(
+            guard.on_invalid(1),
+            Some(DisconnectReason::TooManyInvalidActions)
+        );
+    }
+
+    #[test]
+    fn ban_lists_skip_comments_and_know_where_clients_came_from() {
+        let path = std::env::temp_dir().join(format!("dinojam2-bans-{}", std::process::id()));
+        fs::write(&path, "# troublemakers\n\n10.0.0.1\n  ::1  \n").unwrap();
+        let bans = BanList::load(&path);
+        fs::remove_file(&path).unwrap();
+        let bans = bans.unwrap();
+
+        assert!(bans.is_banned("10.0.0.1".parse().unwrap()));
+        assert!(bans.is_banned("::1".parse().unwrap()));
+        assert!(!bans.is_banned("10.0.0.2".parse().unwrap()));
+
+        bans.note_client(1, "10.0.0.1".parse().unwrap());
+        bans.note_client(2, "10.0.0.2".parse().unwrap());
+        assert!(bans.is_client_banned(1));
+        assert!(!bans.is_client_banned(2));
+        assert!(!bans.is_client_banned(3));
+
+        let mut guard = AbuseGuard::new(bans.clone());
+        guard.forget(1);
+        assert!(!bans.is_client_banned(1));
+    }
+
+    #[test]
+    fn ban_lists_with_something_other_than_addresses_are_refused() {
+        let path = std::env::temp_dir().join(format!("dinojam2-bad-bans-{}", std::process::id()));
+        fs::write(&path, "10.0.0.1\nnot an address\n").unwrap();
+        let bans = BanList::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let err = bans.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"), "{}", err);
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+};
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_renet::renet::RenetServer;
+use iyes_loopless::prelude::*;
+use log::{info, warn, LevelFilter};
+use shared::{
+    buildings::Buildings, messages::DisconnectReason, terrain::Terrain, units::Units,
+    EndGameReason, GameEvent, GameState, Stage,
+};
+
+use crate::plugins::{save::SaveSettings, sessions::Sessions};
+use crate::{AppState, PacketStats};
+
+/// Environment variable holding the password of the admin port
+pub const ADMIN_PASSWORD_ENV: &str = "DINOJAM2_ADMIN_PASSWORD";
+
+const HELP: &str = "\
+Commands:
+  clients          list the connected clients
+  matches          show the match being hosted
+  kick <client>    kick a client, players lose their seat
+  end              end the game right away
+  state            print the GameState as JSON
+  log <level>      log at off, error, warn, info, debug or trace from now on
+  shutdown         save the match and stop the server
+  help             show this
+  quit             close the connection to the admin port";
+
+/// Lets whoever runs the server look into it and take control, by typing commands into its stdin
+/// or over a local tcp port
+pub struct AdminPlugin {
+    /// Read commands from stdin
+    pub stdin: bool,
+    /// Also take commands on this port of localhost, from those that know the password
+    pub port: Option<u16>,
+    pub password: String,
+}
+
+/// Reads the password of the admin port from ADMIN_PASSWORD_ENV
+pub fn password_from_env() -> String {
+    std::env::var(ADMIN_PASSWORD_ENV)
+        .ok()
+        .filter(|password| !password.is_empty())
+        .unwrap_or_else(|| panic!("{} must be set to use the admin port", ADMIN_PASSWORD_ENV))
+}
+
+/// A line typed in by an admin, and where to send the answer
+struct AdminCommand {
+    line: String,
+    reply: Sender<String>,
+}
+
+/// Commands waiting to be run, from every console
+struct AdminConsole(Mutex<Receiver<AdminCommand>>);
+
+impl Plugin for AdminPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = mpsc::channel();
+
+        if self.stdin {
+            let sender = sender.clone();
+            thread::spawn(move || read_stdin(sender));
+        }
+        if let Some(port) = self.port {
+            // Only reachable from the same machine
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            let listener = TcpListener::bind(addr)
+                .unwrap_or_else(|err| panic!("Could not listen for admins on {}: {}", addr, err));
+            info!("Admin console listening on {}", addr);
+            let password = self.password.clone();
+            thread::spawn(move || serve_admins(listener, password, sender));
+        }
+
+        app.insert_resource(AdminConsole(Mutex::new(receiver)));
+        app.add_system(run_admin_commands.run_in_state(AppState::ServerListening));
+    }
+}
+
+fn read_stdin(commands: Sender<AdminCommand>) {
+    let (reply, replies) = mpsc::channel();
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Could not read admin command from stdin: {}", err);
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let command = AdminCommand {
+            line,
+            reply: reply.clone(),
+        };
+        if commands.send(command).is_err() {
+            return;
+        }
+        match replies.recv() {
+            Ok(answer) => println!("{}", answer),
+            Err(_) => return,
+        }
+    }
+}
+
+fn serve_admins(listener: TcpListener, password: String, commands: Sender<AdminCommand>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let password = password.clone();
+                let commands = commands.clone();
+                thread::spawn(move || {
+                    let addr = stream.peer_addr();
+                    if let Err(err) = serve_admin(stream, &password, commands) {
+                        warn!("Admin connection {:?} failed: {}", addr, err);
+                    }
+                });
+            }
+            Err(err) => warn!("Could not accept admin connection: {}", err),
+        }
+    }
+}
+
+/// Asks for the password, then passes on commands until the admin quits
+fn serve_admin(
+    mut stream: TcpStream,
+    password: &str,
+    commands: Sender<AdminCommand>,
+) -> io::Result<()> {
+    let addr = stream.peer_addr()?;
+    let mut lines = BufReader::new(stream.try_clone()?).lines();
+
+    stream.write_all(b"password: ")?;
+    let given = lines.next().transpose()?.unwrap_or_default();
+    if given.trim() != password {
+        warn!("Wrong admin password from {}", addr);
+        stream.write_all(b"wrong password\n")?;
+        return Ok(());
+    }
+    info!("Admin connected from {}", addr);
+    stream.write_all(b"> ")?;
+
+    let (reply, replies) = mpsc::channel();
+    for line in lines {
+        let line = line?;
+        match line.trim() {
+            "" => {}
+            "quit" => break,
+            _ => {
+                let command = AdminCommand {
+                    line,
+                    reply: reply.clone(),
+                };
+                if commands.send(command).is_err() {
+                    break;
+                }
+                match replies.recv() {
+                    Ok(answer) => writeln!(stream, "{}", answer)?,
+                    Err(_) => break,
+                }
+            }
+        }
+        stream.write_all(b"> ")?;
+    }
+    info!("Admin at {} left", addr);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_admin_commands(
+    console: Res<AdminConsole>,
+    mut server: ResMut<RenetServer>,
+    mut packet_stats: ResMut<PacketStats>,
+    mut sessions: ResMut<Sessions>,
+    mut game_state: ResMut<GameState>,
+    mut save_settings: Option<ResMut<SaveSettings>>,
+    mut app_exit: EventWriter<AppExit>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    let commands = console.0.lock().unwrap();
+    for AdminCommand { line, reply } in commands.try_iter() {
+        info!("Admin command: {}", line);
+        let mut words = line.split_whitespace();
+        let answer = match (words.next().unwrap_or_default(), words.next()) {
+            ("clients", None) => list_clients(&server, &sessions, &game_state),
+            ("matches", None) => describe_match(&sessions, &game_state),
+            ("kick", Some(client_id)) => match client_id.parse() {
+                Ok(client_id) if server.clients_id().contains(&client_id) => {
+                    crate::expel_client(
+                        client_id,
+                        DisconnectReason::Kicked,
+                        &mut server,
+                        &mut packet_stats,
+                        &mut sessions,
+                        &mut game_state,
+                        &buildings,
+                        &units,
+                        &terrain,
+                    );
+                    format!("Kicked client {}", client_id)
+                }
+                _ => format!("There is no client {}", client_id),
+            },
+            ("end", None) => {
+                if game_state.stage == Stage::Ended {
+                    "The game is already over".to_string()
+                } else {
+                    let event = GameEvent::EndGame {
+                        reason: EndGameReason::Aborted,
+                    };
+                    crate::apply_event(
+                        event,
+                        &mut server,
+                        &mut packet_stats,
+                        &sessions,
+                        &mut game_state,
+                        &buildings,
+                        &units,
+                        &terrain,
+                    );
+                    "Ended the game".to_string()
+                }
+            }
+            ("state", None) => serde_json::to_string_pretty(&*game_state).unwrap(),
+            ("log", Some(level)) => match level.parse::<LevelFilter>() {
+                Ok(level) => {
+                    log::set_max_level(level);
+                    format!("Logging at {} from now on", level)
+                }
+                Err(_) => format!("Unknown log level {}", level),
+            },
+            ("shutdown", None) => {
+                let saved = match save_settings.as_mut() {
+                    Some(settings) => {
+                        match settings.save(&game_state, &sessions, &buildings, &units, &terrain) {
+                            Ok(()) => format!("Saved the match to {}. ", settings.path().display()),
+                            Err(err) => format!("Could not save the match: {}. ", err),
+                        }
+                    }
+                    None => String::new(),
+                };
+                for client_id in server.clients_id() {
+                    crate::kick_client(
+                        client_id,
+                        DisconnectReason::ShuttingDown,
+                        &mut server,
+                        &mut packet_stats,
+                        &mut sessions,
+                    );
+                }
+                app_exit.send(AppExit);
+                format!("{}Shutting down", saved)
+            }
+            ("help", _) => HELP.to_string(),
+            _ => format!("Unknown command {:?}, try help", line),
+        };
+        // The admin might have left in the meantime
+        let _ = reply.send(answer);
+    }
+}
+
+/// One line for every connected client, with what it is doing
+fn list_clients(server: &RenetServer, sessions: &Sessions, game_state: &GameState) -> String {
+    let mut list = String::new();
+    for client_id in server.clients_id() {
+        let role = if let Some(player_id) = sessions.player_id(client_id) {
+            let name = game_state
+                .players
+                .get(&player_id)
+                .map_or("?", |player| player.name.as_str());
+            format!("player {} {}", player_id, name)
+        } else if let Some(name) = sessions.spectator_name(client_id) {
+            format!("spectator {}", name)
+        } else if sessions.is_pending(client_id) {
+            "connecting".to_string()
+        } else {
+            "without a seat".to_string()
+        };
+        let rtt = server
+            .network_info(client_id)
+            .map_or(0.0, |network_info| network_info.rtt);
+        writeln!(list, "{}  {}  rtt {:.0}ms", client_id, role, rtt).unwrap();
+    }
+    if list.is_empty() {
+        return "No clients connected".to_string();
+    }
+    list.trim_end().to_string()
+}
+
+/// The server only ever hosts a single match, this sums it up
+fn describe_match(sessions: &Sessions, game_state: &GameState) -> String {
+    let mut description = format!(
+        "Match: {:?}, round {}, {} events, {} spectators",
+        game_state.stage,
+        game_state.round,
+        game_state.histroy.len(),
+        sessions.spectators().count()
+    );
+    for (player_id, player) in game_state.players.iter() {
+        let mut notes = Vec::new();
+        if *player_id == game_state.active_player_id && game_state.stage == Stage::InGame {
+            notes.push("their turn");
+        }
+        if sessions.is_held(*player_id) {
+            notes.push("away");
+        }
+        if player.eliminated {
+            notes.push("eliminated");
+        }
+        write!(
+            description,
+            "\n  player {} {}  team {}  {}  {} gold",
+            player_id, player.name, player.team, player.faction, player.gold
+        )
+        .unwrap();
+        if !notes.is_empty() {
+            write!(description, "  ({})", notes.join(", ")).unwrap();
+        }
+    }
+    description
+}
@@ -3,7 +3,9 @@ use bevy_asset_loader::prelude::*;
 use bevy_common_assets::toml::TomlAssetPlugin;
 
 use crate::AppState;
-use shared::asset_management::{terrain_descriptors::*, unit_descriptors::*};
+use shared::asset_management::{
+    building_descriptors::*, terrain_descriptors::*, unit_descriptors::*,
+};
 
 pub struct AssetLoaderPlugin;
 
@@ -17,9 +19,11 @@ impl Plugin for AssetLoaderPlugin {
                     "meta.assets",
                 ])
                 .with_collection::<UnitAssets>()
-                .with_collection::<TerrainAssets>(),
+                .with_collection::<TerrainAssets>()
+                .with_collection::<BuildingAssets>(),
         );
         app.add_plugin(TomlAssetPlugin::<UnitAsset>::new(&["units.toml"]));
         app.add_plugin(TomlAssetPlugin::<TerrainAsset>::new(&["terrain.toml"]));
+        app.add_plugin(TomlAssetPlugin::<BuildingAsset>::new(&["buildings.toml"]));
     }
 }
@@ -0,0 +1,430 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use iyes_loopless::prelude::*;
+use log::{info, warn};
+use shared::{
+    bot_protocol::{
+        decode_line, encode_line, BotClientMessage, BotServerMessage, BOT_PROTOCOL_VERSION,
+        MAX_LINE_LENGTH,
+    },
+    buildings::Buildings,
+    messages::{DisconnectReason, RejectReason},
+    terrain::Terrain,
+    units::Units,
+    GameEvent, GameState, PlayerId, Stage,
+};
+
+use crate::plugins::sessions::Sessions;
+use crate::{AppState, MatchConfig, PacketStats};
+
+// Ids of bots that connect over the protocol, well clear of renet clients and the server's own bots
+const FIRST_BOT_PLAYER_ID: PlayerId = 1 << 62;
+
+// The most that is read from a bot in one frame, the rest waits for the next one
+const MAX_READ_PER_FRAME: usize = 4 * MAX_LINE_LENGTH;
+// Bots that leave this much unread are dropped, rather than buffering for them forever
+const MAX_OUTGOING: usize = 4 * 1024 * 1024;
+
+/// Lets bots written in any language play, by talking line-delimited JSON over a local TCP port.
+/// See shared::bot_protocol for the messages.
+pub struct BotProtocolPlugin {
+    pub port: u16,
+}
+
+impl Plugin for BotProtocolPlugin {
+    fn build(&self, app: &mut App) {
+        // Only bots running on the same machine can connect
+        let addr = SocketAddr::from(([127, 0, 0, 1], self.port));
+        let listener = TcpListener::bind(addr)
+            .unwrap_or_else(|err| panic!("Could not listen for bots on {}: {}", addr, err));
+        listener.set_nonblocking(true).unwrap();
+        info!("Bots can connect on {}", addr);
+
+        app.insert_resource(BotConnections {
+            listener,
+            connections: Vec::new(),
+            next_player_id: FIRST_BOT_PLAYER_ID,
+        });
+        app.add_system(serve_bots.run_in_state(AppState::ServerListening));
+    }
+}
+
+/// Bots connected over the protocol
+pub struct BotConnections {
+    listener: TcpListener,
+    connections: Vec<BotConnection>,
+    next_player_id: PlayerId,
+}
+
+struct BotConnection {
+    stream: TcpStream,
+    addr: SocketAddr,
+    /// The player the bot is sat as, once it has said hello
+    player_id: Option<PlayerId>,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    /// How much of the history the bot has been sent, and the last event of it
+    sent_events: usize,
+    last_event: Option<GameEvent>,
+    /// The length of the history when the bot was last told it is its turn
+    prompted_at: Option<usize>,
+    closed: bool,
+}
+
+impl BotConnection {
+    fn new(stream: TcpStream, addr: SocketAddr) -> Self {
+        Self {
+            stream,
+            addr,
+            player_id: None,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            sent_events: 0,
+            last_event: None,
+            prompted_at: None,
+            closed: false,
+        }
+    }
+
+    fn send(&mut self, message: &BotServerMessage) {
+        if self.closed {
+            return;
+        }
+        self.outgoing.extend(encode_line(message).into_bytes());
+        if self.outgoing.len() > MAX_OUTGOING {
+            warn!("Bot at {} stopped reading, dropping it.", self.addr);
+            self.outgoing.clear();
+            self.closed = true;
+        }
+    }
+
+    /// Reads whatever the bot has sent so far, and hands back the lines that are complete
+    fn receive(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut buffer = [0; 4096];
+        let mut read_this_frame = 0;
+        while !self.closed && read_this_frame < MAX_READ_PER_FRAME {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(read) => {
+                    read_this_frame += read;
+                    self.incoming.extend_from_slice(&buffer[..read]);
+                    while let Some(end) = self.incoming.iter().position(|byte| *byte == b'\n') {
+                        let line: Vec<u8> = self.incoming.drain(..=end).collect();
+                        lines.push(String::from_utf8_lossy(&line).into_owned());
+                    }
+                    // Checked after every read, so a bot that never ends its line cant make
+                    // the buffer grow any further
+                    if self.incoming.len() > MAX_LINE_LENGTH {
+                        warn!(
+                            "Bot at {} sent a line that is too long, dropping it.",
+                            self.addr
+                        );
+                        self.closed = true;
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => self.closed = true,
+            }
+        }
+        lines
+    }
+
+    /// Sends as much of what is waiting to go out as the connection takes right now
+    fn flush(&mut self) {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Sends the events the bot hasnt seen yet, or the whole GameState if the history changed
+    /// under it because something was undone
+    fn catch_up(&mut self, game_state: &GameState) {
+        let history = &game_state.histroy;
+        let in_sync = history.len() >= self.sent_events
+            && (self.sent_events == 0
+                || history.get(self.sent_events - 1) == self.last_event.as_ref());
+        if in_sync {
+            for (index, event) in history.iter().enumerate().skip(self.sent_events) {
+                self.send(&BotServerMessage::Event {
+                    index,
+                    event: event.clone(),
+                });
+            }
+        } else {
+            self.send(&BotServerMessage::State {
+                game_state: Box::new(game_state.clone()),
+            });
+        }
+        self.sent_events = history.len();
+        self.last_event = history.last().cloned();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn serve_bots(
+    mut bots: ResMut<BotConnections>,
+    match_config: Res<MatchConfig>,
+    mut server: ResMut<RenetServer>,
+    sessions: Res<Sessions>,
+    mut game_state: ResMut<GameState>,
+    mut packet_stats: ResMut<PacketStats>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    let BotConnections {
+        listener,
+        connections,
+        next_player_id,
+    } = &mut *bots;
+
+    loop {
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                if let Err(err) = stream.set_nonblocking(true) {
+                    warn!("Could not set up connection of bot at {}: {}", addr, err);
+                    continue;
+                }
+                info!("Bot connected from {}.", addr);
+                connections.push(BotConnection::new(stream, addr));
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => {
+                warn!("Could not accept bot connection: {}", err);
+                break;
+            }
+        }
+    }
+
+    for connection in connections.iter_mut() {
+        for line in connection.receive() {
+            let message = match decode_line::<BotClientMessage>(&line) {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!("Bot at {} sent {:?}: {}", connection.addr, line, err);
+                    continue;
+                }
+            };
+
+            match message {
+                BotClientMessage::Hello { name } => {
+                    if connection.player_id.is_some() {
+                        warn!("Bot at {} said hello twice.", connection.addr);
+                        continue;
+                    }
+
+                    // Bots take up seats like everyone else, and can only join before the match
+                    if game_state.stage != Stage::PreGame
+                        || game_state.players.len() >= match_config.players
+                    {
+                        info!(
+                            "Bot at {} tried to join a match that is full.",
+                            connection.addr
+                        );
+                        connection.send(&BotServerMessage::Disconnect {
+                            reason: DisconnectReason::MatchFull,
+                        });
+                        connection.closed = true;
+                        break;
+                    }
+
+                    // Clients that authenticate unsecurely pick their own ids, so one might
+                    // already have taken the next one meant for a bot
+                    while game_state.players.contains_key(next_player_id) {
+                        *next_player_id += 1;
+                    }
+                    let player_id = *next_player_id;
+                    *next_player_id += 1;
+                    let joined = crate::join_match(
+                        player_id,
+                        name.clone(),
+                        &match_config,
+                        &mut server,
+                        &mut packet_stats,
+                        &sessions,
+                        &mut game_state,
+                        &buildings,
+                        &units,
+                        &terrain,
+                    );
+                    if !joined {
+                        info!(
+                            "Bot at {} could not join as player {}.",
+                            connection.addr, player_id
+                        );
+                        connection.send(&BotServerMessage::Disconnect {
+                            reason: DisconnectReason::PlayerIdTaken,
+                        });
+                        connection.closed = true;
+                        break;
+                    }
+
+                    // The state already has the bot in it, so only events after it are sent on
+                    connection.player_id = Some(player_id);
+                    connection.send(&BotServerMessage::Welcome {
+                        player_id,
+                        protocol_version: BOT_PROTOCOL_VERSION,
+                    });
+                    connection.send(&BotServerMessage::State {
+                        game_state: Box::new(game_state.clone()),
+                    });
+                    connection.sent_events = game_state.histroy.len();
+                    connection.last_event = game_state.histroy.last().cloned();
+
+                    info!(
+                        "Bot {} at {} joined as player {}.",
+                        name, connection.addr, player_id
+                    );
+                }
+                BotClientMessage::Action { event } => {
+                    let result = match connection.player_id {
+                        Some(player_id) => crate::apply_player_event(
+                            player_id,
+                            event.clone(),
+                            &mut server,
+                            &mut packet_stats,
+                            &sessions,
+                            &mut game_state,
+                            &buildings,
+                            &units,
+                            &terrain,
+                        ),
+                        None => Err(RejectReason::NotSeated),
+                    };
+                    packet_stats.count_event(result);
+                    if let Err(reason) = result {
+                        connection.send(&BotServerMessage::Rejected { event, reason });
+                    }
+                }
+            }
+        }
+    }
+
+    // Keep every bot up to date, and let it know when it has to act
+    for connection in connections.iter_mut() {
+        if let Some(player_id) = connection.player_id {
+            connection.catch_up(&game_state);
+
+            let turn = game_state.histroy.len();
+            if game_state.stage == Stage::InGame
+                && game_state.active_player_id == player_id
+                && connection.prompted_at != Some(turn)
+            {
+                connection.prompted_at = Some(turn);
+                let legal_actions =
+                    game_state.legal_actions(player_id, &buildings, &units, &terrain);
+                connection.send(&BotServerMessage::YourTurn { legal_actions });
+            }
+        }
+        connection.flush();
+    }
+
+    // Bots that went away give up their seat for good
+    let (closed, open): (Vec<_>, Vec<_>) = connections
+        .drain(..)
+        .partition(|connection| connection.closed);
+    *connections = open;
+    for connection in closed {
+        info!("Bot at {} disconnected.", connection.addr);
+        let player_id = match connection.player_id {
+            Some(player_id) if game_state.players.contains_key(&player_id) => player_id,
+            _ => continue,
+        };
+        crate::remove_player(
+            player_id,
+            &mut server,
+            &mut packet_stats,
+            &sessions,
+            &mut game_state,
+            &buildings,
+            &units,
+            &terrain,
+        );
+        crate::broadcast_lobby(&mut server, &mut packet_stats, &sessions, &game_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bot connection as the server sees it, along with the bot's end
+    fn connection() -> (BotConnection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let bot = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        (BotConnection::new(stream, addr), bot)
+    }
+
+    /// Receives until the bot's bytes have all arrived, or the connection got dropped
+    fn receive_all(connection: &mut BotConnection, sent: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut received = 0;
+        while !connection.closed && received < sent {
+            let new_lines = connection.receive();
+            received = new_lines.iter().map(String::len).sum::<usize>() + connection.incoming.len();
+            lines.extend(new_lines);
+        }
+        lines
+    }
+
+    #[test]
+    fn lines_are_handed_back_once_complete() {
+        let (mut connection, mut bot) = connection();
+        let hello = encode_line(&BotClientMessage::Hello {
+            name: "Bot".to_string(),
+        });
+        bot.write_all(hello.as_bytes()).unwrap();
+        bot.write_all(b"{\"type\":").unwrap();
+
+        let lines = receive_all(&mut connection, hello.len() + 8);
+        assert_eq!(lines, vec![hello]);
+        assert!(!connection.closed);
+    }
+
+    #[test]
+    fn bots_that_never_end_their_line_are_dropped() {
+        let (mut connection, mut bot) = connection();
+        let line = vec![b'a'; MAX_LINE_LENGTH + 1];
+        bot.write_all(&line).unwrap();
+
+        receive_all(&mut connection, line.len());
+        assert!(connection.closed);
+        assert!(connection.incoming.len() <= MAX_LINE_LENGTH + 4096);
+    }
+
+    #[test]
+    fn bots_that_stop_reading_are_dropped() {
+        let (mut connection, _bot) = connection();
+        let state = BotServerMessage::State {
+            game_state: Box::default(),
+        };
+        while !connection.closed {
+            connection.send(&state);
+            connection.flush();
+        }
+        assert!(connection.outgoing.is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+use bevy::{prelude::*, utils::Duration};
+use bevy_renet::renet::RenetServer;
+use iyes_loopless::prelude::*;
+use log::{info, warn};
+use shared::{
+    ai::{heuristic::HeuristicBot, Bot, Difficulty},
+    buildings::Buildings,
+    terrain::Terrain,
+    units::Units,
+    GameEvent, GameState, PlayerId, Stage,
+};
+
+use crate::plugins::sessions::Sessions;
+use crate::{AppState, MatchConfig, PacketStats};
+
+/// How long bots wait between their actions, so that everyone else can follow what they do
+const BOT_ACTION_DELAY: Duration = Duration::from_millis(500);
+
+/// Sits bots down in some of the seats of the match, and takes their turns for them
+pub struct BotsPlugin {
+    pub count: usize,
+    pub difficulty: Difficulty,
+}
+
+impl Plugin for BotsPlugin {
+    fn build(&self, app: &mut App) {
+        if self.count == 0 {
+            return;
+        }
+
+        let seats = (0..self.count)
+            .map(|index| BotSeat {
+                // Counting down from the top keeps clear of the ids renet hands out to clients
+                player_id: PlayerId::MAX - index as PlayerId,
+                name: format!("Bot {} ({})", index + 1, self.difficulty),
+                bot: Box::new(HeuristicBot::new(self.difficulty)),
+            })
+            .collect();
+        app.insert_resource(BotSeats {
+            seats,
+            seated: false,
+            next_action: Duration::ZERO,
+        });
+        app.add_system(seat_bots.run_in_state(AppState::ServerListening));
+        app.add_system(run_bots.run_in_state(AppState::ServerListening));
+    }
+}
+
+struct BotSeat {
+    player_id: PlayerId,
+    name: String,
+    bot: Box<dyn Bot + Send + Sync>,
+}
+
+/// The players of the match that are played by the server itself
+pub struct BotSeats {
+    seats: Vec<BotSeat>,
+    /// Whether the bots have joined the match yet
+    seated: bool,
+    /// Bots dont act again before this time
+    next_action: Duration,
+}
+
+/// Has the bots join the match as soon as the server is up, before any human does
+#[allow(clippy::too_many_arguments)]
+fn seat_bots(
+    mut bots: ResMut<BotSeats>,
+    match_config: Res<MatchConfig>,
+    mut server: ResMut<RenetServer>,
+    mut packet_stats: ResMut<PacketStats>,
+    mut sessions: ResMut<Sessions>,
+    mut game_state: ResMut<GameState>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    if bots.seated {
+        return;
+    }
+    bots.seated = true;
+
+    for seat in bots.seats.iter() {
+        // Bots of a resumed match are still in it, they just never connect to reclaim their seat
+        if game_state.players.contains_key(&seat.player_id) {
+            sessions.release(seat.player_id);
+            continue;
+        }
+
+        if game_state.stage != Stage::PreGame || game_state.players.len() >= match_config.players {
+            continue;
+        }
+
+        let joined = crate::join_match(
+            seat.player_id,
+            seat.name.clone(),
+            &match_config,
+            &mut server,
+            &mut packet_stats,
+            &sessions,
+            &mut game_state,
+            &buildings,
+            &units,
+            &terrain,
+        );
+        if joined {
+            info!("{} joined as player {}.", seat.name, seat.player_id);
+        } else {
+            warn!("{} could not join as player {}.", seat.name, seat.player_id);
+        }
+    }
+}
+
+/// Lets the bot whose turn it is take one action
+#[allow(clippy::too_many_arguments)]
+fn run_bots(
+    time: Res<Time>,
+    mut bots: ResMut<BotSeats>,
+    mut server: ResMut<RenetServer>,
+    mut packet_stats: ResMut<PacketStats>,
+    sessions: Res<Sessions>,
+    mut game_state: ResMut<GameState>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    let now = time.time_since_startup();
+    if game_state.stage != Stage::InGame || now < bots.next_action {
+        return;
+    }
+
+    let player_id = game_state.active_player_id;
+    let seat = match bots
+        .seats
+        .iter_mut()
+        .find(|seat| seat.player_id == player_id)
+    {
+        Some(seat) => seat,
+        None => return,
+    };
+
+    let event = seat
+        .bot
+        .choose_action(&game_state, player_id, &buildings, &units, &terrain)
+        .filter(|event| game_state.validate(event, &buildings, &units, &terrain))
+        .unwrap_or_else(|| {
+            // Bots are held to the same rules as everyone else, and dont get to hold up the game
+            warn!(
+                "{} could not come up with an action, ending its turn.",
+                seat.name
+            );
+            GameEvent::EndTurn { player_id }
+        });
+    bots.next_action = now + BOT_ACTION_DELAY;
+
+    crate::apply_event(
+        event,
+        &mut server,
+        &mut packet_stats,
+        &sessions,
+        &mut game_state,
+        &buildings,
+        &units,
+        &terrain,
+    );
+    crate::end_game_if_won(
+        &mut server,
+        &mut packet_stats,
+        &sessions,
+        &mut game_state,
+        &buildings,
+        &units,
+        &terrain,
+    );
+    crate::skip_held_turns(
+        &mut server,
+        &mut packet_stats,
+        &sessions,
+        &mut game_state,
+        &buildings,
+        &units,
+        &terrain,
+    );
+}
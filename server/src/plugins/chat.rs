@@ -0,0 +1,226 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::{prelude::*, utils::Duration};
+use bevy_renet::renet::RenetServer;
+use shared::{
+    chat::{ChatRejectReason, ChatScope, Emote, MAX_CHAT_LENGTH},
+    messages::ServerMessage,
+    GameState,
+};
+
+use crate::plugins::sessions::Sessions;
+use crate::PacketStats;
+
+/// How many chat messages and emotes a player can send within CHAT_WINDOW
+const CHAT_BURST: usize = 5;
+const CHAT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Passes chat messages and emotes between the players of the match. Chat never goes through
+/// the GameState, so it doesnt end up in the histroy or in saves.
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        // Whoever builds the app can put in a ChatRelay with a filter of its own
+        app.init_resource::<ChatRelay>();
+    }
+}
+
+/// Looks over a chat message before it goes out. Returns the text to send, which may have been
+/// cleaned up, or None to hold the message back.
+pub type ChatFilter = Box<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Checks chat messages and emotes and sends them on to the players they are meant for
+pub struct ChatRelay {
+    filter: ChatFilter,
+    /// When each client sent its latest messages, to keep them from flooding the chat
+    sent: HashMap<u64, VecDeque<Duration>>,
+}
+
+impl Default for ChatRelay {
+    fn default() -> Self {
+        Self::new(Box::new(|text| Some(text.to_string())))
+    }
+}
+
+impl ChatRelay {
+    pub fn new(filter: ChatFilter) -> Self {
+        Self {
+            filter,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Checks a chat message from a client and sends it to everyone in `scope`
+    #[allow(clippy::too_many_arguments)]
+    pub fn chat(
+        &mut self,
+        client_id: u64,
+        scope: ChatScope,
+        text: &str,
+        now: Duration,
+        server: &mut RenetServer,
+        packet_stats: &mut PacketStats,
+        sessions: &Sessions,
+        game_state: &GameState,
+    ) {
+        let text = text.trim();
+        let checked = match sessions.player_id(client_id) {
+            None => Err(ChatRejectReason::NotSeated),
+            Some(_) if text.is_empty() => Err(ChatRejectReason::Empty),
+            Some(_) if text.chars().count() > MAX_CHAT_LENGTH => Err(ChatRejectReason::TooLong),
+            Some(player_id) => self
+                .take_turn(client_id, now)
+                .and_then(|_| (self.filter)(text).ok_or(ChatRejectReason::Filtered))
+                .map(|text| (player_id, text)),
+        };
+
+        match checked {
+            Ok((player_id, text)) => {
+                let message = ServerMessage::Chat {
+                    player_id,
+                    scope,
+                    text,
+                };
+                for other_id in sessions.seated_clients() {
+                    let in_scope = match scope {
+                        ChatScope::Everyone => true,
+                        ChatScope::Team => sessions
+                            .player_id(other_id)
+                            .is_some_and(|other| game_state.are_allies(player_id, other)),
+                    };
+                    if in_scope {
+                        crate::send(server, packet_stats, other_id, message.clone());
+                    }
+                }
+            }
+            Err(reason) => crate::send(
+                server,
+                packet_stats,
+                client_id,
+                ServerMessage::ChatRejected(reason),
+            ),
+        }
+    }
+
+    /// Sends an emote from a client to all players
+    pub fn emote(
+        &mut self,
+        client_id: u64,
+        emote: Emote,
+        now: Duration,
+        server: &mut RenetServer,
+        packet_stats: &mut PacketStats,
+        sessions: &Sessions,
+    ) {
+        let checked = sessions
+            .player_id(client_id)
+            .ok_or(ChatRejectReason::NotSeated)
+            .and_then(|player_id| self.take_turn(client_id, now).map(|_| player_id));
+
+        match checked {
+            Ok(player_id) => crate::broadcast(
+                server,
+                packet_stats,
+                sessions,
+                ServerMessage::Emote { player_id, emote },
+            ),
+            Err(reason) => crate::send(
+                server,
+                packet_stats,
+                client_id,
+                ServerMessage::ChatRejected(reason),
+            ),
+        }
+    }
+
+    /// Forgets about a client that left
+    pub fn forget(&mut self, client_id: u64) {
+        self.sent.remove(&client_id);
+    }
+
+    /// Notes down that a client sends something now, unless it already sent CHAT_BURST things
+    /// within the last CHAT_WINDOW
+    fn take_turn(&mut self, client_id: u64, now: Duration) -> Result<(), ChatRejectReason> {
+        let sent = self.sent.entry(client_id).or_default();
+        while sent
+            .front()
+            .is_some_and(|sent_at| *sent_at + CHAT_WINDOW <= now)
+        {
+            sent.pop_front();
+        }
+        if sent.len() >= CHAT_BURST {
+            return Err(ChatRejectReason::TooFast);
+        }
+        sent.push_back(now);
+        Ok(())
+    }
+}
+
+/// A filter that hides every word on the `blocklist` behind asterisks, ignoring case
+pub fn masking_filter(blocklist: Vec<String>) -> ChatFilter {
+    let blocklist: Vec<String> = blocklist
+        .into_iter()
+        .map(|word| word.trim().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect();
+    Box::new(move |text| {
+        let masked: Vec<String> = text
+            .split(' ')
+            .map(|word| {
+                let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+                if blocklist.contains(&bare.to_lowercase()) {
+                    word.replace(bare, &"*".repeat(bare.chars().count()))
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect();
+        Some(masked.join(" "))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn players_can_only_send_so_much_within_the_window() {
+        let mut relay = ChatRelay::default();
+        let start = Duration::from_secs(100);
+
+        for _ in 0..CHAT_BURST {
+            assert_eq!(relay.take_turn(1, start), Ok(()));
+        }
+        assert_eq!(
+            relay.take_turn(1, start + CHAT_WINDOW / 2),
+            Err(ChatRejectReason::TooFast)
+        );
+        // Everyone else can still talk
+        assert_eq!(relay.take_turn(2, start), Ok(()));
+
+        // Once the first messages are out of the window there is room again, for as many
+        assert_eq!(relay.take_turn(1, start + CHAT_WINDOW), Ok(()));
+        for _ in 1..CHAT_BURST {
+            assert_eq!(relay.take_turn(1, start + CHAT_WINDOW), Ok(()));
+        }
+        assert_eq!(
+            relay.take_turn(1, start + CHAT_WINDOW),
+            Err(ChatRejectReason::TooFast)
+        );
+
+        // And a client that left starts over when it comes back
+        relay.forget(1);
+        assert_eq!(relay.take_turn(1, start + CHAT_WINDOW), Ok(()));
+    }
+
+    #[test]
+    fn masking_hides_blocked_words_whatever_their_case() {
+        let filter = masking_filter(vec!["Darn ".to_string(), "".to_string()]);
+        assert_eq!(
+            filter("darn it, DARN! darnation").as_deref(),
+            Some("**** it, ****! darnation")
+        );
+        assert_eq!(filter("all good").as_deref(), Some("all good"));
+    }
+}
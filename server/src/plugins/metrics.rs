@@ -0,0 +1,447 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
+};
+
+use bevy::{prelude::*, utils::Duration};
+use bevy_renet::renet::RenetServer;
+use iyes_loopless::prelude::*;
+use log::{error, info, warn};
+use shared::{messages::RejectReason, GameState, Stage};
+
+use crate::plugins::sessions::Sessions;
+use crate::{AppState, PacketStats};
+
+/// How often the metrics are worked out again, and the window the per second rates are over
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the metrics are written to the file, if there is one
+const DUMP_INTERVAL: Duration = Duration::from_secs(10);
+
+// Upper bounds of the tick duration histogram, in seconds. A tick at 60 fps has 16ms to spare.
+const TICK_BUCKETS: [f64; 8] = [0.001, 0.0025, 0.005, 0.01, 0.016, 0.025, 0.05, 0.1];
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keeps track of how the server is doing, for load tests. The metrics are served in the
+/// Prometheus text format over http on a local port, and/or written to a file every so often.
+pub struct MetricsPlugin {
+    /// Serve the metrics on this port of localhost, at /metrics
+    pub port: Option<u16>,
+    /// Write the metrics to this file every DUMP_INTERVAL
+    pub file: Option<PathBuf>,
+}
+
+impl Plugin for MetricsPlugin {
+    fn build(&self, app: &mut App) {
+        let rendered = Arc::new(Mutex::new(String::new()));
+        if let Some(port) = self.port {
+            // Only reachable from the same machine
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            let listener = TcpListener::bind(addr)
+                .unwrap_or_else(|err| panic!("Could not serve metrics on {}: {}", addr, err));
+            info!("Serving metrics on http://{}/metrics", addr);
+            let rendered = rendered.clone();
+            thread::spawn(move || serve_metrics(listener, rendered));
+        }
+
+        app.insert_resource(Metrics {
+            file: self.file.clone(),
+            rendered,
+            tick_started: None,
+            tick_buckets: [0; TICK_BUCKETS.len()],
+            tick_count: 0,
+            tick_sum: 0.0,
+            last_sample: Duration::ZERO,
+            last_dump: None,
+            sampled_processed: 0,
+            sampled_rejected: HashMap::new(),
+            processed_per_second: 0.0,
+            rejected_per_second: HashMap::new(),
+        });
+        app.add_system_to_stage(CoreStage::First, start_tick);
+        // Runs after everything else, so the tick includes sending the packets out
+        app.add_system_to_stage(CoreStage::Last, end_tick.label("end_tick"));
+        app.add_system_to_stage(
+            CoreStage::Last,
+            sample_metrics
+                .run_in_state(AppState::ServerListening)
+                .after("end_tick"),
+        );
+    }
+}
+
+pub struct Metrics {
+    file: Option<PathBuf>,
+    /// The latest metrics in the Prometheus text format, shared with the http server
+    rendered: Arc<Mutex<String>>,
+    tick_started: Option<Instant>,
+    /// How many ticks took at most each of TICK_BUCKETS
+    tick_buckets: [u64; TICK_BUCKETS.len()],
+    tick_count: u64,
+    /// Time spent on all ticks together, in seconds
+    tick_sum: f64,
+    last_sample: Duration,
+    /// When the metrics were last written to the file
+    last_dump: Option<Duration>,
+    /// The event counts as of the last sample, to work out the rates from
+    sampled_processed: u64,
+    sampled_rejected: HashMap<RejectReason, u64>,
+    processed_per_second: f64,
+    rejected_per_second: HashMap<RejectReason, f64>,
+}
+
+fn start_tick(mut metrics: ResMut<Metrics>) {
+    metrics.tick_started = Some(Instant::now());
+}
+
+fn end_tick(mut metrics: ResMut<Metrics>) {
+    let seconds = match metrics.tick_started.take() {
+        Some(started) => started.elapsed().as_secs_f64(),
+        None => return,
+    };
+    for (bucket, upper_bound) in metrics.tick_buckets.iter_mut().zip(TICK_BUCKETS) {
+        if seconds <= upper_bound {
+            *bucket += 1;
+        }
+    }
+    metrics.tick_count += 1;
+    metrics.tick_sum += seconds;
+}
+
+fn sample_metrics(
+    time: Res<Time>,
+    mut metrics: ResMut<Metrics>,
+    server: Res<RenetServer>,
+    sessions: Res<Sessions>,
+    game_state: Res<GameState>,
+    packet_stats: Res<PacketStats>,
+) {
+    let now = time.time_since_startup();
+    let elapsed = now - metrics.last_sample;
+    if elapsed < SAMPLE_INTERVAL {
+        return;
+    }
+    metrics.last_sample = now;
+
+    let seconds = elapsed.as_secs_f64();
+    metrics.processed_per_second =
+        (packet_stats.events_processed - metrics.sampled_processed) as f64 / seconds;
+    metrics.sampled_processed = packet_stats.events_processed;
+    for (reason, count) in packet_stats.events_rejected.iter() {
+        let sampled = metrics.sampled_rejected.insert(*reason, *count);
+        let rate = (count - sampled.unwrap_or_default()) as f64 / seconds;
+        metrics.rejected_per_second.insert(*reason, rate);
+    }
+
+    let text = render(&metrics, &server, &sessions, &game_state, &packet_stats);
+    *metrics.rendered.lock().unwrap() = text.clone();
+
+    if let Some(path) = &metrics.file {
+        let dumped_lately = metrics
+            .last_dump
+            .is_some_and(|last_dump| now - last_dump < DUMP_INTERVAL);
+        if !dumped_lately {
+            if let Err(err) = fs::write(path, text) {
+                error!("Could not write metrics to {}: {}", path.display(), err);
+            }
+            metrics.last_dump = Some(now);
+        }
+    }
+}
+
+/// Writes the metrics out in the Prometheus text format
+fn render(
+    metrics: &Metrics,
+    server: &RenetServer,
+    sessions: &Sessions,
+    game_state: &GameState,
+    packet_stats: &PacketStats,
+) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+        writeln!(text, "# HELP dinojam2_{} {}", name, help).unwrap();
+        writeln!(text, "# TYPE dinojam2_{} {}", name, kind).unwrap();
+        for (labels, value) in samples {
+            writeln!(text, "dinojam2_{}{} {}", name, labels, value).unwrap();
+        }
+    };
+    let by_reason = |counts: Vec<(&RejectReason, f64)>| {
+        counts
+            .into_iter()
+            .map(|(reason, value)| (format!("{{reason=\"{:?}\"}}", reason), value))
+            .collect::<Vec<_>>()
+    };
+
+    let clients = server.clients_id();
+    let players = clients
+        .iter()
+        .filter(|client_id| sessions.player_id(**client_id).is_some())
+        .count();
+    let spectators = sessions.spectators().count();
+    metric(
+        "connected_clients",
+        "gauge",
+        "Clients connected to the server, by what they are doing",
+        vec![
+            ("{role=\"player\"}".to_string(), players as f64),
+            ("{role=\"spectator\"}".to_string(), spectators as f64),
+            (
+                "{role=\"other\"}".to_string(),
+                clients.len().saturating_sub(players + spectators) as f64,
+            ),
+        ],
+    );
+    metric(
+        "active_matches",
+        "gauge",
+        "Matches being played, the server hosts one at a time",
+        vec![(
+            String::new(),
+            (game_state.stage == Stage::InGame) as u8 as f64,
+        )],
+    );
+
+    metric(
+        "events_processed_total",
+        "counter",
+        "Events sent by clients and bots that went through",
+        vec![(String::new(), packet_stats.events_processed as f64)],
+    );
+    metric(
+        "events_rejected_total",
+        "counter",
+        "Events sent by clients and bots that were rejected",
+        by_reason(
+            packet_stats
+                .events_rejected
+                .iter()
+                .map(|(reason, count)| (reason, *count as f64))
+                .collect(),
+        ),
+    );
+    metric(
+        "events_processed_per_second",
+        "gauge",
+        "Events that went through per second, over the last sample",
+        vec![(String::new(), metrics.processed_per_second)],
+    );
+    metric(
+        "events_rejected_per_second",
+        "gauge",
+        "Events that were rejected per second, over the last sample",
+        by_reason(
+            metrics
+                .rejected_per_second
+                .iter()
+                .map(|(reason, rate)| (reason, *rate))
+                .collect(),
+        ),
+    );
+    metric(
+        "malformed_messages_total",
+        "counter",
+        "Messages from clients that could not be read",
+        vec![(String::new(), packet_stats.malformed as f64)],
+    );
+    metric(
+        "desyncs_total",
+        "counter",
+        "Times a client fell out of sync with the server",
+        vec![(String::new(), packet_stats.desyncs as f64)],
+    );
+
+    let mut ticks: Vec<(String, f64)> = TICK_BUCKETS
+        .iter()
+        .zip(metrics.tick_buckets)
+        .map(|(upper_bound, count)| (format!("_bucket{{le=\"{}\"}}", upper_bound), count as f64))
+        .collect();
+    ticks.push((
+        "_bucket{le=\"+Inf\"}".to_string(),
+        metrics.tick_count as f64,
+    ));
+    ticks.push(("_sum".to_string(), metrics.tick_sum));
+    ticks.push(("_count".to_string(), metrics.tick_count as f64));
+    metric(
+        "tick_duration_seconds",
+        "histogram",
+        "How long the server took for a tick, not counting the wait for the next one",
+        ticks,
+    );
+
+    // Counted per message rather than taken from renet's network info, its bandwidth is an
+    // estimate that stays put while a connection goes quiet
+    metric(
+        "sent_bytes_total",
+        "counter",
+        "Bytes of all messages handed to renet for clients",
+        vec![(String::new(), packet_stats.bytes_sent as f64)],
+    );
+    metric(
+        "received_bytes_total",
+        "counter",
+        "Bytes of all messages renet got from clients",
+        vec![(String::new(), packet_stats.bytes_received as f64)],
+    );
+
+    text
+}
+
+fn serve_metrics(listener: TcpListener, rendered: Arc<Mutex<String>>) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|mut stream| {
+            let text = rendered.lock().unwrap().clone();
+            answer_request(&mut stream, &text)
+        });
+        if let Err(err) = result {
+            warn!("Could not serve metrics: {}", err);
+        }
+    }
+}
+
+/// Reads an http request and answers it with the metrics, if that is what it asked for
+fn answer_request(stream: &mut TcpStream, text: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(&*stream);
+    reader.read_line(&mut request_line)?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", text),
+        _ => ("404 Not Found", "Metrics are at /metrics\n"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> Metrics {
+        Metrics {
+            file: None,
+            rendered: Arc::default(),
+            tick_started: None,
+            tick_buckets: [0; TICK_BUCKETS.len()],
+            tick_count: 0,
+            tick_sum: 0.0,
+            last_sample: Duration::ZERO,
+            last_dump: None,
+            sampled_processed: 0,
+            sampled_rejected: HashMap::new(),
+            processed_per_second: 0.0,
+            rejected_per_second: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn metrics_are_rendered_in_the_prometheus_text_format() {
+        let server = crate::new_renet_server("127.0.0.1:0".parse().unwrap(), 4, None);
+        let mut metrics = metrics();
+        metrics.processed_per_second = 2.5;
+        // One quick tick and one that took far too long
+        metrics.tick_buckets = [1, 1, 1, 1, 1, 1, 1, 1];
+        metrics.tick_count = 2;
+        metrics.tick_sum = 0.5;
+        let mut packet_stats = PacketStats {
+            bytes_sent: 1234,
+            ..default()
+        };
+        packet_stats.count_event(Ok(()));
+        packet_stats.count_event(Err(RejectReason::Invalid));
+        packet_stats.count_event(Err(RejectReason::Invalid));
+
+        let text = render(
+            &metrics,
+            &server,
+            &Sessions::default(),
+            &GameState::default(),
+            &packet_stats,
+        );
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            "# HELP dinojam2_sent_bytes_total Bytes of all messages handed to renet for clients",
+            "# TYPE dinojam2_sent_bytes_total counter",
+            "dinojam2_sent_bytes_total 1234",
+            "dinojam2_connected_clients{role=\"player\"} 0",
+            "dinojam2_active_matches 0",
+            "dinojam2_events_processed_total 1",
+            "dinojam2_events_rejected_total{reason=\"Invalid\"} 2",
+            "dinojam2_events_processed_per_second 2.5",
+            "# TYPE dinojam2_tick_duration_seconds histogram",
+            "dinojam2_tick_duration_seconds_bucket{le=\"0.001\"} 1",
+            "dinojam2_tick_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "dinojam2_tick_duration_seconds_sum 0.5",
+            "dinojam2_tick_duration_seconds_count 2",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "{:?} missing from\n{}",
+                expected,
+                text
+            );
+        }
+        // Every sample has a HELP and TYPE line before it
+        for line in lines.iter().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            // Histograms spread over a few samples with suffixes
+            let name = ["_bucket", "_sum", "_count"]
+                .iter()
+                .fold(name, |name, suffix| name.trim_end_matches(suffix));
+            assert!(
+                text.contains(&format!("# TYPE {} ", name)),
+                "{:?} has no TYPE line",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn only_metrics_requests_get_the_metrics() {
+        let get = |path: &str| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            answer_request(&mut server, "dinojam2_desyncs_total 0\n").unwrap();
+            drop(server);
+
+            let mut response = String::new();
+            io::Read::read_to_string(&mut client, &mut response).unwrap();
+            response
+        };
+
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Content-Length: 25\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\ndinojam2_desyncs_total 0\n"));
+
+        let response = get("/");
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+        assert!(!response.contains("dinojam2_desyncs_total"));
+    }
+}
@@ -1 +1,12 @@
+pub mod abuse;
+pub mod admin;
 pub mod asset_loader;
+pub mod bot_protocol;
+pub mod bots;
+pub mod chat;
+pub mod metrics;
+pub mod save;
+pub mod sessions;
+pub mod spectators;
+pub mod token_service;
+pub mod turn_timer;
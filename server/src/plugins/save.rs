@@ -0,0 +1,136 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use bevy::{prelude::*, utils::Duration};
+use iyes_loopless::prelude::*;
+use log::{error, info};
+use shared::{
+    buildings::Buildings, hash, save::SaveFile, terrain::Terrain, units::Units, GameState, Stage,
+};
+
+use crate::plugins::sessions::Sessions;
+use crate::AppState;
+
+pub const DEFAULT_SAVE_PATH: &str = "saves/last_match.json";
+
+/// How long the players of a resumed match have to come back before losing their seat
+pub const RESUME_GRACE_PERIOD: Duration = Duration::from_secs(300);
+
+/// Saves the match to disk after every event, and optionally resumes a saved match on startup
+pub struct SavePlugin {
+    pub save_path: PathBuf,
+    pub resume_from: Option<PathBuf>,
+}
+
+/// Where the match gets saved, and the state it was in when it was last saved
+pub struct SaveSettings {
+    path: PathBuf,
+    /// Hash of the GameState that was saved. The length of the history alone doesnt do, an undo
+    /// and a new action in the same frame leave it as it was.
+    saved_hash: u64,
+}
+
+/// The save to restore once the descriptors have loaded
+struct ResumeFrom(PathBuf);
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SaveSettings {
+            path: self.save_path.clone(),
+            saved_hash: hash::state_hash(&GameState::default()),
+        });
+        if let Some(resume_from) = &self.resume_from {
+            app.insert_resource(ResumeFrom(resume_from.clone()));
+            app.add_enter_system(AppState::ServerListening, resume_match);
+        }
+        app.add_system(autosave.run_in_state(AppState::ServerListening));
+    }
+}
+
+/// Replays a saved match and holds the seats of everyone that was playing in it, for the clients
+/// that come back with their session token.
+///
+/// Panics if the save can't be restored, a server asked to resume a match shouldn't quietly
+/// start a new one instead.
+#[allow(clippy::too_many_arguments)]
+fn resume_match(
+    resume_from: Res<ResumeFrom>,
+    time: Res<Time>,
+    mut settings: ResMut<SaveSettings>,
+    mut game_state: ResMut<GameState>,
+    mut sessions: ResMut<Sessions>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    let path = &resume_from.0;
+    let (save, restored) = SaveFile::read(path)
+        .and_then(|save| {
+            let restored = save.restore(&buildings, &units, &terrain)?;
+            Ok((save, restored))
+        })
+        .unwrap_or_else(|err| panic!("Could not resume {}: {}", path.display(), err));
+
+    if restored.stage == Stage::Ended {
+        error!("Resumed match {} has already ended", path.display());
+    }
+
+    let deadline = time.time_since_startup() + RESUME_GRACE_PERIOD;
+    for player in save.header.players.iter() {
+        sessions.hold_player(player.player_id, player.session_token, deadline);
+    }
+
+    info!(
+        "Resumed match {} with {} events and {} players",
+        path.display(),
+        restored.histroy.len(),
+        restored.players.len()
+    );
+    settings.saved_hash = hash::state_hash(&restored);
+    *game_state = restored;
+}
+
+fn autosave(
+    mut settings: ResMut<SaveSettings>,
+    game_state: Res<GameState>,
+    sessions: Res<Sessions>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    if !game_state.is_changed() || hash::state_hash(&game_state) == settings.saved_hash {
+        return;
+    }
+
+    // Dont try again until something else happens, rather than every frame
+    if let Err(err) = settings.save(&game_state, &sessions, &buildings, &units, &terrain) {
+        error!(
+            "Could not save match to {}: {}",
+            settings.path.display(),
+            err
+        );
+    }
+}
+
+impl SaveSettings {
+    /// Saves the match right away. The save holds the session tokens of the players, so it
+    /// should be kept as private as the server.
+    pub fn save(
+        &mut self,
+        game_state: &GameState,
+        sessions: &Sessions,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> io::Result<()> {
+        self.saved_hash = hash::state_hash(game_state);
+        let tokens = sessions.tokens();
+        SaveFile::new(game_state, &tokens, buildings, units, terrain).write(&self.path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::{prelude::*, utils::Duration};
+use bevy_renet::renet::RenetServer;
+use iyes_loopless::prelude::*;
+use log::info;
+use shared::{
+    connection::{ConnectInfo, Session, SessionToken},
+    messages::DisconnectReason,
+    PlayerId,
+};
+
+use crate::{AppState, PacketStats};
+
+/// How long a disconnected player's seat is kept for them during a game
+pub const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// How long a newly connected client has to send its handshake before it is dropped
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct SessionsPlugin;
+
+impl Plugin for SessionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Sessions>();
+        app.add_event::<SessionExpired>();
+        app.add_system(expire_sessions.run_in_state(AppState::ServerListening));
+        app.add_system(drop_silent_clients.run_in_state(AppState::ServerListening));
+    }
+}
+
+/// Sent when a disconnected player failed to reconnect within the grace period
+pub struct SessionExpired {
+    pub player_id: PlayerId,
+}
+
+/// Keeps track of which connection plays as which player, which seats are being held for
+/// players that have dropped out, and which connections are only watching.
+#[derive(Default)]
+pub struct Sessions {
+    /// Clients that have connected but not yet sent their handshake, along with when they are
+    /// dropped if they still havent
+    pending: HashMap<u64, (ConnectInfo, Duration)>,
+    /// The player each connected renet client is sat as
+    clients: HashMap<u64, PlayerId>,
+    /// The player each handed out token belongs to
+    tokens: HashMap<SessionToken, PlayerId>,
+    /// Players that have dropped out, along with when their seat is given up
+    held: HashMap<PlayerId, Duration>,
+    /// Clients watching the match without a seat, along with the name they gave
+    spectators: BTreeMap<u64, String>,
+}
+
+impl Sessions {
+    /// Remembers a newly connected client until it has completed the handshake, or until
+    /// `deadline` passes
+    pub fn await_handshake(
+        &mut self,
+        client_id: u64,
+        connect_info: ConnectInfo,
+        deadline: Duration,
+    ) {
+        self.pending.insert(client_id, (connect_info, deadline));
+    }
+
+    /// Takes back the connect info of a client that is still waiting on its handshake
+    pub fn take_pending(&mut self, client_id: u64) -> Option<ConnectInfo> {
+        self.pending
+            .remove(&client_id)
+            .map(|(connect_info, _)| connect_info)
+    }
+
+    /// Starts a session for a client joining as a new player
+    pub fn start(&mut self, client_id: u64) -> Session {
+        // The first connection of a player decides their id
+        let player_id = client_id;
+
+        let token = self.new_token();
+        self.tokens.insert(token, player_id);
+        self.clients.insert(client_id, player_id);
+        Session { player_id, token }
+    }
+
+    /// Sits a client back down in the held seat that the token belongs to
+    pub fn resume(&mut self, client_id: u64, token: SessionToken) -> Option<Session> {
+        let player_id = *self.tokens.get(&token)?;
+        self.held.remove(&player_id)?;
+
+        self.clients.insert(client_id, player_id);
+        Some(Session { player_id, token })
+    }
+
+    /// Keeps a seat open until `deadline` for a player that has no connection yet, such as the
+    /// players of a match resumed from a save. Only a client with `token` can take it.
+    pub fn hold_player(
+        &mut self,
+        player_id: PlayerId,
+        token: Option<SessionToken>,
+        deadline: Duration,
+    ) {
+        if let Some(token) = token {
+            self.tokens.insert(token, player_id);
+        }
+        self.held.insert(player_id, deadline);
+    }
+
+    /// Stops holding the seat of a player that will never connect, like a bot
+    pub fn release(&mut self, player_id: PlayerId) {
+        self.held.remove(&player_id);
+    }
+
+    /// Keeps the seat of a disconnected client open until `deadline`
+    pub fn hold(&mut self, client_id: u64, deadline: Duration) -> Option<PlayerId> {
+        let player_id = self.clients.remove(&client_id)?;
+        self.held.insert(player_id, deadline);
+        Some(player_id)
+    }
+
+    /// Lets a client that passed the handshake watch the match without a seat
+    pub fn spectate(&mut self, client_id: u64, name: String) {
+        self.spectators.insert(client_id, name);
+    }
+
+    /// Stops a client from watching, returning false if it wasnt
+    pub fn stop_spectating(&mut self, client_id: u64) -> bool {
+        self.spectators.remove(&client_id).is_some()
+    }
+
+    /// Forgets about a disconnected client and its session for good
+    pub fn end(&mut self, client_id: u64) -> Option<PlayerId> {
+        self.pending.remove(&client_id);
+        self.spectators.remove(&client_id);
+        let player_id = self.clients.remove(&client_id)?;
+        self.tokens.retain(|_, id| *id != player_id);
+        Some(player_id)
+    }
+
+    /// Whether the seat of a player is being held for them while they are disconnected
+    pub fn is_held(&self, player_id: PlayerId) -> bool {
+        self.held.contains_key(&player_id)
+    }
+
+    fn new_token(&self) -> SessionToken {
+        // 0 is reserved for "no token"
+        let mut token = 0;
+        while token == 0 || self.tokens.contains_key(&token) {
+            token = rand::random();
+        }
+        token
+    }
+
+    /// The token of every player that has one, by player
+    pub fn tokens(&self) -> HashMap<PlayerId, SessionToken> {
+        self.tokens
+            .iter()
+            .map(|(token, player_id)| (*player_id, *token))
+            .collect()
+    }
+
+    /// The player a connected client is sat as
+    pub fn player_id(&self, client_id: u64) -> Option<PlayerId> {
+        self.clients.get(&client_id).copied()
+    }
+
+    /// Every connected client that is sat as a player
+    pub fn seated_clients(&self) -> impl Iterator<Item = u64> + '_ {
+        self.clients.keys().copied()
+    }
+
+    pub fn is_spectating(&self, client_id: u64) -> bool {
+        self.spectators.contains_key(&client_id)
+    }
+
+    /// Every client watching the match
+    pub fn spectators(&self) -> impl Iterator<Item = u64> + '_ {
+        self.spectators.keys().copied()
+    }
+
+    /// The name a client watching the match gave
+    pub fn spectator_name(&self, client_id: u64) -> Option<&str> {
+        self.spectators.get(&client_id).map(String::as_str)
+    }
+
+    /// Whether a client has connected but not yet sent its handshake
+    pub fn is_pending(&self, client_id: u64) -> bool {
+        self.pending.contains_key(&client_id)
+    }
+
+    /// The names of everyone watching the match
+    pub fn spectator_names(&self) -> Vec<String> {
+        self.spectators.values().cloned().collect()
+    }
+}
+
+fn expire_sessions(
+    time: Res<Time>,
+    mut sessions: ResMut<Sessions>,
+    mut expired: EventWriter<SessionExpired>,
+) {
+    let now = time.time_since_startup();
+    let expired_players: Vec<PlayerId> = sessions
+        .held
+        .iter()
+        .filter(|(_, deadline)| **deadline <= now)
+        .map(|(player_id, _)| *player_id)
+        .collect();
+
+    for player_id in expired_players {
+        sessions.held.remove(&player_id);
+        sessions.tokens.retain(|_, id| *id != player_id);
+        expired.send(SessionExpired { player_id });
+    }
+}
+
+/// Drops clients that connected but never sent their handshake, so they dont hold on to a slot
+fn drop_silent_clients(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    mut packet_stats: ResMut<PacketStats>,
+    mut sessions: ResMut<Sessions>,
+) {
+    let now = time.time_since_startup();
+    let silent_clients: Vec<u64> = sessions
+        .pending
+        .iter()
+        .filter(|(_, (_, deadline))| *deadline <= now)
+        .map(|(client_id, _)| *client_id)
+        .collect();
+
+    for client_id in silent_clients {
+        info!("Client {} did not send its handshake in time.", client_id);
+        crate::kick_client(
+            client_id,
+            DisconnectReason::HandshakeTimeout,
+            &mut server,
+            &mut packet_stats,
+            &mut sessions,
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+
+use bevy::{prelude::*, utils::Duration};
+use bevy_renet::renet::RenetServer;
+use iyes_loopless::prelude::*;
+use shared::{
+    buildings::Buildings, hash, messages::ServerMessage, terrain::Terrain, units::Units, GameEvent,
+    GameState,
+};
+
+use crate::plugins::sessions::Sessions;
+use crate::{AppState, PacketStats};
+
+/// Keeps spectators up to date with the match, `delay` behind the players so they cant be used
+/// to feed a player what the others are doing
+pub struct SpectatorsPlugin {
+    pub delay: Duration,
+}
+
+impl Plugin for SpectatorsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SpectatorFeed {
+            delay: self.delay,
+            seen: Vec::new(),
+            game_state: GameState::default(),
+            synced: HashSet::new(),
+        });
+        // Runs after the server has handled this frame's events, so spectators without a delay
+        // get them in the same frame as the players
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            feed_spectators.run_in_state(AppState::ServerListening),
+        );
+    }
+}
+
+/// The match as spectators get to see it. Everyone watching sees the same thing.
+pub struct SpectatorFeed {
+    delay: Duration,
+    /// Every event of the match along with when it happened
+    seen: Vec<(GameEvent, Duration)>,
+    /// The match as it was `delay` ago
+    game_state: GameState,
+    /// Spectators that have been sent the game_state, and only need the events after it
+    synced: HashSet<u64>,
+}
+
+impl SpectatorFeed {
+    /// Sends a spectator the whole GameState again, like after it fell out of sync
+    pub fn resync(&mut self, client_id: u64) {
+        self.synced.remove(&client_id);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn feed_spectators(
+    time: Res<Time>,
+    mut feed: ResMut<SpectatorFeed>,
+    mut server: ResMut<RenetServer>,
+    mut packet_stats: ResMut<PacketStats>,
+    sessions: Res<Sessions>,
+    game_state: Res<GameState>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    let now = time.time_since_startup();
+    let feed = &mut *feed;
+
+    // Forget about events that were undone, and note down when the new ones happened
+    let agreed = feed
+        .seen
+        .iter()
+        .zip(game_state.histroy.iter())
+        .take_while(|((seen, _), event)| seen == *event)
+        .count();
+    feed.seen.truncate(agreed);
+    feed.seen.extend(
+        game_state.histroy[agreed..]
+            .iter()
+            .map(|event| (event.clone(), now)),
+    );
+
+    let visible = feed
+        .seen
+        .iter()
+        .take_while(|(_, happened)| *happened + feed.delay <= now)
+        .count();
+    let shown = feed.game_state.histroy.len();
+    let in_sync = feed.game_state.initial_board == game_state.initial_board
+        && shown <= visible
+        && feed.seen[..shown]
+            .iter()
+            .map(|(event, _)| event)
+            .eq(feed.game_state.histroy.iter());
+
+    let spectators: Vec<u64> = sessions.spectators().collect();
+    feed.synced
+        .retain(|client_id| spectators.contains(client_id));
+
+    if in_sync {
+        for index in shown..visible {
+            let event = feed.seen[index].0.clone();
+            feed.game_state
+                .consume(&event, &buildings, &units, &terrain);
+            let message = ServerMessage::GameEvent {
+                index,
+                event,
+                state_hash: hash::state_hash(&feed.game_state),
+            };
+            for client_id in feed.synced.iter() {
+                crate::send(&mut server, &mut packet_stats, *client_id, message.clone());
+            }
+        }
+    } else {
+        // Something spectators already saw was taken back, or the match was swapped for a resumed
+        // one, so they start over from what is left
+        let events: Vec<GameEvent> = feed.seen[..visible]
+            .iter()
+            .map(|(event, _)| event.clone())
+            .collect();
+        feed.game_state = GameState::from_events(
+            &game_state.initial_board,
+            &events,
+            &buildings,
+            &units,
+            &terrain,
+        );
+        feed.synced.clear();
+    }
+
+    // New spectators, and ones that need it again, start from a snapshot
+    for client_id in spectators {
+        if feed.synced.insert(client_id) {
+            crate::send(
+                &mut server,
+                &mut packet_stats,
+                client_id,
+                ServerMessage::StateSnapshot(Box::new(feed.game_state.clone())),
+            );
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Instant, SystemTime},
+};
+
+use bevy::{prelude::*, utils::Duration};
+use bevy_renet::renet::{ConnectToken, RenetServer, NETCODE_KEY_BYTES};
+use log::{info, warn};
+use shared::{
+    auth::{token_service_addr, TokenRequest},
+    connection::ConnectInfo,
+};
+
+use crate::plugins::abuse::{AbuseGuard, BanList};
+
+// How long a client has to use its token after getting it
+const TOKEN_EXPIRE_SECONDS: u64 = 300;
+// How long the connection can go quiet before it is dropped
+const TOKEN_TIMEOUT_SECONDS: i32 = 15;
+// How long a client gets to send its whole request, however slowly it sends it
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Requests are a name, a session token and a password, anything longer isnt read
+const MAX_REQUEST_LEN: u64 = 4096;
+
+/// Hands out connect tokens signed with the server's private key over plain tcp, to clients that
+/// know the server password.
+///
+/// Only needed when the server runs with secure authentication. Addresses on the ban list dont
+/// get a token. The password goes over the wire in the clear, so anything beyond a trusted
+/// network should reach the service through a tls proxy.
+pub struct TokenServicePlugin {
+    pub private_key: [u8; NETCODE_KEY_BYTES],
+    pub password: String,
+}
+
+/// The key tokens are signed with, and the password clients need to get one
+struct TokenServiceKey {
+    private_key: [u8; NETCODE_KEY_BYTES],
+    password: String,
+}
+
+impl Plugin for TokenServicePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TokenServiceKey {
+            private_key: self.private_key,
+            password: self.password.clone(),
+        });
+        app.add_startup_system(start_token_service);
+    }
+}
+
+fn start_token_service(
+    server: Res<RenetServer>,
+    key: Res<TokenServiceKey>,
+    guard: Res<AbuseGuard>,
+) {
+    let service_addr = token_service_addr();
+    let listener = TcpListener::bind(service_addr).unwrap();
+    info!("Token service listening on {}", service_addr);
+
+    let issuer = Issuer {
+        server_addr: server.addr(),
+        private_key: key.private_key,
+        password: key.password.clone(),
+        bans: guard.bans.clone(),
+        // Client ids are handed out here now, so they can just count up
+        next_client_id: AtomicU64::new(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+        ),
+    };
+    thread::spawn(move || run_token_service(listener, Arc::new(issuer)));
+}
+
+/// Everything needed to hand out tokens, shared by the threads serving the requests
+struct Issuer {
+    server_addr: SocketAddr,
+    private_key: [u8; NETCODE_KEY_BYTES],
+    password: String,
+    bans: BanList,
+    next_client_id: AtomicU64,
+}
+
+fn run_token_service(listener: TcpListener, issuer: Arc<Issuer>) {
+    for stream in listener.incoming() {
+        match stream {
+            // Each request gets a thread of its own, so a slow client only holds up itself
+            Ok(stream) => {
+                let issuer = issuer.clone();
+                thread::spawn(move || issuer.serve(stream));
+            }
+            Err(err) => warn!("Token request failed: {}", err),
+        }
+    }
+}
+
+impl Issuer {
+    fn serve(&self, mut stream: TcpStream) {
+        let addr = match stream.peer_addr() {
+            Ok(addr) if !self.bans.is_banned(addr.ip()) => addr,
+            Ok(addr) => {
+                info!("Not issuing a token to banned address {}", addr);
+                return;
+            }
+            Err(err) => {
+                warn!("Token request failed: {}", err);
+                return;
+            }
+        };
+
+        let request = match read_request(&stream) {
+            Ok(request) if passwords_match(&request.password, &self.password) => request,
+            Ok(_) => {
+                warn!("Wrong server password from {}", addr);
+                return;
+            }
+            Err(err) => {
+                warn!("Could not read token request from {}: {}", addr, err);
+                return;
+            }
+        };
+
+        let client_id = self.next_client_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.bans.note_client(client_id, addr.ip());
+        if let Err(err) = issue_token(
+            &mut stream,
+            request,
+            client_id,
+            self.server_addr,
+            &self.private_key,
+        ) {
+            warn!("Could not issue token to {}: {}", addr, err);
+        }
+    }
+}
+
+/// Reads a stream until a deadline, the read timeout alone would let a client that trickles in
+/// a byte at a time keep going for as long as it likes
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<TokenRequest> {
+    let reader = DeadlineReader {
+        stream,
+        deadline: Instant::now() + REQUEST_TIMEOUT,
+    };
+    let mut line = String::new();
+    BufReader::new(reader.take(MAX_REQUEST_LEN)).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request is cut off or too long",
+        ));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Compares passwords in a time that only depends on their length, so how long it takes gives
+/// away nothing about how close a guess was
+fn passwords_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn issue_token(
+    stream: &mut TcpStream,
+    request: TokenRequest,
+    client_id: u64,
+    server_addr: SocketAddr,
+    private_key: &[u8; NETCODE_KEY_BYTES],
+) -> io::Result<()> {
+    let connect_info = ConnectInfo {
+        name: request.name,
+        session_token: request.session_token,
+        spectator: request.spectator,
+    };
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let token = ConnectToken::generate(
+        current_time,
+        shared::PROTOCOL_ID,
+        TOKEN_EXPIRE_SECONDS,
+        client_id,
+        TOKEN_TIMEOUT_SECONDS,
+        vec![server_addr],
+        Some(&connect_info.to_user_data()),
+        private_key,
+    )
+    .map_err(|err| io::Error::other(format!("{:?}", err)))?;
+
+    stream.write_all(&client_id.to_le_bytes())?;
+    token.write(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A connected pair of streams, the first being the server's end
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (server, client)
+    }
+
+    #[test]
+    fn passwords_have_to_match_exactly() {
+        assert!(passwords_match("hunter2", "hunter2"));
+        assert!(!passwords_match("hunter3", "hunter2"));
+        assert!(!passwords_match("hunter", "hunter2"));
+        assert!(!passwords_match("", "hunter2"));
+    }
+
+    #[test]
+    fn requests_are_read_up_to_the_newline() {
+        let (server, mut client) = connection();
+        let request = TokenRequest {
+            name: "Alice".to_string(),
+            session_token: None,
+            spectator: false,
+            password: "hunter2".to_string(),
+        };
+        let line = serde_json::to_string(&request).unwrap() + "\n";
+        client.write_all(line.as_bytes()).unwrap();
+
+        assert_eq!(read_request(&server).unwrap().name, "Alice");
+    }
+
+    #[test]
+    fn long_requests_are_not_read() {
+        let (server, mut client) = connection();
+        // Keeps the connection open, so only the length can stop the read
+        client
+            .write_all(&[b'a'; MAX_REQUEST_LEN as usize * 2])
+            .unwrap();
+
+        let err = read_request(&server).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+
+use bevy::{prelude::*, utils::Duration};
+use bevy_renet::renet::RenetServer;
+use iyes_loopless::prelude::*;
+use log::info;
+use shared::{
+    buildings::Buildings, messages::ServerMessage, terrain::Terrain, units::Units, GameEvent,
+    GameState, PlayerId, Stage,
+};
+
+use crate::plugins::sessions::Sessions;
+use crate::{AppState, PacketStats};
+
+/// How much time players get to take their turns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeControl {
+    Unlimited,
+    /// Every turn has to be over within `limit`
+    PerTurn {
+        limit: Duration,
+    },
+    /// Every player has a clock of `initial` that only runs during their own turns, and gets
+    /// `increment` added to it after each turn they finish
+    ChessClock {
+        initial: Duration,
+        increment: Duration,
+    },
+}
+
+/// What happens to a player that runs out of time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutAction {
+    /// Their turn is ended for them
+    EndTurn,
+    /// They are eliminated from the match
+    Forfeit,
+}
+
+// How often the countdown is sent out again during a turn
+const TIMER_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keeps players from stalling the match by giving turns a time limit
+pub struct TurnTimerPlugin {
+    pub time_control: TimeControl,
+    pub on_timeout: TimeoutAction,
+}
+
+impl Plugin for TurnTimerPlugin {
+    fn build(&self, app: &mut App) {
+        if self.time_control == TimeControl::Unlimited {
+            return;
+        }
+
+        app.insert_resource(TurnClock {
+            time_control: self.time_control,
+            on_timeout: self.on_timeout,
+            turn: None,
+            turn_started: Duration::ZERO,
+            last_broadcast: Duration::ZERO,
+            clocks: BTreeMap::new(),
+        });
+        // Runs after the server has handled this frame's events, so turns that just ended
+        // are not timed out
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            run_turn_clock.run_in_state(AppState::ServerListening),
+        );
+    }
+}
+
+/// Whose turn is being timed, and how much time everyone has left
+pub struct TurnClock {
+    time_control: TimeControl,
+    on_timeout: TimeoutAction,
+    /// The player and round of the turn being timed
+    turn: Option<(PlayerId, u32)>,
+    turn_started: Duration,
+    last_broadcast: Duration,
+    /// Time left on each player's chess clock, not counting the turn being timed
+    clocks: BTreeMap<PlayerId, Duration>,
+}
+
+impl TurnClock {
+    /// Time the player whose turn it is has left, as of `now`
+    fn remaining(&self, player_id: PlayerId, now: Duration) -> Duration {
+        let available = match self.time_control {
+            TimeControl::Unlimited => return Duration::MAX,
+            TimeControl::PerTurn { limit } => limit,
+            TimeControl::ChessClock { initial, .. } => {
+                self.clocks.get(&player_id).copied().unwrap_or(initial)
+            }
+        };
+        available.saturating_sub(now.saturating_sub(self.turn_started))
+    }
+
+    /// Stops the clock of whoever was playing, and starts timing the turn of `player_id`
+    fn start_turn(&mut self, turn: (PlayerId, u32), now: Duration) {
+        if let (Some((previous, _)), TimeControl::ChessClock { increment, .. }) =
+            (self.turn, self.time_control)
+        {
+            let left = self.remaining(previous, now);
+            self.clocks.insert(previous, left + increment);
+        }
+
+        self.turn = Some(turn);
+        self.turn_started = now;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_turn_clock(
+    time: Res<Time>,
+    mut clock: ResMut<TurnClock>,
+    mut server: ResMut<RenetServer>,
+    mut packet_stats: ResMut<PacketStats>,
+    sessions: Res<Sessions>,
+    mut game_state: ResMut<GameState>,
+    buildings: Res<Buildings>,
+    units: Res<Units>,
+    terrain: Res<Terrain>,
+) {
+    if game_state.stage != Stage::InGame {
+        clock.turn = None;
+        return;
+    }
+
+    let now = time.time_since_startup();
+    let turn = (game_state.active_player_id, game_state.round);
+    if clock.turn != Some(turn) {
+        clock.start_turn(turn, now);
+        broadcast_timer(&mut server, &mut packet_stats, &sessions, &mut clock, now);
+        return;
+    }
+
+    let player_id = turn.0;
+    if !clock.remaining(player_id, now).is_zero() {
+        // Every now and then, so clients that just (re)joined get to see the countdown too
+        if now - clock.last_broadcast >= TIMER_SYNC_INTERVAL {
+            broadcast_timer(&mut server, &mut packet_stats, &sessions, &mut clock, now);
+        }
+        return;
+    }
+
+    let event = match clock.on_timeout {
+        TimeoutAction::EndTurn => {
+            info!("Player {} ran out of time, ending their turn.", player_id);
+            GameEvent::EndTurn { player_id }
+        }
+        TimeoutAction::Forfeit => {
+            info!("Player {} ran out of time and forfeits.", player_id);
+            GameEvent::PlayerEliminated { player_id }
+        }
+    };
+    if !game_state.validate(&event, &buildings, &units, &terrain) {
+        return;
+    }
+    crate::apply_event(
+        event,
+        &mut server,
+        &mut packet_stats,
+        &sessions,
+        &mut game_state,
+        &buildings,
+        &units,
+        &terrain,
+    );
+    crate::end_game_if_won(
+        &mut server,
+        &mut packet_stats,
+        &sessions,
+        &mut game_state,
+        &buildings,
+        &units,
+        &terrain,
+    );
+    crate::skip_held_turns(
+        &mut server,
+        &mut packet_stats,
+        &sessions,
+        &mut game_state,
+        &buildings,
+        &units,
+        &terrain,
+    );
+}
+
+/// Tells every player how long the player whose turn it is has left
+fn broadcast_timer(
+    server: &mut RenetServer,
+    packet_stats: &mut PacketStats,
+    sessions: &Sessions,
+    clock: &mut TurnClock,
+    now: Duration,
+) {
+    let (player_id, _) = match clock.turn {
+        Some(turn) => turn,
+        None => return,
+    };
+
+    let message = ServerMessage::TurnTimer {
+        player_id,
+        remaining_ms: clock.remaining(player_id, now).as_millis() as u64,
+    };
+    crate::broadcast(server, packet_stats, sessions, message);
+    clock.last_broadcast = now;
+}
@@ -0,0 +1,754 @@
+//! Runs a real server and real clients in one process, talking to each other over loopback.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::Path,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use bevy::prelude::*;
+use bevy_renet::{
+    renet::{ClientAuthentication, RenetClient, RenetServer},
+    RenetClientPlugin,
+};
+use iyes_loopless::prelude::*;
+use server::{
+    new_renet_server,
+    plugins::{admin::AdminPlugin, bots::BotsPlugin, sessions::HANDSHAKE_TIMEOUT},
+    AppState, PacketStats, ServerPlugin,
+};
+use shared::{
+    ai::Difficulty,
+    buildings::Buildings,
+    channels::{client_connection_config, ServerChannel},
+    chat::{ChatRejectReason, ChatScope, Emote, MAX_CHAT_LENGTH},
+    connection::ConnectInfo,
+    hash,
+    messages::{self, ClientMessage, DisconnectReason, Handshake, RejectReason, ServerMessage},
+    terrain::Terrain,
+    units::Units,
+    EndGameReason, GameEvent, GameState, MatchSettings, PlayerId, Stage,
+};
+
+const PLAYERS: usize = 2;
+
+// How far behind the players spectators see the match
+const SPECTATOR_DELAY: Duration = Duration::from_millis(300);
+
+// How long to wait on the server before giving up on it
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Renet hands out ids by the clock, tests pick their own so they are known up front
+const ALICE: PlayerId = 1;
+const BOB: PlayerId = 2;
+const CAROL: PlayerId = 3;
+const DAVE: PlayerId = 4;
+
+/// Everything a client got from the server, in the order it arrived
+#[derive(Default)]
+struct Inbox(Vec<ServerMessage>);
+
+/// Messages waiting to go out to the server until it is connected
+#[derive(Default)]
+struct Outbox(Vec<ClientMessage>);
+
+fn descriptors() -> (Buildings, Units, Terrain) {
+    let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("../dinojam2/assets");
+    server::descriptors::load(&assets)
+}
+
+fn server_app() -> App {
+    let (buildings, units, terrain) = descriptors();
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    // The descriptors are handed over below, so there is nothing to load
+    app.add_loopless_state(AppState::ServerListening);
+    app.add_plugin(ServerPlugin {
+        players: PLAYERS,
+        settings: MatchSettings::default(),
+        spectators: 1,
+        spectator_delay: SPECTATOR_DELAY,
+    });
+    app.insert_resource(buildings);
+    app.insert_resource(units);
+    app.insert_resource(terrain);
+    app.insert_resource(new_renet_server(
+        "127.0.0.1:0".parse().unwrap(),
+        PLAYERS + 2,
+        None,
+    ));
+    app
+}
+
+fn client_app(server_addr: SocketAddr, client_id: u64, name: &str, spectator: bool) -> App {
+    let (buildings, units, terrain) = descriptors();
+    let handshake = Handshake::new(&buildings, &units, &terrain);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let connect_info = ConnectInfo {
+        name: name.to_string(),
+        session_token: None,
+        spectator,
+    };
+    let authentication = ClientAuthentication::Unsecure {
+        client_id,
+        protocol_id: shared::PROTOCOL_ID,
+        server_addr,
+        user_data: Some(connect_info.to_user_data()),
+    };
+    let client = RenetClient::new(
+        current_time,
+        socket,
+        client_id,
+        client_connection_config(),
+        authentication,
+    )
+    .unwrap();
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    app.add_plugin(RenetClientPlugin);
+    app.insert_resource(client);
+    app.init_resource::<Inbox>();
+    app.insert_resource(Outbox(vec![ClientMessage::Hello(handshake)]));
+    app.add_system(exchange_messages);
+    app
+}
+
+fn exchange_messages(
+    mut client: ResMut<RenetClient>,
+    mut inbox: ResMut<Inbox>,
+    mut outbox: ResMut<Outbox>,
+) {
+    if client.is_connected() {
+        for message in outbox.0.drain(..) {
+            client.send_message(message.channel(), messages::encode(&message));
+        }
+    }
+    // Keeps reading after a disconnect, the server says why it dropped us right before doing so
+    for channel in ServerChannel::ALL {
+        while let Some(message) = client.receive_message(channel) {
+            inbox.0.push(messages::decode(&message).unwrap());
+        }
+    }
+}
+
+/// A server and the clients connected to it, updated in lockstep
+struct Match {
+    server: App,
+    clients: Vec<(PlayerId, App)>,
+}
+
+impl Match {
+    fn new() -> Self {
+        let mut server = server_app();
+        // Runs the startup systems
+        server.update();
+        Self {
+            server,
+            clients: Vec::new(),
+        }
+    }
+
+    fn update(&mut self) {
+        self.server.update();
+        for (_, client) in self.clients.iter_mut() {
+            client.update();
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
+
+    /// Updates everything until `done` holds, failing the test if that takes too long
+    fn run_until(&mut self, what: &str, done: impl FnMut(&Self) -> bool) {
+        self.run_within(TIMEOUT, what, done);
+    }
+
+    /// Like run_until, for things the server takes its time with on purpose
+    fn run_within(&mut self, timeout: Duration, what: &str, mut done: impl FnMut(&Self) -> bool) {
+        let started = Instant::now();
+        while !done(self) {
+            assert!(
+                started.elapsed() < timeout,
+                "Timed out waiting for {}",
+                what
+            );
+            self.update();
+        }
+    }
+
+    /// Connects a client and waits until the server has decided what to do with it
+    fn connect(&mut self, client_id: u64, name: &str) {
+        let addr = self.server.world.resource::<RenetServer>().addr();
+        self.clients
+            .push((client_id, client_app(addr, client_id, name, false)));
+        self.run_until(name, |game| {
+            game.inbox(client_id).iter().any(|message| {
+                matches!(
+                    message,
+                    ServerMessage::Session(_) | ServerMessage::Disconnect(_)
+                )
+            })
+        });
+    }
+
+    /// Connects a client as a spectator and waits until it has been shown the board
+    fn watch(&mut self, client_id: u64, name: &str) {
+        let addr = self.server.world.resource::<RenetServer>().addr();
+        self.clients
+            .push((client_id, client_app(addr, client_id, name, true)));
+        self.run_until(name, |game| {
+            game.inbox(client_id).iter().any(|message| {
+                matches!(
+                    message,
+                    ServerMessage::StateSnapshot(_) | ServerMessage::Disconnect(_)
+                )
+            })
+        });
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        let index = self
+            .clients
+            .iter()
+            .position(|(id, _)| *id == client_id)
+            .unwrap();
+        let (_, mut client) = self.clients.remove(index);
+        client.world.resource_mut::<RenetClient>().disconnect();
+    }
+
+    fn send(&mut self, client_id: u64, message: ClientMessage) {
+        self.client(client_id)
+            .world
+            .resource_mut::<Outbox>()
+            .0
+            .push(message);
+    }
+
+    fn client(&mut self, client_id: u64) -> &mut App {
+        self.clients
+            .iter_mut()
+            .find(|(id, _)| *id == client_id)
+            .map(|(_, client)| client)
+            .unwrap()
+    }
+
+    fn inbox(&self, client_id: u64) -> &[ServerMessage] {
+        let (_, client) = self
+            .clients
+            .iter()
+            .find(|(id, _)| *id == client_id)
+            .unwrap();
+        &client.world.resource::<Inbox>().0
+    }
+
+    /// The events a client was sent one by one, along with their place in the history
+    fn events(&self, client_id: u64) -> Vec<(usize, GameEvent)> {
+        self.inbox(client_id)
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessage::GameEvent { index, event, .. } => Some((*index, event.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn game_state(&self) -> &GameState {
+        self.server.world.resource::<GameState>()
+    }
+
+    /// Plays both players into a game, with Bob going first
+    fn begin() -> Self {
+        let mut game = Self::new();
+        game.connect(ALICE, "Alice");
+        game.connect(BOB, "Bob");
+        game.run_until("the game to begin", |game| {
+            game.events(ALICE).len() == 4 && game.events(BOB).len() == 2
+        });
+        game
+    }
+}
+
+#[test]
+fn players_join_and_take_turns() {
+    let mut game = Match::begin();
+
+    // Alice was there for everything, Bob gets the start of it in his snapshot
+    let settings = MatchSettings::default();
+    let joined = |player_id, name: &str| GameEvent::PlayerJoined {
+        player_id,
+        name: name.to_string(),
+    };
+    assert_eq!(
+        game.events(ALICE),
+        vec![
+            (0, GameEvent::ConfigureMatch { settings }),
+            (1, joined(ALICE, "Alice")),
+            (2, joined(BOB, "Bob")),
+            (3, GameEvent::BeginGame { goes_first: BOB }),
+        ]
+    );
+    let snapshot = game.inbox(BOB).iter().find_map(|message| match message {
+        ServerMessage::StateSnapshot(snapshot) => Some(*snapshot.clone()),
+        _ => None,
+    });
+    assert_eq!(snapshot.unwrap().histroy.len(), 2);
+    assert_eq!(game.events(BOB), game.events(ALICE)[2..].to_vec());
+    for player_id in [ALICE, BOB] {
+        assert!(game.inbox(player_id).iter().any(|message| {
+            matches!(message, ServerMessage::Session(session) if session.player_id == player_id)
+        }));
+    }
+
+    // It is not Alice's turn, and she can't end Bob's for him either
+    let out_of_turn = GameEvent::EndTurn { player_id: ALICE };
+    let for_bob = GameEvent::EndTurn { player_id: BOB };
+    game.send(ALICE, ClientMessage::GameEvent(out_of_turn.clone()));
+    game.send(ALICE, ClientMessage::GameEvent(for_bob.clone()));
+    game.run_until("the rejections", |game| {
+        game.inbox(ALICE)
+            .iter()
+            .filter(|message| matches!(message, ServerMessage::Rejected { .. }))
+            .count()
+            == 2
+    });
+    let rejections: Vec<_> = game
+        .inbox(ALICE)
+        .iter()
+        .filter(|message| matches!(message, ServerMessage::Rejected { .. }))
+        .cloned()
+        .collect();
+    assert_eq!(
+        rejections,
+        vec![
+            ServerMessage::Rejected {
+                event: out_of_turn,
+                reason: RejectReason::Invalid,
+            },
+            ServerMessage::Rejected {
+                event: for_bob.clone(),
+                reason: RejectReason::NotYourPlayer,
+            },
+        ]
+    );
+
+    // Bob ends his turn, then Alice hers, and everyone hears about both
+    game.send(BOB, ClientMessage::GameEvent(for_bob.clone()));
+    game.run_until("Bob's turn to end", |game| game.events(ALICE).len() == 5);
+    game.send(
+        ALICE,
+        ClientMessage::GameEvent(GameEvent::EndTurn { player_id: ALICE }),
+    );
+    game.run_until("Alice's turn to end", |game| {
+        game.events(ALICE).len() == 6 && game.events(BOB).len() == 4
+    });
+
+    let turns = vec![(4, for_bob), (5, GameEvent::EndTurn { player_id: ALICE })];
+    assert_eq!(game.events(ALICE)[4..].to_vec(), turns);
+    assert_eq!(game.events(BOB)[2..].to_vec(), turns);
+    assert_eq!(game.game_state().active_player_id, BOB);
+
+    // The hash sent along with the last event is the one the server ended up with
+    let last_hash = game
+        .inbox(BOB)
+        .iter()
+        .rev()
+        .find_map(|message| match message {
+            ServerMessage::GameEvent { state_hash, .. } => Some(*state_hash),
+            _ => None,
+        });
+    assert_eq!(last_hash, Some(hash::state_hash(game.game_state())));
+}
+
+#[test]
+fn full_match_turns_new_players_away() {
+    let mut game = Match::begin();
+
+    game.connect(CAROL, "Carol");
+    assert!(game
+        .inbox(CAROL)
+        .contains(&ServerMessage::Disconnect(DisconnectReason::MatchFull)));
+    assert!(!game
+        .inbox(CAROL)
+        .iter()
+        .any(|message| matches!(message, ServerMessage::Session(_))));
+
+    // Nobody else noticed a thing
+    assert_eq!(game.events(ALICE).len(), 4);
+    assert_eq!(game.game_state().players.len(), PLAYERS);
+}
+
+#[test]
+fn clients_cant_take_the_id_of_someone_already_playing() {
+    let mut server = server_app();
+    server.add_plugin(BotsPlugin {
+        count: 1,
+        difficulty: Difficulty::Easy,
+    });
+    server.update();
+    let mut game = Match {
+        server,
+        clients: Vec::new(),
+    };
+    let bot = PlayerId::MAX;
+    game.run_until("the bot to join", |game| {
+        game.game_state().players.contains_key(&bot)
+    });
+    let name = game.game_state().players[&bot].name.clone();
+
+    // Unsecure clients pick their own id, this one picked the bot's
+    game.connect(bot, "Mallory");
+    assert!(game
+        .inbox(bot)
+        .contains(&ServerMessage::Disconnect(DisconnectReason::PlayerIdTaken)));
+    assert!(!game
+        .inbox(bot)
+        .iter()
+        .any(|message| matches!(message, ServerMessage::Session(_))));
+    assert_eq!(game.game_state().players.len(), 1);
+    assert_eq!(game.game_state().players[&bot].name, name);
+}
+
+#[test]
+fn disconnected_player_has_their_seat_held_and_turn_skipped() {
+    let mut game = Match::begin();
+
+    // Hand the turn to Alice, then have her drop out
+    game.send(
+        BOB,
+        ClientMessage::GameEvent(GameEvent::EndTurn { player_id: BOB }),
+    );
+    game.run_until("Bob's turn to end", |game| game.events(BOB).len() == 3);
+    game.disconnect(ALICE);
+
+    game.run_until("Alice's turn to be skipped", |game| {
+        game.events(BOB).len() == 4
+    });
+    assert_eq!(
+        game.events(BOB)[3],
+        (5, GameEvent::EndTurn { player_id: ALICE })
+    );
+    game.run_until("the lobby to show Alice away", |game| {
+        game.inbox(BOB).iter().any(|message| match message {
+            ServerMessage::Lobby(players) => players
+                .iter()
+                .any(|player| player.player_id == ALICE && !player.connected),
+            _ => false,
+        })
+    });
+
+    // The game goes on without her, her seat waits for her to come back
+    assert_eq!(game.game_state().stage, Stage::InGame);
+    assert_eq!(game.game_state().active_player_id, BOB);
+    assert!(game.game_state().players.contains_key(&ALICE));
+}
+
+#[test]
+fn spectators_watch_the_match_behind_the_players() {
+    let mut game = Match::begin();
+    game.watch(DAVE, "Dave");
+
+    // Everyone gets to know Dave is watching, but he doesnt take a seat
+    for client_id in [ALICE, BOB, DAVE] {
+        let spectators = ServerMessage::Spectators(vec!["Dave".to_string()]);
+        game.run_until("the spectator list", |game| {
+            game.inbox(client_id).contains(&spectators)
+        });
+    }
+    assert!(!game
+        .inbox(DAVE)
+        .iter()
+        .any(|message| matches!(message, ServerMessage::Session(_))));
+    assert_eq!(game.game_state().players.len(), PLAYERS);
+
+    // Dave only sees Bob end his turn a while after the players do
+    let for_bob = GameEvent::EndTurn { player_id: BOB };
+    game.send(BOB, ClientMessage::GameEvent(for_bob.clone()));
+    game.run_until("Bob's turn to end", |game| game.events(ALICE).len() == 5);
+    assert!(!game.events(DAVE).contains(&(4, for_bob.clone())));
+    game.run_until("Dave to see Bob's turn end", |game| {
+        game.events(DAVE).contains(&(4, for_bob.clone()))
+    });
+
+    // Watching is all he can do
+    let for_alice = GameEvent::EndTurn { player_id: ALICE };
+    game.send(DAVE, ClientMessage::GameEvent(for_alice.clone()));
+    game.run_until("the rejection", |game| {
+        game.inbox(DAVE).contains(&ServerMessage::Rejected {
+            event: for_alice.clone(),
+            reason: RejectReason::NotSeated,
+        })
+    });
+    assert_eq!(game.game_state().active_player_id, ALICE);
+}
+
+#[test]
+fn spectators_get_events_no_sooner_than_the_delay() {
+    let mut game = Match::begin();
+    game.watch(DAVE, "Dave");
+
+    let for_bob = (4, GameEvent::EndTurn { player_id: BOB });
+    game.send(BOB, ClientMessage::GameEvent(for_bob.1.clone()));
+    game.run_until("Alice to see Bob's turn end", |game| {
+        game.events(ALICE).contains(&for_bob)
+    });
+    let seen_by_alice = Instant::now();
+    game.run_until("Dave to see Bob's turn end", |game| {
+        game.events(DAVE).contains(&for_bob)
+    });
+
+    // Alice might only have noticed a frame after the server sent it
+    let behind = seen_by_alice.elapsed();
+    assert!(
+        behind + Duration::from_millis(50) >= SPECTATOR_DELAY,
+        "Dave was only {:?} behind",
+        behind
+    );
+
+    // And everything he is sent builds on what he saw before
+    let hashes: Vec<u64> = game
+        .inbox(DAVE)
+        .iter()
+        .filter_map(|message| match message {
+            ServerMessage::GameEvent { state_hash, .. } => Some(*state_hash),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(hashes.last(), Some(&hash::state_hash(game.game_state())));
+}
+
+#[test]
+fn admins_can_kick_clients_and_end_the_game() {
+    // Whatever port was free a moment ago
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut server = server_app();
+    server.add_plugin(AdminPlugin {
+        stdin: false,
+        port: Some(port),
+        password: "hunter2".to_string(),
+    });
+    server.update();
+    let mut game = Match {
+        server,
+        clients: Vec::new(),
+    };
+    game.connect(ALICE, "Alice");
+    game.connect(BOB, "Bob");
+    game.run_until("the game to begin", |game| {
+        game.game_state().stage == Stage::InGame
+    });
+
+    // The admin waits on the server to answer, which only happens while the match runs
+    let admin = thread::spawn(move || {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let mut answers = BufReader::new(stream.try_clone().unwrap());
+        let mut prompt = [0; 10];
+        answers.read_exact(&mut prompt).unwrap();
+        assert_eq!(&prompt, b"password: ");
+        writeln!(stream, "hunter2").unwrap();
+
+        let answer = |command: &str| {
+            writeln!(stream, "{}", command).unwrap();
+            let mut line = String::new();
+            answers.read_line(&mut line).unwrap();
+            line
+        };
+        let answers = ["nonsense", "kick 9", "end", "end", "kick 1"].map(answer);
+        stream.write_all(b"quit\n").unwrap();
+        answers
+    });
+    game.run_until("the admin to be done", |_| admin.is_finished());
+    let answers = admin.join().unwrap();
+
+    // Every answer comes after the prompt for the command
+    assert_eq!(
+        answers,
+        [
+            "> Unknown command \"nonsense\", try help\n",
+            "> There is no client 9\n",
+            "> Ended the game\n",
+            "> The game is already over\n",
+            "> Kicked client 1\n",
+        ]
+    );
+    game.run_until("Alice to hear she was kicked", |game| {
+        game.inbox(ALICE)
+            .contains(&ServerMessage::Disconnect(DisconnectReason::Kicked))
+    });
+    let aborted = GameEvent::EndGame {
+        reason: EndGameReason::Aborted,
+    };
+    game.run_until("Bob to see the game end", |game| {
+        game.events(BOB).iter().any(|(_, event)| *event == aborted)
+    });
+    assert_eq!(game.game_state().stage, Stage::Ended);
+}
+
+#[test]
+fn chat_reaches_the_right_players_and_stays_out_of_the_game() {
+    let mut game = Match::begin();
+    game.watch(DAVE, "Dave");
+    let events = game.game_state().histroy.len();
+
+    let chat = |scope, text: &str| ClientMessage::Chat {
+        scope,
+        text: text.to_string(),
+    };
+    let heard = |player_id, scope, text: &str| ServerMessage::Chat {
+        player_id,
+        scope,
+        text: text.to_string(),
+    };
+
+    // Everyone at the table hears Bob, but only Alice's own team hears her
+    game.send(BOB, chat(ChatScope::Everyone, "hi all"));
+    game.send(ALICE, chat(ChatScope::Team, "he wont see this"));
+    for client_id in [ALICE, BOB] {
+        game.run_until("Bob's message", |game| {
+            game.inbox(client_id)
+                .contains(&heard(BOB, ChatScope::Everyone, "hi all"))
+        });
+    }
+    game.run_until("Alice's team message", |game| {
+        game.inbox(ALICE)
+            .contains(&heard(ALICE, ChatScope::Team, "he wont see this"))
+    });
+
+    // Emotes go to every player
+    game.send(ALICE, ClientMessage::Emote(Emote::GoodLuck));
+    for client_id in [ALICE, BOB] {
+        game.run_until("Alice's emote", |game| {
+            game.inbox(client_id).contains(&ServerMessage::Emote {
+                player_id: ALICE,
+                emote: Emote::GoodLuck,
+            })
+        });
+    }
+
+    // Spectators only watch, and messages have to fit
+    game.send(DAVE, chat(ChatScope::Everyone, "psst, Bob"));
+    game.run_until("Dave's message to be refused", |game| {
+        game.inbox(DAVE)
+            .contains(&ServerMessage::ChatRejected(ChatRejectReason::NotSeated))
+    });
+    game.send(
+        BOB,
+        chat(ChatScope::Everyone, &"a".repeat(MAX_CHAT_LENGTH + 1)),
+    );
+    game.run_until("the long message to be refused", |game| {
+        game.inbox(BOB)
+            .contains(&ServerMessage::ChatRejected(ChatRejectReason::TooLong))
+    });
+
+    // Alice has said two things already, a few more and she has to slow down
+    for text in ["1", "2", "3", "4"] {
+        game.send(ALICE, chat(ChatScope::Everyone, text));
+    }
+    game.run_until("Alice to be slowed down", |game| {
+        game.inbox(ALICE)
+            .contains(&ServerMessage::ChatRejected(ChatRejectReason::TooFast))
+    });
+    game.run_until("the rest of Alice's messages", |game| {
+        game.inbox(BOB)
+            .contains(&heard(ALICE, ChatScope::Everyone, "3"))
+    });
+
+    assert!(!game
+        .inbox(BOB)
+        .contains(&heard(ALICE, ChatScope::Team, "he wont see this")));
+    assert!(!game
+        .inbox(BOB)
+        .contains(&heard(ALICE, ChatScope::Everyone, "4")));
+    assert!(!game.inbox(DAVE).iter().any(|message| matches!(
+        message,
+        ServerMessage::Chat { .. } | ServerMessage::Emote { .. }
+    )));
+    assert_eq!(game.game_state().histroy.len(), events);
+}
+
+#[test]
+fn players_that_keep_sending_invalid_actions_are_kicked() {
+    let mut game = Match::begin();
+
+    // It is Bob's turn, so none of these go through
+    let for_alice = GameEvent::EndTurn { player_id: ALICE };
+    for _ in 0..20 {
+        game.send(ALICE, ClientMessage::GameEvent(for_alice.clone()));
+    }
+    game.run_until("Alice to be kicked", |game| {
+        game.inbox(ALICE).contains(&ServerMessage::Disconnect(
+            DisconnectReason::TooManyInvalidActions,
+        ))
+    });
+
+    // She doesnt get her seat held, which leaves Bob on his own
+    game.run_until("the game to end", |game| {
+        game.game_state().stage == Stage::Ended
+    });
+    assert!(!game.events(BOB).contains(&(4, for_alice)));
+}
+
+#[test]
+fn clients_flooding_the_server_are_kicked() {
+    let mut game = Match::begin();
+
+    let spam = ClientMessage::Chat {
+        scope: ChatScope::Everyone,
+        text: "spam".to_string(),
+    };
+    for _ in 0..400 {
+        game.send(BOB, spam.clone());
+    }
+    game.run_until("Bob to be kicked", |game| {
+        game.inbox(BOB)
+            .contains(&ServerMessage::Disconnect(DisconnectReason::Flooding))
+    });
+
+    // Alice only heard the little the chat lets through
+    let heard = game
+        .inbox(ALICE)
+        .iter()
+        .filter(|message| matches!(message, ServerMessage::Chat { .. }))
+        .count();
+    assert!(heard <= 5, "Alice heard {} messages", heard);
+}
+
+#[test]
+fn clients_that_never_say_hello_are_dropped() {
+    let mut game = Match::new();
+
+    let addr = game.server.world.resource::<RenetServer>().addr();
+    let mut client = client_app(addr, CAROL, "Carol", false);
+    client.insert_resource(Outbox::default());
+    game.clients.push((CAROL, client));
+    game.run_within(HANDSHAKE_TIMEOUT + TIMEOUT, "Carol to be dropped", |game| {
+        game.inbox(CAROL).contains(&ServerMessage::Disconnect(
+            DisconnectReason::HandshakeTimeout,
+        ))
+    });
+    assert!(game.game_state().players.is_empty());
+}
+
+#[test]
+fn servers_count_the_bytes_they_send_on_their_own() {
+    let mut idle = Match::new();
+    let game = Match::begin();
+    idle.update();
+
+    let sent = |game: &Match| game.server.world.resource::<PacketStats>().bytes_sent;
+    let received = |game: &Match| game.server.world.resource::<PacketStats>().bytes_received;
+    assert!(sent(&game) > 0);
+    assert!(received(&game) > 0);
+    assert_eq!(sent(&idle), 0);
+    assert_eq!(received(&idle), 0);
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use super::{Bot, Difficulty, TurnCounter};
+use crate::{
+    buildings::{BuildingKind, Buildings},
+    hex,
+    terrain::Terrain,
+    units::Units,
+    EndGameReason, GameEvent, GameState, PlayerId, Stage, TeamId,
+};
+
+// What winning or losing is worth, more than anything else on the board could be
+const WIN_SCORE: f32 = 1000.0;
+// Value of a building per point of health it has left
+const BUILDING_WEIGHT: f32 = 2.0;
+// Gold is worth less than the units it can buy, so that the bot spends it
+const GOLD_WEIGHT: f32 = 0.5;
+// Value lost per tile between a unit and the closest thing it could attack
+const DISTANCE_WEIGHT: f32 = 0.1;
+
+// Actions have to be at least this much better than doing nothing for the bot to bother
+const MIN_IMPROVEMENT: f32 = 0.01;
+
+// How far a team has to be ahead of the rest to be given a game that had to stop early
+const LEAD_MARGIN: f32 = 1.0;
+
+/// A bot that tries every action it can take, and picks the one that leaves the board looking
+/// best for it
+pub struct HeuristicBot {
+    difficulty: Difficulty,
+    rng: StdRng,
+    turn: TurnCounter,
+}
+
+impl HeuristicBot {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self::with_seed(difficulty, rand::random())
+    }
+
+    /// A bot that makes the same choices every time it sees the same game
+    pub fn with_seed(difficulty: Difficulty, seed: u64) -> Self {
+        Self {
+            difficulty,
+            rng: StdRng::seed_from_u64(seed),
+            turn: TurnCounter::default(),
+        }
+    }
+
+    fn noise(&mut self) -> f32 {
+        let noise = self.difficulty.noise();
+        if noise == 0.0 {
+            return 0.0;
+        }
+        self.rng.gen_range(-noise..=noise)
+    }
+}
+
+impl Bot for HeuristicBot {
+    fn choose_action(
+        &mut self,
+        game_state: &GameState,
+        player_id: PlayerId,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Option<GameEvent> {
+        if game_state.stage != Stage::InGame || game_state.active_player_id != player_id {
+            return None;
+        }
+
+        let end_turn = GameEvent::EndTurn { player_id };
+        if self.turn.out_of_actions(game_state) {
+            return Some(end_turn);
+        }
+
+        let actions = game_state.legal_actions(player_id, buildings, units, terrain);
+        if self.rng.gen_bool(self.difficulty.blunder_chance()) {
+            return actions.choose(&mut self.rng).cloned();
+        }
+
+        // Ending the turn leaves the board as it is, anything else has to beat that
+        let current = evaluate(game_state, player_id, buildings, units);
+        let mut best = (current + MIN_IMPROVEMENT, end_turn);
+        for action in actions {
+            if matches!(action, GameEvent::EndTurn { .. }) {
+                continue;
+            }
+
+            let mut next = game_state.clone();
+            next.consume(&action, buildings, units, terrain);
+            let score = evaluate(&next, player_id, buildings, units) + self.noise();
+            if score > best.0 {
+                best = (score, action);
+            }
+        }
+        Some(best.1)
+    }
+}
+
+/// Scores a GameState from the point of view of `player_id`, higher is better for them.
+///
+/// Counts the units and buildings on each side, weighted by their cost and health, the gold that
+/// is left to spend, and how close the player's units are to something they can attack.
+pub fn evaluate(
+    game_state: &GameState,
+    player_id: PlayerId,
+    buildings: &Buildings,
+    units: &Units,
+) -> f32 {
+    let player = match game_state.players.get(&player_id) {
+        Some(player) if !player.eliminated => player,
+        _ => return -WIN_SCORE,
+    };
+    if let Some(team) = winner(game_state) {
+        return if team == player.team {
+            WIN_SCORE
+        } else {
+            -WIN_SCORE
+        };
+    }
+
+    let faction = player.faction.to_string();
+    let is_enemy_building = |kind: BuildingKind| buildings[kind].faction != faction;
+    let objectives: Vec<usize> = game_state
+        .board
+        .iter()
+        .enumerate()
+        .filter(|(_, tile)| {
+            let enemy_unit = tile
+                .unit
+                .map(|unit| !game_state.are_allies(player_id, unit.owner))
+                .unwrap_or(false);
+            let enemy_building = tile
+                .building
+                .map(|building| is_enemy_building(building.kind))
+                .unwrap_or(false);
+            enemy_unit || enemy_building
+        })
+        .map(|(index, _)| index)
+        .collect();
+
+    let mut score = game_state.available_gold(player_id) as f32 * GOLD_WEIGHT;
+    for (index, tile) in game_state.board.iter().enumerate() {
+        if let Some(unit) = tile.unit {
+            let descriptor = &units[unit.kind];
+            // Units without health, like lava, die to the first hit they take, so they are
+            // worth no more than a unit that is about to
+            let health = if descriptor.max_hp == 0 {
+                0.0
+            } else {
+                unit.health as f32 / descriptor.max_hp as f32
+            };
+            let value = descriptor.cost as f32 * (0.5 + 0.5 * health);
+
+            if game_state.are_allies(player_id, unit.owner) {
+                score += value;
+                if let Some(distance) = objectives.iter().map(|to| hex::distance(index, *to)).min()
+                {
+                    score -= distance as f32 * DISTANCE_WEIGHT;
+                }
+            } else {
+                score -= value;
+            }
+        }
+
+        if let Some(building) = tile.building {
+            let value = building.health as f32 * BUILDING_WEIGHT;
+            if is_enemy_building(building.kind) {
+                score -= value;
+            } else {
+                score += value;
+            }
+        }
+    }
+    score
+}
+
+/// The team that is ahead, for deciding games that have to stop before anyone has won them.
+///
+/// A team that has won already is ahead of course. Otherwise every team is scored by how
+/// evaluate sees the board for its players, and the best one is ahead if it beats the others by
+/// more than LEAD_MARGIN. None if it is too close to call.
+pub fn leading_team(
+    game_state: &GameState,
+    buildings: &Buildings,
+    units: &Units,
+) -> Option<TeamId> {
+    if let Some(team) = winner(game_state) {
+        return Some(team);
+    }
+
+    let mut teams: BTreeMap<TeamId, (f32, u32)> = BTreeMap::new();
+    for (player_id, player) in game_state.players.iter() {
+        if !player.eliminated {
+            let team = teams.entry(player.team).or_default();
+            team.0 += evaluate(game_state, *player_id, buildings, units);
+            team.1 += 1;
+        }
+    }
+    let mut scores: Vec<(TeamId, f32)> = teams
+        .into_iter()
+        .map(|(team, (score, players))| (team, score / players as f32))
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    match scores.as_slice() {
+        [(team, best), (_, runner_up), ..] if best - runner_up > LEAD_MARGIN => Some(*team),
+        _ => None,
+    }
+}
+
+/// The team that won, whether the game has been ended already or not
+fn winner(game_state: &GameState) -> Option<TeamId> {
+    match game_state.histroy.last() {
+        Some(GameEvent::EndGame {
+            reason: EndGameReason::TeamWon { team },
+        }) => Some(*team),
+        _ => game_state.determine_winner(),
+    }
+}
@@ -0,0 +1,262 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use bevy::utils::Instant;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use super::{heuristic, Bot, TurnCounter};
+use crate::{
+    buildings::Buildings, terrain::Terrain, units::Units, GameEvent, GameState, PlayerId, Stage,
+};
+
+// How much the search favours trying actions it knows little about over ones that did well
+const EXPLORATION: f32 = std::f32::consts::SQRT_2;
+// Rollouts that havent ended the game by then go to the team that is ahead, like the
+// tournament does with games that hit the round cap
+const ROLLOUT_DEPTH: usize = 64;
+// Without this, random rollouts would hardly ever end a turn with so many moves to pick from
+const ROLLOUT_END_TURN_CHANCE: f64 = 0.3;
+// Scales heuristic scores down before they are turned into a reward between 0 and 1
+const REWARD_SCALE: f32 = 10.0;
+
+/// How long a bot gets to think about each action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    /// Play out this many games before deciding
+    Iterations(u32),
+    /// Play out as many games as fit in this time
+    Time(Duration),
+}
+
+/// A bot that plays random games from the current state, and picks the action that most of
+/// the promising ones started with
+pub struct MctsBot {
+    budget: Budget,
+    rng: StdRng,
+    turn: TurnCounter,
+}
+
+/// An action in the search tree, along with how the games going through it turned out
+struct Node {
+    /// The action that lead here from the parent, and the player that took it
+    action: Option<GameEvent>,
+    player_id: PlayerId,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Actions that could follow this one but have not been tried yet
+    untried: Vec<GameEvent>,
+    visits: u32,
+    /// Sum of the rewards `player_id` got from the games played through here
+    reward: f32,
+}
+
+impl MctsBot {
+    pub fn new(budget: Budget) -> Self {
+        Self::with_seed(budget, rand::random())
+    }
+
+    /// A bot that makes the same choices every time it sees the same game, as long as its
+    /// budget is counted in iterations
+    pub fn with_seed(budget: Budget, seed: u64) -> Self {
+        Self {
+            budget,
+            rng: StdRng::seed_from_u64(seed),
+            turn: TurnCounter::default(),
+        }
+    }
+
+    fn search(
+        &mut self,
+        game_state: &GameState,
+        actions: Vec<GameEvent>,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Option<GameEvent> {
+        let mut nodes = vec![Node {
+            action: None,
+            player_id: game_state.active_player_id,
+            parent: None,
+            children: Vec::new(),
+            untried: actions,
+            visits: 0,
+            reward: 0.0,
+        }];
+
+        let started = Instant::now();
+        let mut iterations = 0;
+        while !self.budget_spent(started, iterations) {
+            iterations += 1;
+            let mut state = game_state.clone();
+
+            // Walk down the tree along the most promising actions
+            let mut node = 0;
+            while nodes[node].untried.is_empty() && !nodes[node].children.is_empty() {
+                node = select_child(&nodes, node);
+                let action = nodes[node].action.as_ref().unwrap();
+                state.consume(action, buildings, units, terrain);
+            }
+
+            // Then try something new
+            if !nodes[node].untried.is_empty() {
+                let index = self.rng.gen_range(0..nodes[node].untried.len());
+                let action = nodes[node].untried.swap_remove(index);
+                let player_id = state.active_player_id;
+                state.consume(&action, buildings, units, terrain);
+
+                let untried = if is_over(&state) {
+                    Vec::new()
+                } else {
+                    state.legal_actions(state.active_player_id, buildings, units, terrain)
+                };
+                nodes.push(Node {
+                    action: Some(action),
+                    player_id,
+                    parent: Some(node),
+                    children: Vec::new(),
+                    untried,
+                    visits: 0,
+                    reward: 0.0,
+                });
+                let child = nodes.len() - 1;
+                nodes[node].children.push(child);
+                node = child;
+            }
+
+            self.rollout(&mut state, buildings, units, terrain);
+
+            // And let every action on the way know how it went for whoever took it
+            let mut rewards = BTreeMap::new();
+            let mut current = Some(node);
+            while let Some(index) = current {
+                let node = &mut nodes[index];
+                let gained = *rewards
+                    .entry(node.player_id)
+                    .or_insert_with(|| reward(&state, node.player_id, buildings, units));
+                node.visits += 1;
+                node.reward += gained;
+                current = node.parent;
+            }
+        }
+
+        // The action that was looked into the most is the one the search trusts the most
+        nodes[0]
+            .children
+            .iter()
+            .max_by_key(|child| nodes[**child].visits)
+            .and_then(|child| nodes[*child].action.clone())
+    }
+
+    fn budget_spent(&self, started: Instant, iterations: u32) -> bool {
+        // Always look at least once, or there would be nothing to choose from
+        if iterations == 0 {
+            return false;
+        }
+        match self.budget {
+            Budget::Iterations(limit) => iterations >= limit,
+            Budget::Time(limit) => started.elapsed() >= limit,
+        }
+    }
+
+    /// Plays random actions until the game is over or has gone on long enough
+    fn rollout(
+        &mut self,
+        state: &mut GameState,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) {
+        for _ in 0..ROLLOUT_DEPTH {
+            if is_over(state) {
+                return;
+            }
+
+            let player_id = state.active_player_id;
+            let action = if self.rng.gen_bool(ROLLOUT_END_TURN_CHANCE) {
+                GameEvent::EndTurn { player_id }
+            } else {
+                let actions = state.legal_actions(player_id, buildings, units, terrain);
+                match actions.choose(&mut self.rng) {
+                    Some(action) => action.clone(),
+                    None => return,
+                }
+            };
+            state.consume(&action, buildings, units, terrain);
+        }
+    }
+}
+
+impl Bot for MctsBot {
+    fn choose_action(
+        &mut self,
+        game_state: &GameState,
+        player_id: PlayerId,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Option<GameEvent> {
+        if game_state.stage != Stage::InGame || game_state.active_player_id != player_id {
+            return None;
+        }
+
+        if self.turn.out_of_actions(game_state) {
+            return Some(GameEvent::EndTurn { player_id });
+        }
+
+        let actions = game_state.legal_actions(player_id, buildings, units, terrain);
+        if actions.len() <= 1 {
+            return actions.into_iter().next();
+        }
+        self.search(game_state, actions, buildings, units, terrain)
+    }
+}
+
+/// The child with the best upper confidence bound, balancing how well it did against how
+/// little it has been tried
+fn select_child(nodes: &[Node], parent: usize) -> usize {
+    let parent_visits = (nodes[parent].visits.max(1) as f32).ln();
+    let bound = |child: usize| {
+        let node = &nodes[child];
+        if node.visits == 0 {
+            return f32::INFINITY;
+        }
+        let visits = node.visits as f32;
+        node.reward / visits + EXPLORATION * (parent_visits / visits).sqrt()
+    };
+
+    nodes[parent]
+        .children
+        .iter()
+        .copied()
+        .max_by(|a, b| bound(*a).total_cmp(&bound(*b)))
+        .unwrap()
+}
+
+/// Whether nothing more can happen in the game
+fn is_over(game_state: &GameState) -> bool {
+    game_state.stage != Stage::InGame || game_state.determine_winner().is_some()
+}
+
+/// How good the end of a rollout is for `player_id`, from 0 for a loss to 1 for a win.
+///
+/// Rollouts that stop before anyone has won are decided the way the tournament decides games at
+/// the round cap, by which team is ahead. When it is too close to call, the reward leans towards
+/// whoever the board favours.
+fn reward(
+    game_state: &GameState,
+    player_id: PlayerId,
+    buildings: &Buildings,
+    units: &Units,
+) -> f32 {
+    let team = match game_state.players.get(&player_id) {
+        Some(player) if !player.eliminated => player.team,
+        _ => return 0.0,
+    };
+    match heuristic::leading_team(game_state, buildings, units) {
+        Some(leader) if leader == team => 1.0,
+        Some(_) => 0.0,
+        None => {
+            let score = heuristic::evaluate(game_state, player_id, buildings, units);
+            1.0 / (1.0 + (-score / REWARD_SCALE).exp())
+        }
+    }
+}
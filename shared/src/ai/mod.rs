@@ -0,0 +1,97 @@
+pub mod heuristic;
+pub mod mcts;
+
+use std::{fmt, str::FromStr};
+
+use crate::{buildings::Buildings, terrain::Terrain, units::Units, GameEvent, GameState, PlayerId};
+
+/// Something that can take turns in place of a human player
+pub trait Bot {
+    /// Picks the next action for `player_id`, who has to be the active player. Returns None if
+    /// there is nothing the player is allowed to do.
+    fn choose_action(
+        &mut self,
+        game_state: &GameState,
+        player_id: PlayerId,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Option<GameEvent>;
+}
+
+// Moves dont use up any range yet, so a bot could shuffle units around forever without a limit
+const MAX_ACTIONS_PER_TURN: usize = 16;
+
+/// Counts the actions a bot has taken in its current turn
+#[derive(Debug, Default)]
+struct TurnCounter {
+    round: u32,
+    actions: usize,
+}
+
+impl TurnCounter {
+    /// Counts one more action, returns whether the bot should end its turn instead of taking it
+    fn out_of_actions(&mut self, game_state: &GameState) -> bool {
+        if self.round != game_state.round {
+            self.round = game_state.round;
+            self.actions = 0;
+        }
+        self.actions += 1;
+        self.actions > MAX_ACTIONS_PER_TURN
+    }
+}
+
+/// How well a bot plays
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    /// How far off the bot's judgement of an action can be
+    pub fn noise(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 4.0,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 0.0,
+        }
+    }
+
+    /// The chance of the bot doing something random instead of what it thinks is best
+    pub fn blunder_chance(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.2,
+            Difficulty::Normal => 0.05,
+            Difficulty::Hard => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difficulty::Easy => write!(f, "easy"),
+            Difficulty::Normal => write!(f, "normal"),
+            Difficulty::Hard => write!(f, "hard"),
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!(
+                "unknown difficulty {}, expected easy, normal or hard",
+                s
+            )),
+        }
+    }
+}
@@ -5,7 +5,7 @@ use crate::buildings::*;
 
 #[derive(AssetCollection)]
 pub struct BuildingAssets {
-    #[asset(key = "meta.terrain", collection(typed))]
+    #[asset(key = "meta.buildings", collection(typed))]
     #[allow(dead_code)]
     handles: Vec<Handle<BuildingAsset>>,
     #[allow(dead_code)]
@@ -0,0 +1,130 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use bevy_renet::renet::{ConnectToken, NETCODE_KEY_BYTES};
+use serde::{Deserialize, Serialize};
+
+use crate::connection::{ConnectInfo, SessionToken};
+
+// Set DINOJAM2_AUTH=secure to make clients fetch a connect token before connecting, and the
+// server only accept clients with a valid token. Anything else keeps the unsecure local dev mode.
+pub const AUTH_ENV: &str = "DINOJAM2_AUTH";
+// The key tokens are signed with, as 64 hex characters. Only the server needs this.
+pub const PRIVATE_KEY_ENV: &str = "DINOJAM2_PRIVATE_KEY";
+// The password the token service asks for before it hands out a token. Both the server and
+// the clients need this in secure mode.
+pub const SERVER_PASSWORD_ENV: &str = "DINOJAM2_SERVER_PASSWORD";
+// Where the token service can be found, defaults to DEFAULT_TOKEN_SERVICE_ADDR
+pub const TOKEN_SERVICE_ENV: &str = "DINOJAM2_TOKEN_SERVICE";
+
+pub const DEFAULT_TOKEN_SERVICE_ADDR: &str = "127.0.0.1:5001";
+
+const TOKEN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether secure authentication has been turned on
+pub fn secure_mode() -> bool {
+    std::env::var(AUTH_ENV)
+        .map(|mode| mode.eq_ignore_ascii_case("secure"))
+        .unwrap_or(false)
+}
+
+/// Reads the private key used to sign connect tokens.
+///
+/// Panics if it is missing or malformed, since a secure server can't run without it.
+pub fn private_key_from_env() -> [u8; NETCODE_KEY_BYTES] {
+    let hex = std::env::var(PRIVATE_KEY_ENV)
+        .unwrap_or_else(|_| panic!("{} must be set in secure mode", PRIVATE_KEY_ENV));
+    parse_private_key(hex.trim()).unwrap_or_else(|| {
+        panic!(
+            "{} must be {} hex encoded bytes",
+            PRIVATE_KEY_ENV, NETCODE_KEY_BYTES
+        )
+    })
+}
+
+/// Reads the password clients need to get a connect token.
+///
+/// Panics if it is missing, without it anyone that can reach the token service gets in.
+pub fn server_password_from_env() -> String {
+    std::env::var(SERVER_PASSWORD_ENV)
+        .ok()
+        .filter(|password| !password.is_empty())
+        .unwrap_or_else(|| panic!("{} must be set in secure mode", SERVER_PASSWORD_ENV))
+}
+
+fn parse_private_key(hex: &str) -> Option<[u8; NETCODE_KEY_BYTES]> {
+    if hex.len() != NETCODE_KEY_BYTES * 2 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut key = [0u8; NETCODE_KEY_BYTES];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+/// The address of the token service
+pub fn token_service_addr() -> SocketAddr {
+    std::env::var(TOKEN_SERVICE_ENV)
+        .unwrap_or_else(|_| DEFAULT_TOKEN_SERVICE_ADDR.to_string())
+        .parse()
+        .unwrap()
+}
+
+/// What a client sends to the token service, as a single line of json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenRequest {
+    pub name: String,
+    pub session_token: Option<SessionToken>,
+    #[serde(default)]
+    pub spectator: bool,
+    pub password: String,
+}
+
+/// A connect token along with the client id it was issued for. The id inside the token is
+/// encrypted, so the token service sends it separately too.
+pub struct IssuedToken {
+    pub client_id: u64,
+    pub connect_token: ConnectToken,
+}
+
+/// Asks the token service for a connect token
+pub fn request_connect_token(
+    service_addr: SocketAddr,
+    connect_info: &ConnectInfo,
+) -> io::Result<IssuedToken> {
+    let mut stream = TcpStream::connect_timeout(&service_addr, TOKEN_REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(TOKEN_REQUEST_TIMEOUT))?;
+
+    let request = TokenRequest {
+        name: connect_info.name.clone(),
+        session_token: connect_info.session_token,
+        spectator: connect_info.spectator,
+        password: server_password_from_env(),
+    };
+    let mut line = serde_json::to_string(&request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut client_id = [0u8; 8];
+    stream
+        .read_exact(&mut client_id)
+        .map_err(|err| match err.kind() {
+            // The service hangs up on requests it turns down
+            io::ErrorKind::UnexpectedEof => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the token service turned us down, check the server password",
+            ),
+            _ => err,
+        })?;
+    let connect_token = ConnectToken::read(&mut stream)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)))?;
+    Ok(IssuedToken {
+        client_id: u64::from_le_bytes(client_id),
+        connect_token,
+    })
+}
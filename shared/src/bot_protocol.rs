@@ -0,0 +1,76 @@
+//! Text protocol for bots written in any language.
+//!
+//! Bots connect to the server over TCP and exchange one JSON object per line. A bot starts by
+//! introducing itself:
+//!
+//! ```text
+//! {"type":"hello","name":"My Bot"}
+//! ```
+//!
+//! The server seats it, sends the whole GameState and then every event as it happens. When it
+//! is the bot's turn it is sent the actions it can take, and it answers with one of them:
+//!
+//! ```text
+//! {"type":"action","event":{"MoveUnit":{"player_id":7,"from":3,"to":4}}}
+//! ```
+//!
+//! Actions are checked exactly like the ones human players send, and rejected the same way.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    messages::{DisconnectReason, RejectReason},
+    GameEvent, GameState, PlayerId,
+};
+
+/// Version of the bot protocol. Bump it whenever any of the messages below change shape.
+pub const BOT_PROTOCOL_VERSION: u32 = 2;
+
+/// Lines longer than this are not read, so a bot cant make the server buffer forever
+pub const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Everything the server can send to a bot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotServerMessage {
+    /// The seat the bot has been given
+    Welcome {
+        player_id: PlayerId,
+        protocol_version: u32,
+    },
+    /// The full GameState, sent after the welcome and whenever the bot needs to start over
+    State { game_state: Box<GameState> },
+    /// An event that happened, at position `index` of the GameState history
+    Event { index: usize, event: GameEvent },
+    /// It is the bot's turn, and these are the actions it can take
+    YourTurn { legal_actions: Vec<GameEvent> },
+    /// An action the bot sent that was not accepted
+    Rejected {
+        event: GameEvent,
+        reason: RejectReason,
+    },
+    /// Sent right before the server drops the bot
+    Disconnect { reason: DisconnectReason },
+}
+
+/// Everything a bot can send to the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotClientMessage {
+    /// Asks for a seat in the match, has to come first
+    Hello { name: String },
+    /// An action the bot wants to take
+    Action { event: GameEvent },
+}
+
+/// Writes a message as a single line of JSON, newline included
+pub fn encode_line<T: Serialize>(message: &T) -> String {
+    let mut line = serde_json::to_string(message).unwrap();
+    line.push('\n');
+    line
+}
+
+/// Reads a message from a line of JSON, with or without its newline
+pub fn decode_line<T: DeserializeOwned>(line: &str) -> Result<T, serde_json::Error> {
+    serde_json::from_str(line.trim_end())
+}
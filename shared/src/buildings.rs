@@ -38,7 +38,7 @@ impl std::ops::Index<BuildingKind> for Buildings {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, Hash, serde::Deserialize)]
 pub struct BuildingDescriptor {
     pub name: String,
     // Name that can be shown to players
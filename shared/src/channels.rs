@@ -0,0 +1,138 @@
+use bevy::utils::Duration;
+use bevy_renet::renet::{
+    BlockChannelConfig, ChannelConfig, ReliableChannelConfig, RenetConnectionConfig,
+    UnreliableChannelConfig,
+};
+
+/// Channels used by clients to talk to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientChannel {
+    /// Handshake and game actions, these have to arrive and in order
+    Game,
+    /// Chat messages, kept apart so they never hold up the game
+    Chat,
+    /// Cursor updates and pings, where only the latest one matters
+    Unreliable,
+}
+
+/// Channels used by the server to talk to its clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerChannel {
+    /// Game events, rejections, sessions and lobby updates
+    Game,
+    /// Full game states, which can get too big for a regular message
+    Snapshot,
+    /// Chat messages
+    Chat,
+    /// Cursor updates and pongs
+    Unreliable,
+}
+
+impl ClientChannel {
+    pub const ALL: [ClientChannel; 3] = [Self::Game, Self::Chat, Self::Unreliable];
+
+    /// The biggest message a client can send over the channel, in bytes. Renet drops clients that
+    /// go over it, so a client can't make the server read huge messages.
+    pub fn max_message_size(&self) -> u64 {
+        match self {
+            // Handshakes, events and desync reports are all a few dozen bytes
+            ClientChannel::Game => 512,
+            // A chat message of MAX_CHAT_LENGTH characters, with room to spare
+            ClientChannel::Chat => 1024,
+            ClientChannel::Unreliable => 64,
+        }
+    }
+
+    pub fn channels_config() -> Vec<ChannelConfig> {
+        vec![
+            ReliableChannelConfig {
+                channel_id: Self::Game.into(),
+                message_resend_time: Duration::from_millis(100),
+                max_message_size: Self::Game.max_message_size(),
+                ..Default::default()
+            }
+            .into(),
+            ReliableChannelConfig {
+                channel_id: Self::Chat.into(),
+                message_resend_time: Duration::from_millis(200),
+                max_message_size: Self::Chat.max_message_size(),
+                ..Default::default()
+            }
+            .into(),
+            UnreliableChannelConfig {
+                channel_id: Self::Unreliable.into(),
+                max_message_size: Self::Unreliable.max_message_size(),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+impl From<ClientChannel> for u8 {
+    fn from(channel_id: ClientChannel) -> Self {
+        match channel_id {
+            ClientChannel::Game => 0,
+            ClientChannel::Chat => 1,
+            ClientChannel::Unreliable => 2,
+        }
+    }
+}
+
+impl ServerChannel {
+    pub const ALL: [ServerChannel; 4] = [Self::Game, Self::Snapshot, Self::Chat, Self::Unreliable];
+
+    pub fn channels_config() -> Vec<ChannelConfig> {
+        vec![
+            ReliableChannelConfig {
+                channel_id: Self::Game.into(),
+                message_resend_time: Duration::from_millis(100),
+                ..Default::default()
+            }
+            .into(),
+            BlockChannelConfig {
+                channel_id: Self::Snapshot.into(),
+                ..Default::default()
+            }
+            .into(),
+            ReliableChannelConfig {
+                channel_id: Self::Chat.into(),
+                message_resend_time: Duration::from_millis(200),
+                ..Default::default()
+            }
+            .into(),
+            UnreliableChannelConfig {
+                channel_id: Self::Unreliable.into(),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+impl From<ServerChannel> for u8 {
+    fn from(channel_id: ServerChannel) -> Self {
+        match channel_id {
+            ServerChannel::Game => 0,
+            ServerChannel::Snapshot => 1,
+            ServerChannel::Chat => 2,
+            ServerChannel::Unreliable => 3,
+        }
+    }
+}
+
+pub fn client_connection_config() -> RenetConnectionConfig {
+    RenetConnectionConfig {
+        send_channels_config: ClientChannel::channels_config(),
+        receive_channels_config: ServerChannel::channels_config(),
+        ..Default::default()
+    }
+}
+
+pub fn server_connection_config() -> RenetConnectionConfig {
+    RenetConnectionConfig {
+        send_channels_config: ServerChannel::channels_config(),
+        receive_channels_config: ClientChannel::channels_config(),
+        ..Default::default()
+    }
+}
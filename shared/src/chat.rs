@@ -0,0 +1,92 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// The longest chat message the server passes on, in characters
+pub const MAX_CHAT_LENGTH: usize = 200;
+
+/// Who a chat message is meant for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatScope {
+    /// Every player in the match
+    Everyone,
+    /// Only the players on the sender's team
+    Team,
+}
+
+/// Quick messages players can send with a single key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Emote {
+    Hello,
+    GoodLuck,
+    WellPlayed,
+    Thanks,
+    Oops,
+    GoodGame,
+}
+
+impl Emote {
+    pub const ALL: [Emote; 6] = [
+        Emote::Hello,
+        Emote::GoodLuck,
+        Emote::WellPlayed,
+        Emote::Thanks,
+        Emote::Oops,
+        Emote::GoodGame,
+    ];
+
+    /// What the emote says when it is shown
+    pub fn text(&self) -> &'static str {
+        match self {
+            Emote::Hello => "Hello!",
+            Emote::GoodLuck => "Good luck!",
+            Emote::WellPlayed => "Well played!",
+            Emote::Thanks => "Thanks!",
+            Emote::Oops => "Oops!",
+            Emote::GoodGame => "Good game!",
+        }
+    }
+}
+
+impl fmt::Display for Emote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text())
+    }
+}
+
+impl FromStr for Emote {
+    type Err = String;
+
+    /// Reads an emote by its name, ignoring case, like `wellplayed` or `WellPlayed`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Emote::ALL
+            .into_iter()
+            .find(|emote| format!("{:?}", emote).eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let names: Vec<String> = Emote::ALL
+                    .iter()
+                    .map(|emote| format!("{:?}", emote).to_lowercase())
+                    .collect();
+                format!(
+                    "unknown emote {:?}, expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Why the server did not pass on a chat message or emote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatRejectReason {
+    /// There was nothing in it
+    Empty,
+    /// It was longer than MAX_CHAT_LENGTH
+    TooLong,
+    /// The player is sending messages faster than the server allows
+    TooFast,
+    /// The server's chat filter held it back
+    Filtered,
+    /// Only players can chat, spectators and clients without a seat cant
+    NotSeated,
+}
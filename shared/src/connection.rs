@@ -0,0 +1,70 @@
+use bevy_renet::renet::NETCODE_USER_DATA_BYTES;
+
+use crate::PlayerId;
+
+/// Secret handed to a player when they join so they can prove who they are when reconnecting
+pub type SessionToken = u64;
+
+// The session token lives in the last 8 bytes of the user data, with the spectator flag right
+// before it, leaving the rest for the name
+const TOKEN_OFFSET: usize = NETCODE_USER_DATA_BYTES - 8;
+const SPECTATOR_OFFSET: usize = TOKEN_OFFSET - 1;
+const MAX_NAME_LEN: usize = SPECTATOR_OFFSET - 8;
+
+/// Everything a client tells the server about itself when connecting.
+///
+/// It is packed into the netcode user data, since that is the only thing the server sees before
+/// it decides what to do with a new connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectInfo {
+    pub name: String,
+    /// Token from a previous connection, if the client is trying to get back into its game
+    pub session_token: Option<SessionToken>,
+    /// Whether the client only wants to watch the match instead of playing in it
+    pub spectator: bool,
+}
+
+impl ConnectInfo {
+    pub fn to_user_data(&self) -> [u8; NETCODE_USER_DATA_BYTES] {
+        let mut user_data = [0u8; NETCODE_USER_DATA_BYTES];
+
+        // Names that are too long simply get cut off
+        let name = self.name.as_bytes();
+        let len = name.len().min(MAX_NAME_LEN);
+        user_data[0..8].copy_from_slice(&(len as u64).to_le_bytes());
+        user_data[8..len + 8].copy_from_slice(&name[..len]);
+
+        // A token of 0 is never handed out, so it is used to mean "no token"
+        let token = self.session_token.unwrap_or(0);
+        user_data[TOKEN_OFFSET..].copy_from_slice(&token.to_le_bytes());
+        user_data[SPECTATOR_OFFSET] = self.spectator as u8;
+
+        user_data
+    }
+
+    pub fn from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Self {
+        let mut buffer = [0u8; 8];
+        buffer.copy_from_slice(&user_data[0..8]);
+        let len = (u64::from_le_bytes(buffer) as usize).min(MAX_NAME_LEN);
+        let name = String::from_utf8_lossy(&user_data[8..len + 8]).to_string();
+
+        buffer.copy_from_slice(&user_data[TOKEN_OFFSET..]);
+        let session_token = match u64::from_le_bytes(buffer) {
+            0 => None,
+            token => Some(token),
+        };
+
+        Self {
+            name,
+            session_token,
+            spectator: user_data[SPECTATOR_OFFSET] != 0,
+        }
+    }
+}
+
+/// What the server tells a client about the seat it has been given
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Session {
+    pub player_id: PlayerId,
+    pub token: SessionToken,
+}
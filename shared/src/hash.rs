@@ -0,0 +1,87 @@
+use std::hash::{Hash, Hasher};
+
+use crate::{
+    buildings::Buildings, terrain::Terrain, units::Units, GameState, MAP_HEIGHT, MAP_WIDTH,
+};
+
+// Bump this whenever validate or consume change behaviour, so that clients with different rules
+// are turned away instead of silently disagreeing with the server. Changes to the descriptors
+// change the rules hash on their own.
+const RULES_REVISION: u32 = 6;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a hasher that gives the same result on every platform and every run.
+///
+/// The std hasher makes no such promise, and usize differs in size between the native and the
+/// wasm builds, so it is always hashed as a u64 here.
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write(&(i as u64).to_le_bytes());
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write(&(i as i64).to_le_bytes());
+    }
+}
+
+/// Hashes anything with the StableHasher
+pub fn stable_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash of the game rules, from the unit, terrain and building descriptors loaded from the asset
+/// files along with the rules that are baked into the code
+pub fn rules_hash(buildings: &Buildings, units: &Units, terrain: &Terrain) -> u64 {
+    stable_hash(&(
+        RULES_REVISION,
+        MAP_WIDTH,
+        MAP_HEIGHT,
+        &buildings.0,
+        &units.0,
+        &terrain.0,
+    ))
+}
+
+/// Hash of everything in a GameState that consuming an event can change.
+///
+/// The history itself is left out, only its length goes in, since two states that agree on
+/// everything else after the same number of events are in sync. The state is hashed through its
+/// bincode encoding, which has a fixed layout and iterates the players in id order.
+pub fn state_hash(game_state: &GameState) -> u64 {
+    let canonical = bincode::serialize(&(
+        game_state.stage,
+        &game_state.board[..],
+        game_state.active_player_id,
+        &game_state.turn_order,
+        game_state.round,
+        game_state.settings,
+        &game_state.players,
+        game_state.histroy.len() as u64,
+        &game_state.initial_board[..],
+    ))
+    .unwrap();
+    stable_hash(&canonical[..])
+}
@@ -1 +1,20 @@
 pub mod neighbors;
+
+use crate::MAP_WIDTH;
+
+/// Number of steps it takes to walk from one tile of the board to another.
+///
+/// The board is laid out in rows, with every odd row shifted half a tile to the right.
+pub fn distance(from: usize, to: usize) -> u32 {
+    let (from_q, from_r) = axial(from);
+    let (to_q, to_r) = axial(to);
+    let (dq, dr) = (from_q - to_q, from_r - to_r);
+    ((dq.abs() + dr.abs() + (dq + dr).abs()) / 2) as u32
+}
+
+/// Axial coordinates of a tile, in which walking to any neighbor changes q and r by at most one
+fn axial(index: usize) -> (i32, i32) {
+    let x = (index % MAP_WIDTH) as i32;
+    let y = (index / MAP_WIDTH) as i32;
+    (x - (y - (y & 1)) / 2, y)
+}
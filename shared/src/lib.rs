@@ -1,10 +1,19 @@
 use std::fmt;
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+pub mod ai;
 pub mod asset_management;
+pub mod auth;
+pub mod bot_protocol;
+pub mod channels;
+pub mod chat;
+pub mod connection;
+pub mod hash;
 pub mod hex;
+pub mod messages;
+pub mod save;
 pub mod buildings;
 pub mod units;
 pub mod terrain;
@@ -18,12 +27,13 @@ use crate::terrain::*;
 // instance.
 pub const PROTOCOL_ID: u64 = 1208;
 
-const MAP_WIDTH: usize = 8;
-const MAP_HEIGHT: usize = 8;
-const MAP_SIZE: usize = MAP_WIDTH * MAP_HEIGHT;
+pub const MAP_WIDTH: usize = 8;
+pub const MAP_HEIGHT: usize = 8;
+pub const MAP_SIZE: usize = MAP_WIDTH * MAP_HEIGHT;
 
 // This just makes it easier to dissern between a player id and any u64
-type PlayerId = u64;
+pub type PlayerId = u64;
+pub type TeamId = u8;
 
 /// Struct for board positional related data.
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -59,12 +69,43 @@ impl fmt::Display for Faction {
     }
 }
 
+/// How players are grouped into teams
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TeamMode {
+    /// Everyone playing the same faction is on one team
+    #[default]
+    ByFaction,
+    /// Every player is on a team of their own
+    FreeForAll,
+}
+
+/// Where players get the gold to build units from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Economy {
+    /// Every player pays for their own units
+    #[default]
+    PerPlayer,
+    /// Players can spend the gold of everyone on their team
+    Shared,
+}
+
+/// Rules of a match that are decided before anyone joins
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchSettings {
+    pub teams: TeamMode,
+    pub economy: Economy,
+}
+
 /// Struct for storing player related data.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Player {
     pub name: String,
     pub faction: Faction,
     pub gold: u32,
+    /// Players on the same team are allies, they win together and cant attack each other
+    pub team: TeamId,
+    /// Eliminated players stay in the game but no longer get a turn
+    pub eliminated: bool,
 }
 
 /// The different states a game can be in. (not to be confused with the entire "GameState")
@@ -79,12 +120,17 @@ pub enum Stage {
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum EndGameReason {
     PlayerLeft { player_id: PlayerId },
-    PlayerWon { winner: PlayerId },
+    TeamWon { team: TeamId },
+    /// Whoever runs the server called the game off
+    Aborted,
 }
 
 /// An event that progresses the GameState forward
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum GameEvent {
+    ConfigureMatch {
+        settings: MatchSettings,
+    },
     BeginGame {
         goes_first: PlayerId,
     },
@@ -98,6 +144,9 @@ pub enum GameEvent {
     PlayerDisconnected {
         player_id: PlayerId,
     },
+    PlayerEliminated {
+        player_id: PlayerId,
+    },
     BuildUnit {
         player_id: PlayerId,
         at: usize,
@@ -113,6 +162,20 @@ pub enum GameEvent {
     },
 }
 
+impl GameEvent {
+    /// The player performing this event, if it is an action a player can take on their own.
+    /// Events that only the server should be creating return None.
+    pub fn acting_player(&self) -> Option<PlayerId> {
+        use GameEvent::*;
+        match self {
+            BuildUnit { player_id, .. } | MoveUnit { player_id, .. } | EndTurn { player_id } => {
+                Some(*player_id)
+            }
+            _ => None,
+        }
+    }
+}
+
 /// A GameState object that is able to keep track of a game
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GameState {
@@ -120,19 +183,22 @@ pub struct GameState {
     #[serde(with = "BigArray")]
     pub board: [BoardTile; MAP_SIZE],
     pub active_player_id: PlayerId,
-    pub players: HashMap<PlayerId, Player>,
+    /// The order players take their turns in, starting with whoever went first
+    pub turn_order: Vec<PlayerId>,
+    /// Goes up every time the turn order comes back around to the first player, starting at 1
+    pub round: u32,
+    pub settings: MatchSettings,
+    // Kept sorted by id, so that every machine walks through the players in the same order
+    pub players: BTreeMap<PlayerId, Player>,
     pub histroy: Vec<GameEvent>,
+    /// The board the match started on, undoing an event replays the history on top of it
+    #[serde(with = "BigArray")]
+    pub initial_board: [BoardTile; MAP_SIZE],
 }
 
 impl Default for GameState {
     fn default() -> Self {
-        Self {
-            stage: Stage::PreGame,
-            board: [BoardTile::default(); MAP_SIZE],
-            active_player_id: 0,
-            players: HashMap::new(),
-            histroy: Vec::new(),
-        }
+        Self::on_board([BoardTile::default(); MAP_SIZE])
     }
 }
 
@@ -143,6 +209,21 @@ pub struct Descriptors {
 }
 
 impl GameState {
+    /// A match that hasnt started yet, on `board`
+    pub fn on_board(board: [BoardTile; MAP_SIZE]) -> Self {
+        Self {
+            stage: Stage::PreGame,
+            board,
+            active_player_id: 0,
+            turn_order: Vec::new(),
+            round: 0,
+            settings: MatchSettings::default(),
+            players: BTreeMap::new(),
+            histroy: Vec::new(),
+            initial_board: board,
+        }
+    }
+
     /// Determins where an event is valid considering the current GameState
     pub fn validate(
         &self,
@@ -153,6 +234,12 @@ impl GameState {
     ) -> bool {
         use GameEvent::*;
         match event {
+            ConfigureMatch { settings: _ } => {
+                // Check that nobody has joined yet, teams are handed out as players join
+                if self.stage != Stage::PreGame || !self.players.is_empty() {
+                    return false;
+                }
+            }
             BeginGame { goes_first } => {
                 // Check that the player supposed to go first exists
                 if !self.players.contains_key(goes_first) {
@@ -165,7 +252,7 @@ impl GameState {
                 }
             }
             EndGame { reason } => match reason {
-                EndGameReason::PlayerWon { winner: _ } => {
+                EndGameReason::TeamWon { team: _ } => {
                     //Check that the game has started before someone wins it
                     if self.stage != Stage::InGame {
                         return false;
@@ -178,6 +265,11 @@ impl GameState {
                 if self.players.contains_key(player_id) {
                     return false;
                 }
+
+                // Check that the game hasnt started yet, players cant join halfway through
+                if self.stage != Stage::PreGame {
+                    return false;
+                }
             }
             PlayerDisconnected { player_id } => {
                 // Check that player exists
@@ -185,6 +277,17 @@ impl GameState {
                     return false;
                 }
             }
+            PlayerEliminated { player_id } => {
+                // Check that player exists and is still in the game
+                match self.players.get(player_id) {
+                    Some(player) if !player.eliminated => {}
+                    _ => return false,
+                }
+
+                if self.stage != Stage::InGame {
+                    return false;
+                }
+            }
             BuildUnit {
                 player_id,
                 at,
@@ -196,12 +299,12 @@ impl GameState {
                 }
 
                 //Check if player is currently the one making their move
-                if self.active_player_id != *player_id {
+                if self.stage != Stage::InGame || self.active_player_id != *player_id {
                     return false;
                 }
 
                 // Check that the tile index is inside the board
-                if *at > MAP_SIZE {
+                if *at >= MAP_SIZE {
                     return false;
                 }
 
@@ -220,14 +323,18 @@ impl GameState {
                 }
 
                 // Check that the player is not trying to place a piece on top of existing peice
-                if board_tile.unit != None {
+                if board_tile.unit.is_some() {
                     return false;
                 }
 
-                let unit_descriptor = units[*unit_kind].clone();
+                // Check that the unit exists at all
+                let unit_descriptor = match units.0.get(unit_kind.0) {
+                    Some(unit_descriptor) => unit_descriptor,
+                    None => return false,
+                };
 
                 // Check that player could afford to build unit
-                if unit_descriptor.cost > player.gold {
+                if unit_descriptor.cost > self.available_gold(*player_id) {
                     return false;
                 }
 
@@ -248,16 +355,15 @@ impl GameState {
                 }
 
                 //Check if player is currently the one making their move
-                if self.active_player_id != *player_id {
+                if self.stage != Stage::InGame || self.active_player_id != *player_id {
                     return false;
                 }
 
                 // Check that the tile index is inside the board
-                if *from > MAP_SIZE || *to > MAP_SIZE {
+                if *from >= MAP_SIZE || *to >= MAP_SIZE {
                     return false;
                 }
 
-                let player = self.players.get(player_id).unwrap();
                 let from_board_tile = self.board[*from];
                 let to_board_tile = self.board[*to];
 
@@ -267,7 +373,12 @@ impl GameState {
                     return false;
                 }
 
-                if let Some(_unit_to_move) = from_board_tile.unit {
+                if let Some(unit_to_move) = from_board_tile.unit {
+                    // Check that the player is moving one of their own units
+                    if unit_to_move.owner != *player_id {
+                        return false;
+                    }
+
                     // TODO Check to see if movement is within range
                     //let from_tilepos = ;
                     //let to_tilepos = ;
@@ -278,8 +389,7 @@ impl GameState {
 
                     if let Some(unit_to_attack) = to_board_tile.unit {
                         // Check that the player is not trying to place a piece on top of an allied
-                        let unit_to_attack_descriptor = units[unit_to_attack.kind].clone();
-                        if unit_to_attack_descriptor.faction == player.faction.to_string() {
+                        if self.are_allies(*player_id, unit_to_attack.owner) {
                             return false;
                         }
                     }
@@ -294,6 +404,11 @@ impl GameState {
                     return false;
                 }
 
+                // Check that there is a game going on to end a turn in
+                if self.stage != Stage::InGame {
+                    return false;
+                }
+
                 //Check if player is currently the one making their move
                 if self.active_player_id != *player_id {
                     return false;
@@ -317,28 +432,67 @@ impl GameState {
     ) {
         use GameEvent::*;
         match valid_event {
+            ConfigureMatch { settings } => self.settings = *settings,
             BeginGame { goes_first } => {
+                // Everyone keeps the order they joined in, just starting from whoever goes first
+                if let Some(first) = self.turn_order.iter().position(|id| id == goes_first) {
+                    self.turn_order.rotate_left(first);
+                }
                 self.active_player_id = *goes_first;
+                self.round = 1;
                 self.stage = Stage::InGame;
             }
             EndGame { reason: _ } => self.stage = Stage::Ended,
             PlayerJoined { player_id, name } => {
+                // Players join whichever faction has fewer players right now, volcano if its
+                // even. Counting who is still here rather than everyone that ever joined keeps
+                // both sides filled when someone leaves the lobby.
+                let volcanoes = self
+                    .players
+                    .values()
+                    .filter(|player| player.faction == Faction::Volcano)
+                    .count();
+                let faction = if volcanoes * 2 > self.players.len() {
+                    Faction::Dinosaur
+                } else {
+                    Faction::Volcano
+                };
+                let team = match self.settings.teams {
+                    TeamMode::ByFaction => faction as TeamId,
+                    TeamMode::FreeForAll => self
+                        .players
+                        .values()
+                        .map(|player| player.team + 1)
+                        .max()
+                        .unwrap_or(0),
+                };
                 self.players.insert(
                     *player_id,
                     Player {
                         name: name.to_string(),
-                        // First player to join get volcano, second get dinos
-                        faction: if self.players.len() > 0 {
-                            Faction::Dinosaur
-                        } else {
-                            Faction::Volcano
-                        },
+                        faction,
                         gold: 0,
+                        team,
+                        eliminated: false,
                     },
                 );
+                self.turn_order.push(*player_id);
             }
             PlayerDisconnected { player_id } => {
+                // Pass the turn on before the player disappears from the turn order
+                if self.stage == Stage::InGame && self.active_player_id == *player_id {
+                    self.advance_turn();
+                }
                 self.players.remove(player_id);
+                self.turn_order.retain(|id| id != player_id);
+            }
+            PlayerEliminated { player_id } => {
+                if let Some(player) = self.players.get_mut(player_id) {
+                    player.eliminated = true;
+                }
+                if self.active_player_id == *player_id {
+                    self.advance_turn();
+                }
             }
             BuildUnit {
                 player_id,
@@ -346,17 +500,11 @@ impl GameState {
                 unit_kind,
             } => {
                 let x = (at % MAP_WIDTH) as u32;
-                let y = (at / MAP_HEIGHT) as u32;
-                let mut board_tile = self.board[*at];
-                board_tile.unit = Some(Unit::new(
-                        (x , y),
-                        *unit_kind,
-                        units,
-                ));
+                let y = (at / MAP_WIDTH) as u32;
+                self.board[*at].unit = Some(Unit::new((x, y), *unit_kind, *player_id, units));
 
                 let unit_descriptor = &units[*unit_kind];
-                let mut player = self.players.get_mut(player_id).unwrap();
-                player.gold -= unit_descriptor.cost;
+                self.spend_gold(*player_id, unit_descriptor.cost);
             }
             MoveUnit {
                 player_id: _,
@@ -364,18 +512,19 @@ impl GameState {
                 to,
             } => {
                 let x = (to % MAP_WIDTH) as u32;
-                let y = (to / MAP_HEIGHT) as u32;
+                let y = (to / MAP_WIDTH) as u32;
                 let mut from_unit = self.board[*from].unit.unwrap();
-                if let Some(mut to_unit) = self.board[*from].unit {
+                if let Some(mut to_unit) = self.board[*to].unit {
                     let unit_descriptor = &units[from_unit.kind];
-                    to_unit.health -= unit_descriptor.damage;
-                    if to_unit.health <= 0 {
+                    to_unit.health = to_unit.health.saturating_sub(unit_descriptor.damage);
+                    if to_unit.health == 0 {
                         from_unit.position = (x, y);
                         self.board[*from].unit = None;
                         self.board[*to].unit = Some(from_unit);
                     } else {
                         // TODO Handle case where enemy unit doesnt die
                         // probably move to closest point along path to unit
+                        self.board[*to].unit = Some(to_unit);
                     }
                 } else {
                     from_unit.position = (x, y);
@@ -383,45 +532,247 @@ impl GameState {
                     self.board[*to].unit = Some(from_unit);
                 }
             }
-            EndTurn { player_id } => {
-                // Switch which player is the active player
-                self.active_player_id = self
-                    .players
-                    .keys()
-                    .find(|id| *id != player_id)
-                    .unwrap()
-                    .clone();
-                }
+            EndTurn { player_id: _ } => self.advance_turn(),
         }
 
         self.histroy.push(valid_event.clone());
     }
 
-    /// Determines if someone has won the game
-    pub fn determine_winner(&self) -> Option<PlayerId> {
+    /// Every action `player_id` can take right now: building units, moving and attacking with
+    /// them, and ending their turn. Gives the same answer as running every possible event through
+    /// validate, without having to try them all.
+    pub fn legal_actions(
+        &self,
+        player_id: PlayerId,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Vec<GameEvent> {
+        let mut actions = Vec::new();
+        let player = match self.players.get(&player_id) {
+            Some(player) if self.stage == Stage::InGame && self.active_player_id == player_id => {
+                player
+            }
+            _ => return actions,
+        };
+        let faction = player.faction.to_string();
+
+        // Units can be built on top of any empty building of the player's faction
+        let gold = self.available_gold(player_id);
+        let affordable: Vec<UnitKind> = (0..units.len())
+            .map(UnitKind)
+            .filter(|kind| units[*kind].faction == faction && units[*kind].cost <= gold)
+            .collect();
+        for (at, tile) in self.board.iter().enumerate() {
+            let own_building = tile
+                .building
+                .map(|building| buildings[building.kind].faction == faction)
+                .unwrap_or(false);
+            if own_building && tile.unit.is_none() {
+                actions.extend(affordable.iter().map(|unit_kind| GameEvent::BuildUnit {
+                    player_id,
+                    at,
+                    unit_kind: *unit_kind,
+                }));
+            }
+        }
+
+        // Units can move onto any tile that isnt a wall or taken by an ally, attacking whatever
+        // enemy is standing there
+        let destinations: Vec<usize> = (0..MAP_SIZE)
+            .filter(|to| {
+                let tile = &self.board[*to];
+                !terrain[tile.terrain].wall
+                    && tile
+                        .unit
+                        .map(|unit| !self.are_allies(player_id, unit.owner))
+                        .unwrap_or(true)
+            })
+            .collect();
+        for (from, tile) in self.board.iter().enumerate() {
+            if tile.unit.map(|unit| unit.owner) == Some(player_id) {
+                actions.extend(destinations.iter().map(|to| GameEvent::MoveUnit {
+                    player_id,
+                    from,
+                    to: *to,
+                }));
+            }
+        }
+
+        actions.push(GameEvent::EndTurn { player_id });
+        actions
+    }
+
+    /// Builds a GameState by consuming events one after another, starting on `initial_board`
+    pub fn from_events(
+        initial_board: &[BoardTile; MAP_SIZE],
+        events: &[GameEvent],
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Self {
+        let mut game_state = Self::on_board(*initial_board);
+        for event in events {
+            game_state.consume(event, buildings, units, terrain);
+        }
+        game_state
+    }
+
+    /// The GameState as it was before the last event, if `player_id` is allowed to take that
+    /// event back.
+    ///
+    /// Only the active player's own actions from the current turn can be undone, and only if they
+    /// didnt reveal anything new to them. Building a unit is fine, moving is too as long as the
+    /// move didnt start a fight.
+    pub fn undo_last(
+        &self,
+        player_id: PlayerId,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Option<Self> {
+        if self.stage != Stage::InGame || self.active_player_id != player_id {
+            return None;
+        }
+
+        let (last, earlier) = self.histroy.split_last()?;
+        if last.acting_player() != Some(player_id) {
+            return None;
+        }
+
+        let previous = Self::from_events(&self.initial_board, earlier, buildings, units, terrain);
+        match last {
+            GameEvent::BuildUnit { .. } => Some(previous),
+            GameEvent::MoveUnit { to, .. } if previous.board[*to].unit.is_none() => Some(previous),
+            // Ending the turn hands it to someone else, and attacking reveals how the fight went
+            _ => None,
+        }
+    }
+
+    /// The player whose turn comes after the active player's, skipping anyone that is
+    /// eliminated. None if nobody else is left to take a turn.
+    pub fn next_player(&self) -> Option<PlayerId> {
+        let active = self
+            .turn_order
+            .iter()
+            .position(|id| *id == self.active_player_id)?;
+        (1..self.turn_order.len())
+            .map(|offset| self.turn_order[(active + offset) % self.turn_order.len()])
+            .find(|id| {
+                self.players
+                    .get(id)
+                    .map(|player| !player.eliminated)
+                    .unwrap_or(false)
+            })
+    }
+
+    /// Hands the turn to the next player, starting a new round when the turn order wraps around
+    fn advance_turn(&mut self) {
+        let next = match self.next_player() {
+            Some(next) => next,
+            None => return,
+        };
+
+        let position = |id: PlayerId| self.turn_order.iter().position(|other| *other == id);
+        if position(next) <= position(self.active_player_id) {
+            self.round += 1;
+        }
+        self.active_player_id = next;
+    }
+
+    /// Determines if a team has won the game. Everyone on the winning team wins together.
+    pub fn determine_winner(&self) -> Option<TeamId> {
+        if self.stage != Stage::InGame {
+            return None;
+        }
+
+        // The last team with anyone left in the game wins
+        let mut teams_left = self
+            .players
+            .values()
+            .filter(|player| !player.eliminated)
+            .map(|player| player.team);
+        if let Some(team) = teams_left.next() {
+            if teams_left.all(|other| other == team) {
+                return Some(team);
+            }
+        }
+
         if self.volcano_has_been_plugged() {
-            if let Some((dinosaur_player, _)) = self
+            if let Some(dinosaur_player) = self
                 .players
-                    .iter()
-                    .find(|(_, player)| player.faction == Faction::Dinosaur)
+                    .values()
+                    .find(|player| player.faction == Faction::Dinosaur && !player.eliminated)
             {
-                return Some(*dinosaur_player);
+                return Some(dinosaur_player.team);
             }
         }
 
         if self.all_dino_dead() && self.all_dino_villages_destroyed() {
-            if let Some((volcano_player, _)) = self
+            if let Some(volcano_player) = self
                 .players
-                    .iter()
-                    .find(|(_, player)| player.faction == Faction::Volcano)
+                    .values()
+                    .find(|player| player.faction == Faction::Volcano && !player.eliminated)
             {
-                return Some(*volcano_player);
+                return Some(volcano_player.team);
             }
         }
 
         None
     }
 
+    /// Whether two players are on the same team. Everyone is their own ally.
+    pub fn are_allies(&self, player_id: PlayerId, other_id: PlayerId) -> bool {
+        if player_id == other_id {
+            return true;
+        }
+        match (self.players.get(&player_id), self.players.get(&other_id)) {
+            (Some(player), Some(other)) => player.team == other.team,
+            _ => false,
+        }
+    }
+
+    /// The gold a player can spend, which includes their allies' gold in a shared economy
+    pub fn available_gold(&self, player_id: PlayerId) -> u32 {
+        match self.settings.economy {
+            Economy::PerPlayer => self
+                .players
+                .get(&player_id)
+                .map(|player| player.gold)
+                .unwrap_or(0),
+            Economy::Shared => self
+                .players
+                .keys()
+                .filter(|other_id| self.are_allies(player_id, **other_id))
+                .map(|other_id| self.players[other_id].gold)
+                .sum(),
+        }
+    }
+
+    /// Takes gold from a player, going on to their allies in a shared economy once the player has
+    /// run out. Assumes the player can afford it, see available_gold.
+    fn spend_gold(&mut self, player_id: PlayerId, amount: u32) {
+        let mut payers = vec![player_id];
+        if self.settings.economy == Economy::Shared {
+            let allies = self
+                .players
+                .keys()
+                .filter(|other_id| {
+                    **other_id != player_id && self.are_allies(player_id, **other_id)
+                });
+            payers.extend(allies);
+        }
+
+        let mut remaining = amount;
+        for payer in payers {
+            if let Some(player) = self.players.get_mut(&payer) {
+                let paid = remaining.min(player.gold);
+                player.gold -= paid;
+                remaining -= paid;
+            }
+        }
+    }
+
     /// Determines if the volcano has been plugged with boulder
     pub fn volcano_has_been_plugged(&self) -> bool {
         // TODO - Impliment function
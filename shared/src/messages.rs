@@ -0,0 +1,255 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    buildings::Buildings,
+    channels::{ClientChannel, ServerChannel},
+    chat::{ChatRejectReason, ChatScope, Emote},
+    connection::Session,
+    hash,
+    terrain::Terrain,
+    units::Units,
+    Faction, GameEvent, GameState, PlayerId, TeamId,
+};
+
+/// Version of the message layout. Bump it whenever any message sent over the network changes
+/// shape, old clients will then be told they are incompatible instead of failing to read things.
+pub const PROTOCOL_VERSION: u32 = 15;
+
+/// Everything the server can send down to a client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    /// A validated event that the client should consume into its own GameState. `index` is the
+    /// position of the event in the GameState history, and `state_hash` the hash::state_hash of
+    /// the server's GameState after consuming it.
+    GameEvent {
+        index: usize,
+        event: GameEvent,
+        state_hash: u64,
+    },
+    /// The last event was taken back by the player that made it. Clients should rebuild their
+    /// GameState without it, `index` is the position the event had in the history and
+    /// `state_hash` the hash of the server's GameState once it was gone.
+    Undone { index: usize, state_hash: u64 },
+    /// An undo the client asked for was not allowed
+    UndoRejected,
+    /// An event the client sent that was not accepted
+    Rejected {
+        event: GameEvent,
+        reason: RejectReason,
+    },
+    /// The full GameState, sent once on connect so that clients joining a game in progress can
+    /// initialize their board before applying any further events.
+    StateSnapshot(Box<GameState>),
+    /// The seat this client has been given, along with the token needed to reclaim it after a
+    /// disconnect
+    Session(Session),
+    /// Everyone with a seat in the game, sent whenever that changes
+    Lobby(Vec<LobbyPlayer>),
+    /// The names of everyone watching the game, sent whenever that changes
+    Spectators(Vec<String>),
+    /// A chat message from one of the players, to everyone or just their team
+    Chat {
+        player_id: PlayerId,
+        scope: ChatScope,
+        text: String,
+    },
+    /// An emote one of the players sent
+    Emote { player_id: PlayerId, emote: Emote },
+    /// A chat message or emote the client sent that was not passed on
+    ChatRejected(ChatRejectReason),
+    /// The tile another player is hovering over
+    Cursor {
+        player_id: PlayerId,
+        tile: Option<usize>,
+    },
+    /// How long the player whose turn it is has left to finish it. Sent when a turn starts,
+    /// and every few seconds after.
+    TurnTimer {
+        player_id: PlayerId,
+        remaining_ms: u64,
+    },
+    /// Answer to a ping, carrying the same value back
+    Pong(u64),
+    /// Sent right before the server drops a client
+    Disconnect(DisconnectReason),
+}
+
+impl ServerMessage {
+    /// The channel this message should be sent over
+    pub fn channel(&self) -> ServerChannel {
+        match self {
+            ServerMessage::StateSnapshot(_) => ServerChannel::Snapshot,
+            ServerMessage::Chat { .. }
+            | ServerMessage::Emote { .. }
+            | ServerMessage::ChatRejected(_) => ServerChannel::Chat,
+            ServerMessage::Cursor { .. } | ServerMessage::Pong(_) => ServerChannel::Unreliable,
+            _ => ServerChannel::Game,
+        }
+    }
+}
+
+/// Everything a client can send up to the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// The first thing a client sends, the server wont seat it until it has checked this
+    Hello(Handshake),
+    /// An event the client would like to happen
+    GameEvent(GameEvent),
+    /// Take back the last action we did this turn
+    UndoAction,
+    /// Something to say to the other players, or just to our team. Kept out of the GameState,
+    /// so it never ends up in the history of the match.
+    Chat { scope: ChatScope, text: String },
+    /// One of the predefined quick messages, to everyone
+    Emote(Emote),
+    /// The tile the player is hovering over
+    Cursor(Option<usize>),
+    /// Any value, the server sends it straight back in a Pong
+    Ping(u64),
+    /// Our GameState no longer matches the server's
+    Desync(DesyncReport),
+}
+
+impl ClientMessage {
+    /// The channel this message should be sent over
+    pub fn channel(&self) -> ClientChannel {
+        match self {
+            ClientMessage::Hello(_)
+            | ClientMessage::GameEvent(_)
+            | ClientMessage::UndoAction
+            | ClientMessage::Desync(_) => ClientChannel::Game,
+            ClientMessage::Chat { .. } | ClientMessage::Emote(_) => ClientChannel::Chat,
+            ClientMessage::Cursor(_) | ClientMessage::Ping(_) => ClientChannel::Unreliable,
+        }
+    }
+}
+
+/// What a client saw when its GameState stopped matching the server's. The server has the real
+/// state already and answers with a snapshot, so the client's own state isnt sent along.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DesyncReport {
+    /// Position in the history of the event after which the hashes differed
+    pub index: usize,
+    pub expected_hash: u64,
+    pub actual_hash: u64,
+}
+
+/// Why the server refused an event sent by a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// The client has not been given a seat yet
+    NotSeated,
+    /// The event was on behalf of someone other than the client's own player
+    NotYourPlayer,
+    /// The event is not valid in the current GameState
+    Invalid,
+}
+
+/// A seat in the game as shown in the lobby
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LobbyPlayer {
+    pub player_id: PlayerId,
+    pub name: String,
+    pub faction: Faction,
+    pub team: TeamId,
+    /// False while the seat is being held for a player that dropped out
+    pub connected: bool,
+}
+
+/// Why the server dropped a client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    /// The client runs a different version of the game than the server. `client` is None when
+    /// the server could not even read the client's handshake.
+    IncompatibleClient {
+        server: Handshake,
+        client: Option<Handshake>,
+    },
+    /// The match already has all the players it needs, or has already begun
+    MatchFull,
+    /// Someone in the match already plays as the id the client connected with
+    PlayerIdTaken,
+    /// The match already has as many spectators as the server allows
+    SpectatorsFull,
+    /// The client's address is on the server's ban list
+    Banned,
+    /// The client kept sending messages faster than the server allows
+    Flooding,
+    /// The client sent too many invalid actions or unreadable messages in a row
+    TooManyInvalidActions,
+    /// Whoever runs the server kicked the client
+    Kicked,
+    /// The server is going down
+    ShuttingDown,
+    /// The client didnt send its handshake in time
+    HandshakeTimeout,
+}
+
+/// Identifies which version of the game a client or server is running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    pub rules_hash: u64,
+}
+
+impl Handshake {
+    pub fn new(buildings: &Buildings, units: &Units, terrain: &Terrain) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            rules_hash: hash::rules_hash(buildings, units, terrain),
+        }
+    }
+}
+
+/// Wraps every payload that goes over the network.
+///
+/// The version always comes first, so it can still be read when the rest of the message has a
+/// layout we dont know about.
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u32,
+    payload: T,
+}
+
+/// The reasons a message could not be read
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The message was sent with a different protocol version
+    WrongVersion(u32),
+    /// The message has the right version but could not be deserialized
+    Malformed(bincode::Error),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::WrongVersion(version) => write!(
+                f,
+                "message has protocol version {}, expected {}",
+                version, PROTOCOL_VERSION
+            ),
+            DecodeError::Malformed(err) => write!(f, "malformed message: {}", err),
+        }
+    }
+}
+
+/// Serializes a payload inside of an envelope, ready to send
+pub fn encode<T: Serialize>(payload: &T) -> Vec<u8> {
+    bincode::serialize(&Envelope {
+        version: PROTOCOL_VERSION,
+        payload,
+    })
+    .unwrap()
+}
+
+/// Reads a payload back out of its envelope
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let version: u32 = bincode::deserialize(bytes).map_err(DecodeError::Malformed)?;
+    if version != PROTOCOL_VERSION {
+        return Err(DecodeError::WrongVersion(version));
+    }
+
+    bincode::deserialize::<Envelope<T>>(bytes)
+        .map(|envelope| envelope.payload)
+        .map_err(DecodeError::Malformed)
+}
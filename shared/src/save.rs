@@ -0,0 +1,189 @@
+use std::{collections::HashMap, fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+
+use crate::{
+    buildings::Buildings, connection::SessionToken, hash, terrain::Terrain, units::Units,
+    BoardTile, Faction, GameEvent, GameState, PlayerId, TeamId, MAP_SIZE,
+};
+
+/// Version of the save file layout. Bump it whenever the header or any event changes shape.
+pub const SAVE_FORMAT_VERSION: u32 = 4;
+
+/// A match written to disk. Only the events are stored, the GameState is rebuilt by replaying
+/// them on top of the map in the header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveFile {
+    pub format_version: u32,
+    pub header: SaveHeader,
+    pub events: Vec<GameEvent>,
+}
+
+/// Everything needed to check that a save can be replayed by this version of the game
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveHeader {
+    pub rules_hash: u64,
+    /// The board the match started on
+    #[serde(with = "BigArray")]
+    pub map: [BoardTile; MAP_SIZE],
+    /// Who was playing when the match was saved
+    pub players: Vec<SavedPlayer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPlayer {
+    pub player_id: PlayerId,
+    pub name: String,
+    pub faction: Faction,
+    pub team: TeamId,
+    /// The token the player needs to take their seat back, None for players that never had one
+    /// like bots
+    pub session_token: Option<SessionToken>,
+}
+
+/// Just enough of a save to find out which layout the rest of it has
+#[derive(Deserialize)]
+struct FormatVersion {
+    format_version: u32,
+}
+
+/// The reasons a save could not be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Malformed(serde_json::Error),
+    /// The file was written with a different save format
+    WrongFormatVersion(u32),
+    /// The file was written with different game rules or unit, building or terrain descriptors
+    RulesMismatch,
+    /// An event in the file is not valid at its point in the match
+    InvalidEvent {
+        index: usize,
+        event: GameEvent,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "could not read save: {}", err),
+            LoadError::Malformed(err) => write!(f, "malformed save: {}", err),
+            LoadError::WrongFormatVersion(version) => write!(
+                f,
+                "save has format version {}, expected {}",
+                version, SAVE_FORMAT_VERSION
+            ),
+            LoadError::RulesMismatch => write!(f, "save was made with different game rules"),
+            LoadError::InvalidEvent { index, event } => {
+                write!(f, "event {} is invalid: {:?}", index, event)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl SaveFile {
+    /// Captures a match so it can be written to disk, along with the session tokens of its
+    /// players
+    pub fn new(
+        game_state: &GameState,
+        tokens: &HashMap<PlayerId, SessionToken>,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Self {
+        let players = game_state
+            .players
+            .iter()
+            .map(|(player_id, player)| SavedPlayer {
+                player_id: *player_id,
+                name: player.name.clone(),
+                faction: player.faction,
+                team: player.team,
+                session_token: tokens.get(player_id).copied(),
+            })
+            .collect();
+
+        Self {
+            format_version: SAVE_FORMAT_VERSION,
+            header: SaveHeader {
+                rules_hash: hash::rules_hash(buildings, units, terrain),
+                map: game_state.initial_board,
+                players,
+            },
+            events: game_state.histroy.clone(),
+        }
+    }
+
+    /// Writes the save as json, going through a temporary file so an interrupted write never
+    /// leaves a broken save behind
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::from)?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let bytes = fs::read(path)?;
+
+        let FormatVersion { format_version } =
+            serde_json::from_slice(&bytes).map_err(LoadError::Malformed)?;
+        if format_version != SAVE_FORMAT_VERSION {
+            return Err(LoadError::WrongFormatVersion(format_version));
+        }
+
+        serde_json::from_slice(&bytes).map_err(LoadError::Malformed)
+    }
+
+    /// Rebuilds the GameState by replaying every event through validate and consume
+    pub fn restore(
+        &self,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Result<GameState, LoadError> {
+        self.check_compatible(buildings, units, terrain)?;
+
+        let mut game_state = self.initial_state();
+        for (index, event) in self.events.iter().enumerate() {
+            if !game_state.validate(event, buildings, units, terrain) {
+                return Err(LoadError::InvalidEvent {
+                    index,
+                    event: event.clone(),
+                });
+            }
+            game_state.consume(event, buildings, units, terrain);
+        }
+        Ok(game_state)
+    }
+
+    /// Checks that the save was made with the same rules and descriptors we are running
+    pub fn check_compatible(
+        &self,
+        buildings: &Buildings,
+        units: &Units,
+        terrain: &Terrain,
+    ) -> Result<(), LoadError> {
+        if self.header.rules_hash != hash::rules_hash(buildings, units, terrain) {
+            return Err(LoadError::RulesMismatch);
+        }
+        Ok(())
+    }
+
+    /// The GameState before any of the events happened
+    pub fn initial_state(&self) -> GameState {
+        GameState::on_board(self.header.map)
+    }
+}
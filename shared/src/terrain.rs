@@ -16,7 +16,7 @@ impl std::ops::Index<TerrainKind> for Terrain {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, Hash, serde::Deserialize)]
 pub struct TerrainDescriptor {
     pub name: String,
     pub sprite_idx: usize,
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use bevy::prelude::*;
 
+use crate::PlayerId;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UnitKind(pub usize);
 
@@ -8,6 +10,7 @@ pub struct UnitKind(pub usize);
 pub struct Unit{
     pub position: (u32, u32),
     pub kind: UnitKind,
+    pub owner: PlayerId,
     pub health: u32,
     pub range_remaining: u32,
 }
@@ -16,11 +19,13 @@ impl Unit {
     pub fn new(
         pos: (u32, u32),
         kind: UnitKind,
+        owner: PlayerId,
         stats: &Units,
     ) -> Self {
         Self {
             position: pos,
             kind,
+            owner,
             health: stats.0[kind.0].max_hp,
             range_remaining: stats.0[kind.0].move_range,
         }
@@ -40,7 +45,7 @@ impl std::ops::Index<UnitKind> for Units {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, Hash, serde::Deserialize)]
 pub struct UnitDescriptor {
     pub name: String,
     // Name that can be shown to players
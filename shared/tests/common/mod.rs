@@ -0,0 +1,185 @@
+//! Descriptors and generated games shared by the integration tests
+#![allow(dead_code)]
+
+use proptest::prelude::*;
+use shared::{
+    buildings::{Building, BuildingDescriptor, BuildingKind, Buildings},
+    terrain::{Terrain, TerrainDescriptor, TerrainKind},
+    units::{Unit, UnitDescriptor, UnitKind, Units},
+    Economy, GameEvent, GameState, MatchSettings, PlayerId, TeamMode, MAP_SIZE, MAP_WIDTH,
+};
+
+/// The same descriptors as the test asset files
+pub fn descriptors() -> (Buildings, Units, Terrain) {
+    let building = |name: &str, max_hp, sprite_idx, faction: &str| BuildingDescriptor {
+        name: name.to_string(),
+        pub_name: name.to_string(),
+        max_hp,
+        sprite_idx,
+        faction: faction.to_string(),
+    };
+    let buildings = Buildings(vec![
+        building("Volcano", 8, 0, "Volcano"),
+        building("DinoVillage", 4, 1, "Dinosaur"),
+    ]);
+
+    let unit = |name: &str, max_hp, move_range, attack_range, damage, cost, faction: &str| {
+        UnitDescriptor {
+            name: name.to_string(),
+            pub_name: name.to_string(),
+            max_hp,
+            move_range,
+            attack_range,
+            damage,
+            cost,
+            sprite_idx: 0,
+            faction: faction.to_string(),
+        }
+    };
+    let units = Units(vec![
+        unit("DinoFigher", 2, 1, 1, 2, 2, "Dinosaur"),
+        unit("DinoRanged", 1, 1, 2, 2, 2, "Dinosaur"),
+        unit("DinoScout", 1, 2, 1, 1, 3, "Dinosaur"),
+        unit("LavaGolem", 4, 1, 1, 2, 2, "Volcano"),
+        unit("Lava", 0, 0, 0, 1, 2, "Volcano"),
+        unit("VolcanoRocks", 0, 0, 3, 1, 4, "Volcano"),
+    ]);
+
+    let terrain = |name: &str, sprite_idx, wall| TerrainDescriptor {
+        name: name.to_string(),
+        sprite_idx,
+        wall,
+    };
+    let terrain = Terrain(vec![
+        terrain("Land", 0, false),
+        terrain("Water", 1, true),
+        terrain("Lava", 2, true),
+    ]);
+
+    (buildings, units, terrain)
+}
+
+/// What can be on a generated tile: its terrain, maybe a building and maybe a unit along with
+/// which of the players owns it
+type TileParts = (usize, Option<(usize, u32)>, Option<(usize, usize, u32)>);
+
+fn tile() -> impl Strategy<Value = TileParts> {
+    let terrain = prop_oneof![4 => Just(0usize), 1 => 1..3usize];
+    // Most tiles are empty, so the boards stay small enough to reason about
+    let building = proptest::option::weighted(0.15, (0..2usize, 1..=8u32));
+    let unit = proptest::option::weighted(0.2, (0..6usize, 0..4usize, 0..=4u32));
+    (terrain, building, unit)
+}
+
+/// A match of two to four players that has begun, with buildings and units of every kind
+/// scattered over the board in no particular order
+pub fn game_state() -> impl Strategy<Value = GameState> {
+    (
+        2..=4usize,
+        any::<bool>(),
+        any::<bool>(),
+        proptest::collection::vec(0..10u32, 4),
+        proptest::collection::vec(tile(), MAP_SIZE),
+        0..4usize,
+    )
+        .prop_map(
+            |(player_count, free_for_all, shared_economy, gold, tiles, goes_first)| {
+                let (buildings, units, terrain) = descriptors();
+                let settings = MatchSettings {
+                    teams: if free_for_all {
+                        TeamMode::FreeForAll
+                    } else {
+                        TeamMode::ByFaction
+                    },
+                    economy: if shared_economy {
+                        Economy::Shared
+                    } else {
+                        Economy::PerPlayer
+                    },
+                };
+                let player_ids: Vec<PlayerId> = (1..=player_count as PlayerId).collect();
+
+                let mut game_state = GameState::default();
+                let mut setup = vec![GameEvent::ConfigureMatch { settings }];
+                setup.extend(player_ids.iter().map(|player_id| GameEvent::PlayerJoined {
+                    player_id: *player_id,
+                    name: format!("Player {}", player_id),
+                }));
+                setup.push(GameEvent::BeginGame {
+                    goes_first: player_ids[goes_first % player_count],
+                });
+                for event in setup {
+                    game_state.consume(&event, &buildings, &units, &terrain);
+                }
+
+                for (player, gold) in game_state.players.values_mut().zip(gold) {
+                    player.gold = gold;
+                }
+                for (index, (terrain_kind, building, unit)) in tiles.into_iter().enumerate() {
+                    let position = ((index % MAP_WIDTH) as u32, (index / MAP_WIDTH) as u32);
+                    let tile = &mut game_state.board[index];
+                    tile.terrain = TerrainKind(terrain_kind);
+                    tile.building = building.map(|(kind, health)| Building {
+                        position,
+                        kind: BuildingKind(kind),
+                        health,
+                    });
+                    tile.unit = unit.map(|(kind, owner, health)| Unit {
+                        position,
+                        kind: UnitKind(kind),
+                        owner: player_ids[owner % player_count],
+                        health,
+                        range_remaining: units.0[kind].move_range,
+                    });
+                }
+                // The match started out on the generated board, nothing in the setup touches it
+                game_state.initial_board = game_state.board;
+                game_state
+            },
+        )
+}
+
+/// Every event a player could possibly try to take, valid or not
+pub fn every_action(player_id: PlayerId, units: &Units) -> Vec<GameEvent> {
+    let mut actions = Vec::new();
+    for at in 0..MAP_SIZE {
+        actions.extend((0..units.0.len()).map(|kind| GameEvent::BuildUnit {
+            player_id,
+            at,
+            unit_kind: UnitKind(kind),
+        }));
+    }
+    for from in 0..MAP_SIZE {
+        actions.extend((0..MAP_SIZE).map(|to| GameEvent::MoveUnit {
+            player_id,
+            from,
+            to,
+        }));
+    }
+    actions.push(GameEvent::EndTurn { player_id });
+    actions
+}
+
+/// Like game_state, except every unit is one its owner's faction could have built
+pub fn fair_game_state() -> impl Strategy<Value = GameState> {
+    game_state().prop_map(|mut game_state| {
+        let (_, units, _) = descriptors();
+        let factions: Vec<(PlayerId, String)> = game_state
+            .players
+            .iter()
+            .map(|(player_id, player)| (*player_id, player.faction.to_string()))
+            .collect();
+        for tile in game_state.board.iter_mut() {
+            if let Some(unit) = &mut tile.unit {
+                let faction = &factions.iter().find(|(id, _)| *id == unit.owner).unwrap().1;
+                let kinds: Vec<usize> = (0..units.0.len())
+                    .filter(|kind| &units.0[*kind].faction == faction)
+                    .collect();
+                unit.kind = UnitKind(kinds[unit.kind.0 % kinds.len()]);
+                unit.range_remaining = units.0[unit.kind.0].move_range;
+            }
+        }
+        game_state.initial_board = game_state.board;
+        game_state
+    })
+}
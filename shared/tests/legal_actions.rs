@@ -0,0 +1,32 @@
+mod common;
+
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn legal_actions_pass_validate(game_state in common::game_state(), seat in 0..4usize) {
+        let (buildings, units, terrain) = common::descriptors();
+        let player_id = game_state.turn_order[seat % game_state.turn_order.len()];
+
+        for action in game_state.legal_actions(player_id, &buildings, &units, &terrain) {
+            prop_assert!(
+                game_state.validate(&action, &buildings, &units, &terrain),
+                "{:?} is not valid",
+                action
+            );
+        }
+    }
+
+    #[test]
+    fn legal_actions_miss_nothing_valid(game_state in common::game_state(), seat in 0..4usize) {
+        let (buildings, units, terrain) = common::descriptors();
+        let player_id = game_state.turn_order[seat % game_state.turn_order.len()];
+        let legal = game_state.legal_actions(player_id, &buildings, &units, &terrain);
+
+        for action in common::every_action(player_id, &units) {
+            if game_state.validate(&action, &buildings, &units, &terrain) {
+                prop_assert!(legal.contains(&action), "{:?} is valid but missing", action);
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use proptest::prelude::*;
+use shared::{
+    units::UnitKind, Faction, GameEvent, GameState, PlayerId, Stage, MAP_SIZE, MAP_WIDTH,
+};
+
+/// One thing a player or the server tries to do to the game
+#[derive(Debug, Clone)]
+enum Step {
+    /// Whatever the active player's legal action at this index is
+    Legal(prop::sample::Index),
+    /// Anything at all, most of which should be turned down
+    Raw(GameEvent),
+}
+
+fn raw_event() -> impl Strategy<Value = GameEvent> {
+    // Generated games have players 1 to 4, anyone else doesnt exist. Tiles and unit kinds go
+    // a little past the end to check those get turned down too.
+    let player_id = 0..=6 as PlayerId;
+    let tile = 0..MAP_SIZE + 2;
+    prop_oneof![
+        4 => (player_id.clone(), tile.clone(), 0..=6usize).prop_map(
+            |(player_id, at, kind)| GameEvent::BuildUnit {
+                player_id,
+                at,
+                unit_kind: UnitKind(kind),
+            }
+        ),
+        4 => (player_id.clone(), tile.clone(), tile).prop_map(|(player_id, from, to)| {
+            GameEvent::MoveUnit {
+                player_id,
+                from,
+                to,
+            }
+        }),
+        2 => player_id.clone().prop_map(|player_id| GameEvent::EndTurn { player_id }),
+        1 => player_id.clone().prop_map(|player_id| GameEvent::PlayerEliminated { player_id }),
+        1 => player_id.prop_map(|player_id| GameEvent::PlayerDisconnected { player_id }),
+    ]
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        3 => any::<prop::sample::Index>().prop_map(Step::Legal),
+        1 => raw_event().prop_map(Step::Raw),
+    ]
+}
+
+fn unit_count(game_state: &GameState) -> usize {
+    game_state
+        .board
+        .iter()
+        .filter(|tile| tile.unit.is_some())
+        .count()
+}
+
+proptest! {
+    #[test]
+    fn rules_hold_up_to_any_events(
+        mut game_state in common::fair_game_state(),
+        steps in proptest::collection::vec(step(), 1..40),
+    ) {
+        let (buildings, units, terrain) = common::descriptors();
+
+        for step in steps {
+            let event = match step {
+                Step::Legal(index) => {
+                    let player_id = game_state.active_player_id;
+                    let legal = game_state.legal_actions(player_id, &buildings, &units, &terrain);
+                    if legal.is_empty() {
+                        continue;
+                    }
+                    index.get(&legal).clone()
+                }
+                Step::Raw(event) => event,
+            };
+            if !game_state.validate(&event, &buildings, &units, &terrain) {
+                continue;
+            }
+
+            let before = game_state.clone();
+            game_state.consume(&event, &buildings, &units, &terrain);
+
+            match &event {
+                GameEvent::BuildUnit { player_id, at, unit_kind } => {
+                    // Gold is only spent if it is there to spend
+                    let cost = units[*unit_kind].cost;
+                    let gold = before.available_gold(*player_id);
+                    prop_assert!(gold >= cost, "{:?} with only {} gold", event, gold);
+                    prop_assert_eq!(game_state.available_gold(*player_id), gold - cost);
+
+                    let unit = game_state.board[*at].unit;
+                    prop_assert_eq!(unit.map(|unit| (unit.owner, unit.kind)), Some((*player_id, *unit_kind)));
+                    prop_assert_eq!(unit_count(&game_state), unit_count(&before) + 1);
+                }
+                GameEvent::MoveUnit { from, to, .. } => {
+                    let attacker = before.board[*from].unit.unwrap();
+                    match before.board[*to].unit {
+                        Some(defender) => {
+                            // Attacks hurt whoever stands on the tile being attacked
+                            let damage = units[attacker.kind].damage;
+                            let health = defender.health.saturating_sub(damage);
+                            if health == 0 {
+                                prop_assert_eq!(game_state.board[*to].unit.map(|unit| unit.owner), Some(attacker.owner));
+                                prop_assert!(game_state.board[*from].unit.is_none());
+                                prop_assert_eq!(unit_count(&game_state), unit_count(&before) - 1);
+                            } else {
+                                prop_assert_eq!(game_state.board[*to].unit.map(|unit| unit.health), Some(health));
+                                prop_assert_eq!(game_state.board[*from].unit, Some(attacker));
+                                prop_assert_eq!(unit_count(&game_state), unit_count(&before));
+                            }
+                        }
+                        None => {
+                            prop_assert!(game_state.board[*from].unit.is_none());
+                            prop_assert_eq!(game_state.board[*to].unit.map(|unit| unit.owner), Some(attacker.owner));
+                            prop_assert_eq!(unit_count(&game_state), unit_count(&before));
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            // Units stay where the board says they are, and only ever belong to a faction their
+            // owner plays
+            for (index, tile) in game_state.board.iter().enumerate() {
+                if let Some(unit) = tile.unit {
+                    let position = ((index % MAP_WIDTH) as u32, (index / MAP_WIDTH) as u32);
+                    prop_assert_eq!(unit.position, position, "unit on tile {}", index);
+                    if let Some(owner) = game_state.players.get(&unit.owner) {
+                        prop_assert_eq!(&units[unit.kind].faction, &owner.faction.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn undo_brings_back_the_board(
+        mut game_state in common::fair_game_state(),
+        index in any::<prop::sample::Index>(),
+    ) {
+        let (buildings, units, terrain) = common::descriptors();
+        let player_id = game_state.active_player_id;
+        let legal = game_state.legal_actions(player_id, &buildings, &units, &terrain);
+        let event = index.get(&legal).clone();
+
+        let before = game_state.clone();
+        game_state.consume(&event, &buildings, &units, &terrain);
+        // Undoing replays the history on top of the board the match started on, not the default
+        if let Some(undone) = game_state.undo_last(player_id, &buildings, &units, &terrain) {
+            prop_assert_eq!(&undone.board[..], &before.board[..]);
+            prop_assert_eq!(&undone.initial_board[..], &before.initial_board[..]);
+        }
+    }
+}
+
+#[test]
+fn joining_after_someone_left_the_lobby_keeps_both_factions() {
+    let (buildings, units, terrain) = common::descriptors();
+    let mut game_state = GameState::default();
+    let joined = |player_id: PlayerId| GameEvent::PlayerJoined {
+        player_id,
+        name: format!("Player {}", player_id),
+    };
+    for event in [
+        joined(1),
+        joined(2),
+        GameEvent::PlayerDisconnected { player_id: 1 },
+        joined(3),
+        GameEvent::BeginGame { goes_first: 2 },
+    ] {
+        assert!(
+            game_state.validate(&event, &buildings, &units, &terrain),
+            "{:?}",
+            event
+        );
+        game_state.consume(&event, &buildings, &units, &terrain);
+    }
+
+    // The volcano left, so whoever takes their place plays volcano against the dinos
+    assert_eq!(game_state.players[&2].faction, Faction::Dinosaur);
+    assert_eq!(game_state.players[&3].faction, Faction::Volcano);
+    assert_ne!(game_state.players[&2].team, game_state.players[&3].team);
+    assert_eq!(game_state.determine_winner(), None);
+    assert_eq!(game_state.stage, Stage::InGame);
+}